@@ -0,0 +1,193 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::io::{Cursor, Read};
+
+use bitcoin_hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, Criterion,
+};
+use internet2::sphinx::{
+    Hop, OnionPacket, RouteKeys, SphinxPayload, TlvHopPayload,
+};
+use internet2::tlv;
+use lightning_encoding::LightningEncode;
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+const PACKET_LEN: usize = 1300;
+const HOPS: u8 = 5;
+const RHO_KEY: &[u8] = &[0x72, 0x68, 0x6f];
+
+/// Copy of the original allocation-based `SphinxPacket::unfold` used as a
+/// baseline for the in-place implementation
+mod baseline {
+    use super::*;
+
+    fn generate_key(key: &[u8], shared_secret: impl AsRef<[u8]>) -> [u8; 32] {
+        let mut engine = HmacEngine::<sha256::Hash>::new(key);
+        engine.input(shared_secret.as_ref());
+        Hmac::from_engine(engine).into_inner()
+    }
+
+    fn generate_cipher_stream(prng_seed: [u8; 32], len: usize) -> Vec<u8> {
+        let mut stream = vec![0u8; len];
+        let mut cypher = ChaCha20::new_from_slices(&prng_seed, &[0u8; 12])
+            .expect("incorrect ChaCha20 initialization");
+        cypher
+            .try_apply_keystream(&mut stream)
+            .expect("cypher stream implementation diverged from BOLT-4");
+        stream
+    }
+
+    pub fn unfold<Payload: SphinxPayload>(
+        packet: &mut [u8; PACKET_LEN],
+        shared_secret: sha256::Hash,
+    ) -> (Payload, Hmac<sha256::Hash>) {
+        let rho_key = generate_key(RHO_KEY, shared_secret);
+        let stream_bytes = generate_cipher_stream(rho_key, PACKET_LEN * 2);
+
+        let mut data = packet.to_vec();
+        data.resize(PACKET_LEN * 2, 0);
+
+        let data = data
+            .iter()
+            .zip(&stream_bytes)
+            .map(|(b, m)| b ^ m)
+            .collect::<Vec<u8>>();
+        let mut cursor = Cursor::new(data);
+
+        let payload = Payload::decode(&mut cursor).unwrap();
+
+        let mut hmac_slice = [0u8; 32];
+        cursor.read_exact(&mut hmac_slice).unwrap();
+        let hmac = Hmac::<sha256::Hash>::from_slice(&hmac_slice)
+            .expect("32-byte slice");
+
+        cursor.read_exact(packet).unwrap();
+
+        (payload, hmac)
+    }
+}
+
+fn route() -> (Vec<SecretKey>, Vec<Hop<TlvHopPayload>>) {
+    let secp = Secp256k1::new();
+    (1..=HOPS)
+        .map(|byte| {
+            let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+            let node_id = PublicKey::from_secret_key(&secp, &secret).into();
+            let mut stream = tlv::Stream::new();
+            stream.insert(2usize.into(), [byte; 8]);
+            stream.insert(4usize.into(), [byte; 4]);
+            (secret, Hop::with(node_id, TlvHopPayload::with(stream)))
+        })
+        .unzip()
+}
+
+fn construct(c: &mut Criterion) {
+    let secp = Secp256k1::new();
+    let (_, hops) = route();
+    let session_key = SecretKey::from_slice(&[0x41; 32]).unwrap();
+
+    c.bench_function("sphinx_construct", |b| {
+        b.iter(|| {
+            OnionPacket::<PACKET_LEN>::with_session_key(
+                &secp,
+                session_key,
+                black_box(&hops),
+                &[],
+            )
+            .unwrap()
+        })
+    });
+
+    let mut keys = RouteKeys::with_session_key(
+        &secp,
+        session_key,
+        hops.iter().map(|hop| hop.node_id),
+    )
+    .unwrap();
+    // Route keys are computed in the benchmark setup, so only the packet
+    // construction itself is measured
+    c.bench_function("sphinx_construct_route_keys", |b| {
+        b.iter_batched(
+            || keys.next(&secp),
+            |keys| {
+                OnionPacket::<PACKET_LEN>::with_route_keys(
+                    keys,
+                    black_box(&hops),
+                    &[],
+                )
+                .unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn process(c: &mut Criterion) {
+    let secp = Secp256k1::new();
+    let (secrets, hops) = route();
+    let session_key = SecretKey::from_slice(&[0x41; 32]).unwrap();
+    let onion = OnionPacket::<PACKET_LEN>::with_session_key(
+        &secp,
+        session_key,
+        &hops,
+        &[],
+    )
+    .unwrap();
+
+    c.bench_function("sphinx_check_hmac", |b| {
+        b.iter(|| black_box(&onion).check_hmac(secrets[0], &[]))
+    });
+    let shared_secret = onion.shared_secret(secrets[0]);
+    c.bench_function("sphinx_unfold_packet", |b| {
+        b.iter(|| {
+            let mut packet = black_box(&onion).packet;
+            packet.unfold::<TlvHopPayload>(shared_secret).unwrap()
+        })
+    });
+
+    let mut data = [0u8; PACKET_LEN];
+    data.copy_from_slice(&onion.packet.lightning_serialize().unwrap());
+    let mut packet = onion.packet;
+    let mut baseline_data = data;
+    assert_eq!(
+        baseline::unfold::<TlvHopPayload>(&mut baseline_data, shared_secret),
+        packet.unfold::<TlvHopPayload>(shared_secret).unwrap()
+    );
+    assert_eq!(&baseline_data[..], packet.lightning_serialize().unwrap());
+    c.bench_function("sphinx_unfold_packet_baseline", |b| {
+        b.iter(|| {
+            let mut data = *black_box(&data);
+            baseline::unfold::<TlvHopPayload>(&mut data, shared_secret)
+        })
+    });
+    c.bench_function("sphinx_unfold", |b| {
+        b.iter(|| {
+            let mut onion = *black_box(&onion);
+            onion.unfold::<TlvHopPayload>(secrets[0]).unwrap()
+        })
+    });
+    c.bench_function("sphinx_peel", |b| {
+        b.iter(|| {
+            black_box(&onion)
+                .peel::<_, TlvHopPayload>(&secp, secrets[0], &[])
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, construct, process);
+criterion_main!(benches);
@@ -11,18 +11,25 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 
 use proc_macro2::{Span, TokenStream as TokenStream2};
+use syn::punctuated::IntoIter;
 use syn::spanned::Spanned;
 use syn::{
-    Data, DataEnum, DeriveInput, Error, Fields, Ident, Lit, Path, Result,
+    Data, DataEnum, DeriveInput, Error, Fields, GenericArgument, Ident, Lit,
+    Meta, NestedMeta, Path, PathArguments, Result, Type, Variant,
 };
 
-use crate::util::{attr_list, get_encoding_crate, nested_one_named_value};
+use crate::util::{attr_list, get_encoding_crate, nested_metas};
 
 const NAME: &str = "api";
-const EXAMPLE: &str = "#[api(encoding=\"strict|bitcoin|lightning\")]";
+const EXAMPLE: &str = "#[api(encoding=\"strict|bitcoin|lightning\", \
+                       handler=\"RequestHandler\", serde, extract, schema)]";
+const VARIANT_EXAMPLE: &str = "#[api(type=1000, required, tlv, since=2, \
+                               deprecated, max_len=65535, reply=\"Ack\", \
+                               error=\"Failure\")]";
 
 pub(crate) fn inner(input: DeriveInput) -> Result<TokenStream2> {
     match input.data {
@@ -44,11 +51,61 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
 
     let global_params = attr_list(&input.attrs, NAME, EXAMPLE)?
         .ok_or_else(|| attr_err!(input, "encoding type must be specified"))?;
-    let global_encoding = EncodingSrategy::try_from(
-        nested_one_named_value(&global_params, "encoding", EXAMPLE)?
-            .ok_or_else(|| attr_err!(input, "encoding must be specified"))?
-            .lit,
-    )?;
+    let mut global_encoding = None;
+    let mut handler = None;
+    let mut use_serde = false;
+    let mut use_extract = false;
+    let mut use_schema = false;
+    for meta in nested_metas(global_params, NAME, EXAMPLE)? {
+        let name_value = match meta {
+            Meta::Path(path) if path.is_ident("serde") => {
+                if use_serde {
+                    err!(path, "`serde` must be specified only once")
+                }
+                use_serde = true;
+                continue;
+            }
+            Meta::Path(path) if path.is_ident("extract") => {
+                if use_extract {
+                    err!(path, "`extract` must be specified only once")
+                }
+                use_extract = true;
+                continue;
+            }
+            Meta::Path(path) if path.is_ident("schema") => {
+                if use_schema {
+                    err!(path, "`schema` must be specified only once")
+                }
+                use_schema = true;
+                continue;
+            }
+            Meta::NameValue(name_value) => name_value,
+            meta => err!(meta, "unexpected argument"),
+        };
+        let lit = name_value.lit;
+        match name_value.path.get_ident().map(Ident::to_string).as_deref() {
+            Some("encoding") if global_encoding.is_some() => {
+                err!(lit, "`encoding` must be specified only once")
+            }
+            Some("encoding") => {
+                global_encoding = Some(EncodingSrategy::try_from(lit)?)
+            }
+            Some("handler") if handler.is_some() => {
+                err!(lit, "`handler` must be specified only once")
+            }
+            Some("handler") => match lit {
+                Lit::Str(ref s) => {
+                    handler = Some(s.parse::<Ident>().map_err(|_| {
+                        attr_err!(s, "`handler` must be a valid trait name")
+                    })?)
+                }
+                _ => err!(lit, "`handler` must be a string with a trait name"),
+            },
+            _ => err!(name_value.path, "unknown argument"),
+        }
+    }
+    let global_encoding = global_encoding
+        .ok_or_else(|| attr_err!(input, "encoding must be specified"))?;
     let import = get_encoding_crate(
         input,
         match global_encoding {
@@ -66,13 +123,25 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
     let decode_use = global_encoding.decode_use(&import);
     let encode_fn = global_encoding.encode_fn();
 
-    let example = "#[api(type=1000)]";
+    let example = VARIANT_EXAMPLE;
     let mut msg_const = vec![];
     let mut unmarshaller = vec![];
     let mut unmarshall_fn = vec![];
     let mut from_type = vec![];
     let mut get_type = vec![];
     let mut get_payload = vec![];
+    let mut expected_reply_types = vec![];
+    let mut has_replies = false;
+    let mut message_schemas = vec![];
+    let mut versions = vec![];
+    let mut max_lens = vec![];
+    let mut get_version = vec![];
+    let mut get_tlvs = vec![];
+    let mut handler_fn = vec![];
+    let mut serde_variants = vec![];
+    let mut serde_from = vec![];
+    let mut dispatch = vec![];
+    let mut type_ids = BTreeMap::new();
     for v in &data.variants {
         let meta = attr_list(&v.attrs, "api", example)?.ok_or_else(|| {
             Error::new(
@@ -85,27 +154,27 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
             )
         })?;
 
-        let type_lit: Lit = nested_one_named_value(&meta, "type", EXAMPLE)?
-            .ok_or_else(|| attr_err!(v, "type must be specified"))?
-            .lit;
-        let type_id: u16 = match type_lit {
-            Lit::Int(i) => i
-                .base10_parse()
-                .map_err(|_| attr_err!(i, "`type` must be an integer"))?,
-            _ => err!(type_lit, "`type` must be an integer"),
-        };
+        let params = VariantParams::with(v, meta)?;
+        let type_id = params.type_id;
         let type_name = &v.ident;
+        if let Some(other) = type_ids.insert(type_id, type_name) {
+            return Err(Error::new(
+                params.type_span,
+                format!(
+                    "Attribute `#[{}]`: type id {:#06x} is already used by \
+                     `{}` variant",
+                    NAME, type_id, other
+                ),
+            ));
+        }
         let type_snake = Ident::new(
             &format!("parse_{}", type_name.to_string().to_lowercase()),
             type_name.span(),
         );
-        let type_const = Ident::new(
-            &format!("MSG_TYPE_{}", type_name.to_string().to_uppercase()),
-            type_name.span(),
-        );
+        let type_const = type_const(type_name);
 
         msg_const.push(quote_spanned! { v.span() =>
-            const #type_const: u16 = #type_id;
+            pub const #type_const: u16 = #type_id;
         });
 
         unmarshaller.push(quote_spanned! { v.span() =>
@@ -119,89 +188,281 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
             }
         };
 
-        match &v.fields {
+        let fields = match &v.fields {
+            Fields::Named(fields) => fields.named.iter().collect::<Vec<_>>(),
+            Fields::Unnamed(fields) => {
+                fields.unnamed.iter().collect::<Vec<_>>()
+            }
+            Fields::Unit => vec![],
+        };
+        if params.tlv && fields.is_empty() {
+            return Err(Error::new(
+                v.span(),
+                format!(
+                    "Attribute `#[{}]`: `tlv` messages must have TLV \
+                     extension stream (`tlv::Stream`) as their last field",
+                    NAME
+                ),
+            ));
+        }
+        let bindings = (0..fields.len())
+            .map(|no| Ident::new(&format!("field{}", no), v.span()))
+            .collect::<Vec<_>>();
+        let pattern = match &v.fields {
             Fields::Named(_) => {
-                err!(
-                    v,
-                    "API does not support requests represented by named enums"
-                )
+                let names = fields.iter().map(|f| &f.ident);
+                quote! { Self::#type_name { #( #names: #bindings ),* } }
             }
-            Fields::Unnamed(args) => {
-                let fields = &args.unnamed;
-                if fields.len() > 1 {
-                    err!(
-                        v,
-                        "each API message enum variant must contain not more \
-                         than a single argument"
-                    );
-                }
-                if let Some(f) = fields.first() {
-                    let payload = &f.ty;
-                    let payload_fisheye: TokenStream2 = syn::parse_str(
-                        &quote!(#payload).to_string().replacen('<', "::<", 1),
+            Fields::Unnamed(_) => {
+                quote! { Self::#type_name( #( #bindings ),* ) }
+            }
+            Fields::Unit => quote! { Self::#type_name },
+        };
+
+        if handler.is_some() {
+            let handler_name = Ident::new(
+                &format!("handle_{}", snake_case(type_name)),
+                type_name.span(),
+            );
+            let args = fields.iter().zip(&bindings).map(|(f, binding)| {
+                let ty = borrowed_type(&f.ty);
+                let name = f.ident.as_ref().unwrap_or(binding);
+                quote! { #name: &#ty }
+            });
+            let doc =
+                format!(" Handles `{}::{}` message", ident_name, type_name);
+            handler_fn.push(quote_spanned! { v.span() =>
+                #[doc = #doc]
+                #[allow(unused_variables)]
+                fn #handler_name(&mut self, #( #args ),*) -> Self::Reply {
+                    <Self::Reply as ::internet2::presentation::UnsupportedReply>::unsupported(
+                        ::internet2::TypeId::with(#ident_name::#type_const)
                     )
-                    .expect("Internal error");
-                    let serialize_fn =
-                        global_encoding.serialize_fn(f.span(), &import);
-                    let decode_fn = global_encoding.decode_fn(f.span());
-
-                    unmarshall_fn.push(quote_spanned! { v.span() =>
-                        fn #type_snake(mut reader: &mut dyn ::std::io::Read) -> Result<::std::sync::Arc<dyn ::std::any::Any>, ::internet2::presentation::Error> {
-                            #decode_use
-                            Ok(::std::sync::Arc::new(#payload_fisheye::#decode_fn(&mut reader)?))
-                        }
-                    });
+                }
+            });
+            dispatch.push(quote_spanned! { v.span() =>
+                #pattern => handler.#handler_name(#( #bindings ),*),
+            });
+        }
 
-                    from_type.push(quote_spanned! { v.span() =>
-                        Self::#type_const => {
-                            #[allow(clippy::clone_on_copy)]
-                            Self::#type_name(data.downcast_ref::<#payload>().expect(ERR).clone())
-                        }
+        if use_serde {
+            let serde_fields = fields.iter().map(|f| {
+                let ty = &f.ty;
+                match &f.ident {
+                    Some(name) => quote! { #name: #ty },
+                    None => quote! { #ty },
+                }
+            });
+            let serde_pattern = match &v.fields {
+                Fields::Named(_) => {
+                    let names = fields.iter().map(|f| &f.ident);
+                    serde_variants.push(quote! {
+                        #type_name { #( #serde_fields ),* }
                     });
+                    quote! { ApiPayload::#type_name { #( #names: #bindings ),* } }
+                }
+                Fields::Unnamed(_) => {
+                    serde_variants
+                        .push(quote! { #type_name( #( #serde_fields ),* ) });
+                    quote! { ApiPayload::#type_name( #( #bindings ),* ) }
+                }
+                Fields::Unit => {
+                    serde_variants.push(quote! { #type_name });
+                    quote! { ApiPayload::#type_name }
+                }
+            };
+            serde_from.push((serde_pattern, pattern.clone()));
+        }
 
-                    get_payload.push(quote_spanned! { v.span() =>
-                        Self::#type_name(obj) => #serialize_fn,
-                    });
+        let any_pattern = match &v.fields {
+            Fields::Named(_) => quote! { Self::#type_name { .. } },
+            Fields::Unnamed(_) => quote! { Self::#type_name(..) },
+            Fields::Unit => quote! { Self::#type_name },
+        };
+        get_type.push(quote_spanned! { v.span() =>
+            #any_pattern => Self::#type_const,
+        });
 
-                    get_type.push(quote_spanned! { v.span() =>
-                        Self::#type_name(_) => Self::#type_const,
-                    });
-                } else {
-                    unmarshall_fn.push(unmarshall_empty);
+        let replies = params
+            .replies
+            .iter()
+            .map(|path| reply_type_const(path, ident_name, data))
+            .collect::<Result<Vec<_>>>()?;
+        let errors = params
+            .errors
+            .iter()
+            .map(|path| reply_type_const(path, ident_name, data))
+            .collect::<Result<Vec<_>>>()?;
+        has_replies |= !replies.is_empty() || !errors.is_empty();
+        expected_reply_types.push(quote_spanned! { v.span() =>
+            #any_pattern => {
+                const TYPES: &[::internet2::TypeId] = &[
+                    #( ::internet2::TypeId::with(#replies), )*
+                    #( ::internet2::TypeId::with(#errors), )*
+                ];
+                TYPES
+            }
+        });
 
-                    from_type.push(quote_spanned! { v.span() =>
-                        Self::#type_const => {
-                            Self::#type_name()
-                        }
-                    });
+        let tlv = params.tlv;
+        let max_len = match params.max_len {
+            Some(max_len) => quote! { Some(#max_len) },
+            None => quote! { None },
+        };
+        let since = params.since;
+        let deprecated = params.deprecated;
+        let version = quote! {
+            ::internet2::presentation::MessageVersion {
+                since: #since,
+                deprecated: #deprecated,
+            }
+        };
+        versions.push(quote_spanned! { v.span() =>
+            versions.insert(Self::#type_const, #version);
+        });
+        if let Some(max_len) = params.max_len {
+            max_lens.push(quote_spanned! { v.span() =>
+                max_lens.insert(Self::#type_const, #max_len);
+            });
+        }
+        get_version.push(quote_spanned! { v.span() =>
+            #any_pattern => #version,
+        });
 
-                    get_payload.push(quote_spanned! { v.span() =>
-                        Self::#type_name() => vec![],
-                    });
+        if params.tlv {
+            let stream_pattern = match &v.fields {
+                Fields::Named(_) => {
+                    let name = &fields[fields.len() - 1].ident;
+                    quote! { Self::#type_name { #name: stream, .. } }
+                }
+                _ => quote! { Self::#type_name(.., stream) },
+            };
+            get_tlvs.push(quote_spanned! { v.span() =>
+                #stream_pattern => stream.clone(),
+            });
+        }
 
-                    get_type.push(quote_spanned! { v.span() =>
-                        Self::#type_name() => Self::#type_const,
-                    });
+        let variant_name = type_name.to_string();
+        let field_schemas = fields.iter().map(|f| {
+            let name = match &f.ident {
+                Some(ident) => {
+                    let name = ident.to_string();
+                    quote! { Some(#name.to_owned()) }
+                }
+                None => quote! { None },
+            };
+            let type_name = type_name_str(&f.ty);
+            quote! {
+                ::internet2::presentation::FieldSchema {
+                    name: #name,
+                    type_name: #type_name.to_owned(),
                 }
             }
-            Fields::Unit => {
-                unmarshall_fn.push(unmarshall_empty);
+        });
+        message_schemas.push(quote_spanned! { v.span() =>
+            ::internet2::presentation::MessageSchema {
+                name: #variant_name.to_owned(),
+                type_id: Self::#type_const,
+                fields: vec![ #( #field_schemas ),* ],
+                tlv: #tlv,
+                replies: vec![ #( #replies ),* ],
+                errors: vec![ #( #errors ),* ],
+                since: #since,
+                deprecated: #deprecated,
+                max_len: #max_len,
+            }
+        });
 
-                from_type.push(quote_spanned! { v.span() =>
-                    Self::#type_const => {
-                        Self::#type_name
-                    }
-                });
+        if fields.is_empty() {
+            unmarshall_fn.push(unmarshall_empty);
 
-                get_payload.push(quote_spanned! { v.span() =>
-                    Self::#type_name => vec![],
-                });
+            from_type.push(quote_spanned! { v.span() =>
+                Self::#type_const => {
+                    #pattern
+                }
+            });
 
-                get_type.push(quote_spanned! { v.span() =>
-                    Self::#type_name => Self::#type_const,
-                });
-            }
+            get_payload.push(quote_spanned! { v.span() =>
+                #pattern => vec![],
+            });
+            continue;
         }
+
+        // Single-field variants keep the field value itself as the parsed
+        // payload; variants with multiple fields use a tuple of the field
+        // values, which are encoded one after another. TLV extension stream,
+        // if present, is always the last field and is encoded according to
+        // BOLT-1 regardless of the API encoding.
+        let types = fields.iter().map(|f| &f.ty).collect::<Vec<_>>();
+        let decode_fn = global_encoding.decode_fn(v.span());
+        let last = fields.len() - 1;
+        let decodes = fields.iter().enumerate().map(|(no, f)| {
+            let ty = &f.ty;
+            if params.tlv && no == last {
+                quote_spanned! { f.span() =>
+                    ::internet2::presentation::tlv::Stream::read_extension(&mut reader)?
+                }
+            } else {
+                quote_spanned! { f.span() => <#ty>::#decode_fn(&mut reader)? }
+            }
+        });
+        let encodes = bindings.iter().enumerate().map(|(no, binding)| {
+            if params.tlv && no == last {
+                quote! { #binding.write_extension(&mut e).expect(ERR); }
+            } else {
+                quote! { #binding.#encode_fn(&mut e).expect(ERR); }
+            }
+        });
+        let payload = if let [ty] = types[..] {
+            quote! { #ty }
+        } else {
+            quote! { ( #( #types ),* ) }
+        };
+        let decode = if types.len() == 1 {
+            quote! { #( #decodes )* }
+        } else {
+            quote! { ( #( #decodes ),* ) }
+        };
+        let serialize = if types.len() == 1 && !params.tlv {
+            let binding = &bindings[0];
+            let serialize_fn =
+                global_encoding.serialize_fn(fields[0].span(), &import);
+            quote! {{
+                let obj = #binding;
+                #serialize_fn
+            }}
+        } else {
+            quote! {{
+                #encode_use
+                let mut e = vec![];
+                #( #encodes )*
+                e
+            }}
+        };
+
+        unmarshall_fn.push(quote_spanned! { v.span() =>
+            fn #type_snake(mut reader: &mut dyn ::std::io::Read) -> Result<::std::sync::Arc<dyn ::std::any::Any>, ::internet2::presentation::Error> {
+                #decode_use
+                Ok(::std::sync::Arc::new(#decode))
+            }
+        });
+
+        let destructure = if bindings.len() == 1 {
+            quote! { #( #bindings )* }
+        } else {
+            quote! { ( #( #bindings ),* ) }
+        };
+        from_type.push(quote_spanned! { v.span() =>
+            Self::#type_const => {
+                #[allow(clippy::clone_on_copy)]
+                let #destructure = data.downcast_ref::<#payload>().expect(ERR).clone();
+                #pattern
+            }
+        });
+
+        get_payload.push(quote_spanned! { v.span() =>
+            #pattern => #serialize,
+        });
     }
     let msg_const = quote! { #( #msg_const )* };
     let unmarshaller = quote! { #( #unmarshaller )* };
@@ -209,6 +470,184 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
     let from_type = quote! { #( #from_type )* };
     let get_type = quote! { #( #get_type )* };
     let get_payload = quote! { #( #get_payload )* };
+    let expected_reply_types = quote! { #( #expected_reply_types )* };
+    let enum_name = ident_name.to_string();
+
+    let serde_impl = if use_serde {
+        let (from_serde, into_serde): (Vec<_>, Vec<_>) = serde_from
+            .iter()
+            .map(|(serde_pattern, pattern)| {
+                (
+                    quote! { #serde_pattern => #pattern, },
+                    quote! { #pattern => #serde_pattern, },
+                )
+            })
+            .unzip();
+        quote! {
+            const _: () = {
+                use ::internet2::_private::serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+                #[derive(Serialize, Deserialize)]
+                #[serde(
+                    crate = "::internet2::_private::serde",
+                    tag = "type",
+                    content = "payload",
+                    rename_all = "snake_case"
+                )]
+                enum ApiPayload {
+                    #( #serde_variants ),*
+                }
+
+                #[derive(Serialize, Deserialize)]
+                #[serde(crate = "::internet2::_private::serde")]
+                struct ApiMessage {
+                    #[serde(flatten)]
+                    message: ApiPayload,
+                    #[serde(default, skip_serializing_if = "Option::is_none")]
+                    type_id: Option<u16>,
+                }
+
+                impl Serialize for #ident_name {
+                    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+                    where
+                        S: Serializer,
+                    {
+                        use ::amplify::Wrapper;
+                        let type_id = ::internet2::TypedEnum::get_type(self).into_inner();
+                        let message = match self.clone() {
+                            #( #into_serde )*
+                        };
+                        ApiMessage {
+                            message,
+                            type_id: Some(type_id),
+                        }
+                        .serialize(serializer)
+                    }
+                }
+
+                impl<'de> Deserialize<'de> for #ident_name {
+                    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+                    where
+                        D: Deserializer<'de>,
+                    {
+                        use ::amplify::Wrapper;
+                        let ApiMessage { message, type_id } =
+                            ApiMessage::deserialize(deserializer)?;
+                        let message = match message {
+                            #( #from_serde )*
+                        };
+                        let expected = ::internet2::TypedEnum::get_type(&message).into_inner();
+                        match type_id {
+                            Some(type_id) if type_id != expected => {
+                                Err(::internet2::_private::serde::de::Error::custom(format!(
+                                    "type id {:#06x} does not match message type {:#06x}",
+                                    type_id, expected
+                                )))
+                            }
+                            _ => Ok(message),
+                        }
+                    }
+                }
+            };
+        }
+    } else {
+        quote! {}
+    };
+
+    let handler_impl = match handler {
+        None => quote! {},
+        Some(handler) => {
+            let vis = &input.vis;
+            let doc = format!(
+                " Handler for `{}` messages, having a method for each of the \
+                 message types. Message fields are passed to the methods by \
+                 reference, with `String` and `Vec<T>` fields borrowed as \
+                 `&str` and `&[T]`. Methods which are not implemented return \
+                 `UnsupportedReply::unsupported` reply.",
+                ident_name
+            );
+            quote! {
+                #[doc = #doc]
+                #vis trait #handler {
+                    /// Reply returned by the handler methods
+                    type Reply: ::internet2::presentation::UnsupportedReply;
+
+                    #( #handler_fn )*
+                }
+
+                impl<H> ::internet2::Dispatch<H> for #ident_name
+                where
+                    H: #handler + ?Sized,
+                {
+                    type Reply = H::Reply;
+
+                    fn dispatch(&self, handler: &mut H) -> H::Reply {
+                        match self {
+                            #( #dispatch )*
+                        }
+                    }
+                }
+
+                impl #ident_name {
+                    /// Calls handler method corresponding to the message type,
+                    /// returning its reply
+                    pub fn dispatch<H>(&self, handler: &mut H) -> H::Reply
+                    where
+                        H: #handler + ?Sized,
+                    {
+                        ::internet2::Dispatch::dispatch(self, handler)
+                    }
+                }
+            }
+        }
+    };
+
+    // `AsAny` and `Extract` are needed only for processing messages with TLV
+    // extensions, so they are not implemented for other enums, which may
+    // already have their own implementations of these traits.
+    let extract_impl = if use_extract || !get_tlvs.is_empty() {
+        quote! {
+            impl ::amplify::AsAny for #ident_name {
+                fn as_any(&self) -> &dyn ::std::any::Any { self }
+            }
+
+            impl ::internet2::presentation::Extract for #ident_name {
+                fn get_type(&self) -> ::internet2::TypeId {
+                    ::internet2::TypedEnum::get_type(self)
+                }
+
+                fn get_payload(&self) -> ::internet2::presentation::message::Source {
+                    let payload: ::std::sync::Arc<dyn ::std::any::Any> =
+                        ::std::sync::Arc::new(::internet2::TypedEnum::get_payload(self));
+                    ::internet2::presentation::message::Source::from(vec![payload])
+                }
+
+                #[allow(unreachable_patterns)]
+                fn get_tlvs(&self) -> ::internet2::presentation::tlv::Stream {
+                    match self {
+                        #( #get_tlvs )*
+                        _ => ::internet2::presentation::tlv::Stream::new(),
+                    }
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let reply_impl = if has_replies {
+        quote! {
+            impl ::internet2::ExpectedReply for #ident_name {
+                fn expected_reply_types(&self) -> &[::internet2::TypeId] {
+                    match self {
+                        #expected_reply_types
+                    }
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
 
     let encoding_type = match global_encoding {
         EncodingSrategy::Strict => quote! { Strict },
@@ -216,12 +655,35 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
         EncodingSrategy::Lightning => quote! { Lightning },
     };
 
+    let schema_impl = if use_schema {
+        quote! {
+            impl ::internet2::presentation::DescribeApi for #ident_name {
+                fn api_schema() -> ::internet2::presentation::ApiSchema {
+                    ::internet2::presentation::ApiSchema {
+                        name: #enum_name.to_owned(),
+                        encoding: ::internet2::presentation::EncodingType::#encoding_type,
+                        messages: vec![ #( #message_schemas ),* ],
+                    }
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
     Ok(quote! {
         impl ::internet2::CreateUnmarshaller for #ident_name {
             fn create_unmarshaller() -> ::internet2::Unmarshaller<Self> {
                 let mut map = ::std::collections::BTreeMap::new();
                 #unmarshaller
+                let mut versions = ::std::collections::BTreeMap::new();
+                #( #versions )*
+                #[allow(unused_mut)]
+                let mut max_lens = ::std::collections::BTreeMap::new();
+                #( #max_lens )*
                 ::internet2::Unmarshaller::new(map, ::internet2::presentation::EncodingType::#encoding_type)
+                    .with_versions(versions)
+                    .with_max_lens(max_lens)
             }
         }
 
@@ -231,6 +693,8 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
             #unmarshall_fn
         }
 
+        #schema_impl
+
         impl ::internet2::TypedEnum for #ident_name {
             fn try_from_type(type_id: ::internet2::TypeId, data: &dyn ::std::any::Any) -> Result<Self, ::internet2::UnknownTypeError> {
                 use ::amplify::Wrapper;
@@ -252,6 +716,12 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
                 })
             }
 
+            fn get_version(&self) -> ::internet2::presentation::MessageVersion {
+                match self {
+                    #( #get_version )*
+                }
+            }
+
             fn get_payload(&self) -> Vec<u8> {
                 const ERR: &'static str = "Message encoding has failed";
                 match self {
@@ -263,11 +733,262 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
                 #encode_use
                 use ::std::io::Write;
                 let mut e = vec![];
-                let _ = self.get_type().#encode_fn(&mut e);
-                e.extend(self.get_payload());
+                let _ = ::internet2::TypedEnum::get_type(self).#encode_fn(&mut e);
+                e.extend(::internet2::TypedEnum::get_payload(self));
                 e
             }
         }
+
+        #handler_impl
+
+        #serde_impl
+
+        #extract_impl
+
+        #reply_impl
+    })
+}
+
+/// Parameters of the `#[api(...)]` attribute given for an enum variant
+struct VariantParams {
+    /// Message type id
+    type_id: u16,
+    /// Span of the type id literal
+    type_span: Span,
+    /// Variants which are expected as successful replies to the message
+    replies: Vec<Path>,
+    /// Variants which are expected as failure replies to the message
+    errors: Vec<Path>,
+    /// Protocol version starting from which the message is supported
+    since: u16,
+    /// Whether the message is deprecated
+    deprecated: bool,
+    /// Whether the message payload is followed by TLV extension stream
+    tlv: bool,
+    /// Maximum size of the message payload
+    max_len: Option<usize>,
+}
+
+impl VariantParams {
+    fn with(variant: &Variant, list: IntoIter<NestedMeta>) -> Result<Self> {
+        let mut type_lit = None;
+        let mut parity = None;
+        let mut replies = vec![];
+        let mut errors = vec![];
+        let mut since = None;
+        let mut deprecated = false;
+        let mut tlv = false;
+        let mut max_len = None;
+        for meta in nested_metas(list, NAME, VARIANT_EXAMPLE)? {
+            let name_value = match meta {
+                Meta::Path(path) if path.is_ident("deprecated") => {
+                    if deprecated {
+                        err!(path, "`deprecated` must be specified only once")
+                    }
+                    deprecated = true;
+                    continue;
+                }
+                Meta::Path(path) if path.is_ident("tlv") => {
+                    if tlv {
+                        err!(path, "`tlv` must be specified only once")
+                    }
+                    tlv = true;
+                    continue;
+                }
+                Meta::Path(path)
+                    if path.is_ident("required")
+                        || path.is_ident("optional") =>
+                {
+                    if parity.is_some() {
+                        err!(
+                            path,
+                            "only one of `required` and `optional` may be \
+                             specified"
+                        );
+                    }
+                    parity = Some(path);
+                    continue;
+                }
+                Meta::NameValue(name_value) => name_value,
+                meta => err!(meta, "unexpected argument"),
+            };
+            let name = name_value
+                .path
+                .get_ident()
+                .map(Ident::to_string)
+                .unwrap_or_default();
+            let lit = name_value.lit;
+            match name.as_str() {
+                "type" if type_lit.is_some() => {
+                    err!(lit, "`type` must be specified only once")
+                }
+                "type" => match lit {
+                    Lit::Int(i) => type_lit = Some(i),
+                    _ => err!(lit, "`type` must be an integer"),
+                },
+                "since" if since.is_some() => {
+                    err!(lit, "`since` must be specified only once")
+                }
+                "since" => match lit {
+                    Lit::Int(ref i) => {
+                        since = Some(i.base10_parse().map_err(|_| {
+                            attr_err!(i, "`since` must be a 16-bit integer")
+                        })?)
+                    }
+                    _ => err!(lit, "`since` must be an integer"),
+                },
+                "max_len" if max_len.is_some() => {
+                    err!(lit, "`max_len` must be specified only once")
+                }
+                "max_len" => match lit {
+                    Lit::Int(ref i) => {
+                        max_len = Some(i.base10_parse().map_err(|_| {
+                            attr_err!(i, "`max_len` must be a valid size")
+                        })?)
+                    }
+                    _ => err!(lit, "`max_len` must be an integer"),
+                },
+                "reply" => replies.push(parse_variant_path(lit)?),
+                "error" => errors.push(parse_variant_path(lit)?),
+                _ => err!(name_value.path, "unknown argument"),
+            }
+        }
+        let type_lit = type_lit
+            .ok_or_else(|| attr_err!(variant, "type must be specified"))?;
+        let type_id: u16 = type_lit
+            .base10_parse()
+            .map_err(|_| attr_err!(type_lit, "`type` must be an integer"))?;
+        // Following the "it's ok to be odd" rule, messages which must be
+        // understood by the receiver have even type ids
+        match parity {
+            Some(path) if path.is_ident("required") && type_id % 2 == 1 => {
+                err!(type_lit, "`required` messages must have even type id")
+            }
+            Some(path) if path.is_ident("optional") && type_id % 2 == 0 => {
+                err!(type_lit, "`optional` messages must have odd type id")
+            }
+            _ => {}
+        }
+        Ok(VariantParams {
+            type_id,
+            type_span: type_lit.span(),
+            replies,
+            errors,
+            since: since.unwrap_or_default(),
+            deprecated,
+            tlv,
+            max_len,
+        })
+    }
+}
+
+fn parse_variant_path(lit: Lit) -> Result<Path> {
+    match lit {
+        Lit::Str(ref s) => s.parse().map_err(|_| {
+            attr_err!(s, "reply must be a name of an API enum variant")
+        }),
+        _ => err!(lit, "reply must be a string with an API enum variant name"),
+    }
+}
+
+/// Returns type name as it is written in the source code, removing spaces
+/// between tokens introduced by the token stream conversion
+fn type_name_str(ty: &Type) -> String {
+    let mut name = String::new();
+    let mut prev_word = false;
+    for c in quote!(#ty).to_string().split(' ') {
+        let word = c.starts_with(|c: char| c.is_alphanumeric() || c == '_');
+        if prev_word && word {
+            name.push(' ');
+        }
+        name.push_str(c);
+        prev_word = c.ends_with(|c: char| c.is_alphanumeric() || c == '_');
+    }
+    name
+}
+
+/// Returns type which handler methods take by reference for a field of type
+/// `ty`: `String` fields are passed as `&str` and `Vec<T>` fields as `&[T]`;
+/// other types are passed as is.
+fn borrowed_type(ty: &Type) -> TokenStream2 {
+    if let Type::Path(path) = ty {
+        if let (None, Some(segment)) = (&path.qself, path.path.segments.last())
+        {
+            match &segment.arguments {
+                PathArguments::None if segment.ident == "String" => {
+                    return quote! { str };
+                }
+                PathArguments::AngleBracketed(args)
+                    if segment.ident == "Vec" && args.args.len() == 1 =>
+                {
+                    if let Some(GenericArgument::Type(inner)) =
+                        args.args.first()
+                    {
+                        return quote! { [#inner] };
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    quote! { #ty }
+}
+
+/// Converts `CamelCase` variant name into `snake_case`
+fn snake_case(variant: &Ident) -> String {
+    let mut name = String::new();
+    for (no, c) in variant.to_string().chars().enumerate() {
+        if c.is_uppercase() {
+            if no > 0 {
+                name.push('_');
+            }
+            name.extend(c.to_lowercase());
+        } else {
+            name.push(c);
+        }
+    }
+    name
+}
+
+fn type_const(variant: &Ident) -> Ident {
+    Ident::new(
+        &format!("MSG_TYPE_{}", variant.to_string().to_uppercase()),
+        variant.span(),
+    )
+}
+
+/// Resolves reply variant path into the constant holding the reply message
+/// type. Variant names without enum prefix must belong to the enum being
+/// derived; for paths like `Reply::Ack` the existence of the variant is
+/// checked by the compiler via the `Reply::MSG_TYPE_ACK` constant generated
+/// by the `Api` derivation for the `Reply` enum.
+fn reply_type_const(
+    path: &Path,
+    enum_name: &Ident,
+    data: &DataEnum,
+) -> Result<TokenStream2> {
+    let mut segments = path.segments.iter().collect::<Vec<_>>();
+    let variant = &segments
+        .pop()
+        .expect("syn parses paths with at least one segment")
+        .ident;
+    let type_const = type_const(variant);
+    if segments.is_empty() {
+        if !data.variants.iter().any(|v| &v.ident == variant) {
+            return Err(Error::new(
+                path.span(),
+                format!(
+                    "Attribute `#[{}]`: `{}` has no `{}` variant\nExample \
+                     use: {}",
+                    NAME, enum_name, variant, VARIANT_EXAMPLE
+                ),
+            ));
+        }
+        return Ok(quote_spanned! { path.span() => #enum_name::#type_const });
+    }
+    let leading_colon = path.leading_colon;
+    Ok(quote_spanned! { path.span() =>
+        #leading_colon #( #segments:: )* #type_const
     })
 }
 
@@ -284,7 +1005,7 @@ impl EncodingSrategy {
                 quote_spanned!(span => #import::strict_serialize(obj).expect(ERR))
             }
             Self::Bitcoin => {
-                quote_spanned!(span => #import::consensus::encode::consensus_encode(obj))
+                quote_spanned!(span => #import::consensus::encode::serialize(obj))
             }
             Self::Lightning => {
                 quote_spanned!(span => #import::lightning_serialize(obj).expect(ERR))
@@ -320,7 +1041,7 @@ impl EncodingSrategy {
                 use #import::StrictEncode;
             ),
             Self::Bitcoin => quote!(
-                use #import::consensus::encode::Encode;
+                use #import::consensus::encode::Encodable;
             ),
             Self::Lightning => quote!(
                 use #import::LightningEncode;
@@ -334,7 +1055,7 @@ impl EncodingSrategy {
                 use #import::StrictDecode;
             ),
             Self::Bitcoin => quote!(
-                use #import::consensus::encode::Decode;
+                use #import::consensus::encode::Decodable;
             ),
             Self::Lightning => quote!(
                 use #import::LightningDecode;
@@ -199,3 +199,17 @@ pub(crate) fn nested_one_named_value(
     })
     .transpose()
 }
+
+pub(crate) fn nested_metas(
+    list: IntoIter<NestedMeta>,
+    attr_name: &str,
+    example: &str,
+) -> Result<Vec<Meta>> {
+    list.map(|nested| match nested {
+        NestedMeta::Meta(meta) => Ok(meta),
+        NestedMeta::Lit(_) => {
+            Err(attr_err!(attr_name, "unexpected literal argument", example))
+        }
+    })
+    .collect()
+}
@@ -0,0 +1,14 @@
+#[macro_use]
+extern crate inet2_derive;
+
+#[derive(Clone, Debug, Api)]
+#[api(encoding = "strict")]
+enum Message {
+    #[api(type = 0x0010)]
+    Hello,
+
+    #[api(type = 16)] //~ ERROR type id 0x0010 is already used by `Hello` variant
+    Other,
+}
+
+fn main() {}
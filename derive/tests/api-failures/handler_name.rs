@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate inet2_derive;
+
+#[derive(Clone, Debug, Api)]
+#[api(encoding = "strict", handler = "Request Handler")] //~ ERROR `handler` must be a valid trait name
+enum Message {
+    #[api(type = 0x0010)]
+    Hello,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate inet2_derive;
+
+#[derive(Clone, Debug, Api)]
+#[api(encoding = "strict")]
+enum Message {
+    #[api(type = 0x0010, max_len = "1kb")] //~ ERROR `max_len` must be an integer
+    Hello,
+}
+
+fn main() {}
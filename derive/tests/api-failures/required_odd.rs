@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate inet2_derive;
+
+#[derive(Clone, Debug, Api)]
+#[api(encoding = "strict")]
+enum Message {
+    #[api(type = 0x0011, required)] //~ ERROR `required` messages must have even type id
+    Hello,
+}
+
+fn main() {}
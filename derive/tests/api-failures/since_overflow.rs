@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate inet2_derive;
+
+#[derive(Clone, Debug, Api)]
+#[api(encoding = "strict")]
+enum Message {
+    #[api(type = 0x0010, since = 70000)] //~ ERROR `since` must be a 16-bit integer
+    Hello,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate inet2_derive;
+
+#[derive(Clone, Debug, Api)]
+#[api(encoding = "lightning")]
+enum Message {
+    #[api(type = 0x0010, tlv)] //~ ERROR `tlv` messages must have TLV extension stream
+    Hello,
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+#[macro_use]
+extern crate inet2_derive;
+
+#[derive(Clone, Debug, Api)]
+#[api(encoding = "strict")]
+enum Message {
+    #[api(type = 0x0010, reply = "Ack")] //~ ERROR `Message` has no `Ack` variant
+    Hello,
+
+    #[api(type = 0x0001)]
+    Nack,
+}
+
+fn main() {}
@@ -0,0 +1,98 @@
+#[macro_use]
+extern crate inet2_derive;
+
+use std::io::Cursor;
+use std::str::FromStr;
+
+use bitcoin::hashes::Hash;
+use internet2::{CreateUnmarshaller, TypedEnum, Unmarshall};
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "bitcoin")]
+pub enum Request {
+    #[api(type = 0x0001)]
+    Hello(String),
+
+    /// Some attribute
+    #[api(type = 0x0003)]
+    Empty(),
+
+    #[api(type = 0x0005)]
+    NoArgs,
+
+    #[api(type = 0x0103)]
+    AddBlocks(Vec<bitcoin::BlockHash>),
+
+    #[api(type = 0x0201)]
+    Pair(u16, String),
+
+    #[api(type = 0x0203)]
+    Named { id: u8, name: String },
+}
+
+#[test]
+fn roundtrip() {
+    let unmarshaller = Request::create_unmarshaller();
+
+    let message = Request::Hello("world".to_owned());
+    let payload = message.serialize();
+    assert_eq!(payload, b"\x01\x00\x05world".to_vec());
+    let roundtrip = &*unmarshaller.unmarshall(Cursor::new(payload)).unwrap();
+    assert_eq!(&message, roundtrip);
+
+    let message = Request::Empty();
+    let payload = message.serialize();
+    assert_eq!(payload, b"\x03\x00".to_vec());
+    let roundtrip = &*unmarshaller.unmarshall(Cursor::new(payload)).unwrap();
+    assert_eq!(&message, roundtrip);
+
+    let message = Request::NoArgs;
+    let payload = message.serialize();
+    assert_eq!(payload, b"\x05\x00".to_vec());
+    let roundtrip = &*unmarshaller.unmarshall(Cursor::new(payload)).unwrap();
+    assert_eq!(&message, roundtrip);
+
+    let hashes: Vec<_> = vec![
+        "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+        "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048",
+    ]
+    .into_iter()
+    .map(bitcoin::BlockHash::from_str)
+    .map(Result::unwrap)
+    .collect();
+    let message = Request::AddBlocks(hashes.clone());
+    let payload = message.serialize();
+    let mut expect = b"\x03\x01\x02".to_vec();
+    expect.extend(hashes.iter().flat_map(|hash| hash.into_inner()));
+    assert_eq!(payload, expect);
+    let roundtrip = &*unmarshaller.unmarshall(Cursor::new(payload)).unwrap();
+    assert_eq!(&message, roundtrip);
+}
+
+#[test]
+fn multiple_fields() {
+    let unmarshaller = Request::create_unmarshaller();
+
+    let message = Request::Pair(42, "ab".to_owned());
+    let payload = message.serialize();
+    assert_eq!(payload, b"\x01\x02\x2a\x00\x02ab".to_vec());
+    let roundtrip = &*unmarshaller.unmarshall(Cursor::new(payload)).unwrap();
+    assert_eq!(&message, roundtrip);
+
+    let message = Request::Named {
+        id: 7,
+        name: "name".to_owned(),
+    };
+    let payload = message.serialize();
+    assert_eq!(payload, b"\x03\x02\x07\x04name".to_vec());
+    let roundtrip = &*unmarshaller.unmarshall(Cursor::new(payload)).unwrap();
+    assert_eq!(&message, roundtrip);
+}
+
+#[test]
+fn broken_payload() {
+    let unmarshaller = Request::create_unmarshaller();
+    assert!(unmarshaller
+        .unmarshall(Cursor::new(b"\x01\x00\x05wor".to_vec()))
+        .is_err());
+}
@@ -0,0 +1,58 @@
+extern crate compiletest_rs as compiletest;
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Locates `inet2_derive` proc-macro library built for this test run.
+///
+/// Integration tests are placed into the same `deps` directory as their
+/// dependencies, which may also contain other builds of the library made with
+/// different features and compiler flags (causing E0464 error if all of them
+/// are passed to the compiler). The library used by this test is built right
+/// before the test executable, so we pick the latest one which is not newer
+/// than the executable itself.
+fn proc_macro_lib() -> PathBuf {
+    let exe = std::env::current_exe().expect("unknown test executable");
+    let built = fs::metadata(&exe)
+        .and_then(|meta| meta.modified())
+        .expect("unknown test executable build time");
+    let deps = exe.parent().expect("test executable has no directory");
+    let prefix = format!("{}inet2_derive-", std::env::consts::DLL_PREFIX);
+    fs::read_dir(deps)
+        .expect("missing dependency directory")
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.starts_with(&prefix)
+                && name.ends_with(std::env::consts::DLL_SUFFIX)
+        })
+        .filter_map(|entry| {
+            let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+            Some((modified, entry.path()))
+        })
+        .filter(|(modified, _)| *modified <= built)
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path)
+        .expect("inet2_derive library is not built")
+}
+
+#[test]
+fn api_failures() {
+    let mut config = compiletest::Config {
+        mode: compiletest::common::Mode::CompileFail,
+        src_base: PathBuf::from("tests/api-failures"),
+        ..Default::default()
+    };
+
+    config.link_deps(); // Populate config.target_rustcflags with dependencies on the path
+    config.clean_rmeta(); // If your tests import the parent crate, this helps with E0464
+
+    let flags = config.target_rustcflags.take().unwrap_or_default();
+    config.target_rustcflags = Some(format!(
+        "{} --extern inet2_derive={}",
+        flags,
+        proc_macro_lib().display()
+    ));
+
+    compiletest::run_tests(&config);
+}
@@ -0,0 +1,79 @@
+#[macro_use]
+extern crate inet2_derive;
+
+use internet2::presentation::UnsupportedReply;
+use internet2::{Dispatch, TypeId};
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "strict", handler = "RequestHandler")]
+pub enum Request {
+    #[api(type = 0x0001)]
+    Hello(String),
+
+    #[api(type = 0x0003)]
+    AddKeys { keys: Vec<u8>, force: bool },
+
+    #[api(type = 0x0005)]
+    Ping,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Reply {
+    Greeting(String),
+    Added(usize),
+    Unsupported(TypeId),
+}
+
+impl UnsupportedReply for Reply {
+    fn unsupported(type_id: TypeId) -> Self { Reply::Unsupported(type_id) }
+}
+
+#[derive(Default)]
+struct Service {
+    keys: Vec<u8>,
+}
+
+impl RequestHandler for Service {
+    type Reply = Reply;
+
+    fn handle_hello(&mut self, field0: &str) -> Reply {
+        Reply::Greeting(format!("hello, {}", field0))
+    }
+
+    fn handle_add_keys(&mut self, keys: &[u8], force: &bool) -> Reply {
+        if *force {
+            self.keys.clear();
+        }
+        self.keys.extend(keys);
+        Reply::Added(self.keys.len())
+    }
+}
+
+struct Ignore;
+
+impl RequestHandler for Ignore {
+    type Reply = Option<()>;
+}
+
+#[test]
+fn dispatch() {
+    let mut service = Service::default();
+    assert_eq!(
+        Request::Hello("world".to_owned()).dispatch(&mut service),
+        Reply::Greeting("hello, world".to_owned())
+    );
+    let request = Request::AddKeys {
+        keys: vec![1, 2],
+        force: false,
+    };
+    assert_eq!(request.dispatch(&mut service), Reply::Added(2));
+    assert_eq!(Dispatch::dispatch(&request, &mut service), Reply::Added(4));
+    assert_eq!(
+        Request::Ping.dispatch(&mut service),
+        Reply::Unsupported(TypeId::with(Request::MSG_TYPE_PING))
+    );
+
+    let handler: &mut dyn RequestHandler<Reply = Option<()>> = &mut Ignore;
+    assert_eq!(Request::Ping.dispatch(handler), None);
+    assert_eq!(Request::Hello("world".to_owned()).dispatch(handler), None);
+}
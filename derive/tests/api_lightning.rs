@@ -21,6 +21,15 @@ pub enum Request {
 
     #[api(type = 0x0103)]
     AddKeys(Vec<secp256k1::PublicKey>),
+
+    #[api(type = 0x0201)]
+    Pair(u16, String),
+
+    #[api(type = 0x0203)]
+    Named { id: u8, name: String },
+
+    #[api(type = 0x0205)]
+    NamedEmpty {},
 }
 
 #[test]
@@ -61,3 +70,29 @@ fn roundtrip() {
     let roundtrip = &*unmarshaller.unmarshall(Cursor::new(payload)).unwrap();
     assert_eq!(&message, roundtrip);
 }
+
+#[test]
+fn multiple_fields() {
+    let unmarshaller = Request::create_unmarshaller();
+
+    let message = Request::Pair(42, "ab".to_owned());
+    let payload = message.serialize();
+    assert_eq!(payload, b"\x02\x01\x00\x2a\x00\x02ab".to_vec());
+    let roundtrip = &*unmarshaller.unmarshall(Cursor::new(payload)).unwrap();
+    assert_eq!(&message, roundtrip);
+
+    let message = Request::Named {
+        id: 7,
+        name: "name".to_owned(),
+    };
+    let payload = message.serialize();
+    assert_eq!(payload, b"\x02\x03\x07\x00\x04name".to_vec());
+    let roundtrip = &*unmarshaller.unmarshall(Cursor::new(payload)).unwrap();
+    assert_eq!(&message, roundtrip);
+
+    let message = Request::NamedEmpty {};
+    let payload = message.serialize();
+    assert_eq!(payload, b"\x02\x05".to_vec());
+    let roundtrip = &*unmarshaller.unmarshall(Cursor::new(payload)).unwrap();
+    assert_eq!(&message, roundtrip);
+}
@@ -0,0 +1,131 @@
+#[macro_use]
+extern crate inet2_derive;
+
+use std::io::Cursor;
+
+use internet2::presentation::{tlv, Error};
+use internet2::{CreateUnmarshaller, TypeId, TypedEnum, Unmarshall};
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "lightning")]
+pub enum Request {
+    #[api(type = 0x0010, max_len = 8)]
+    Hello(String),
+
+    #[api(type = 0x0012)]
+    Data(Vec<u8>),
+
+    #[api(type = 0x0014, max_len = 4, tlv)]
+    Extended(u16, tlv::Stream),
+}
+
+#[test]
+fn type_limits() {
+    let unmarshaller = Request::create_unmarshaller();
+    assert_eq!(unmarshaller.type_max_len(TypeId::with(0x0010)), Some(8));
+    assert_eq!(unmarshaller.type_max_len(TypeId::with(0x0012)), None);
+
+    // Payload of exactly the maximum size
+    let message = Request::Hello("world!".to_owned());
+    let roundtrip = &*unmarshaller
+        .unmarshall(Cursor::new(message.serialize()))
+        .unwrap();
+    assert_eq!(&message, roundtrip);
+
+    let message = Request::Hello("world!!".to_owned());
+    assert_eq!(
+        unmarshaller
+            .unmarshall(Cursor::new(message.serialize()))
+            .unwrap_err(),
+        Error::PayloadTooLarge(TypeId::with(0x0010), 8)
+    );
+
+    let message = Request::Data(vec![0u8; 0x1000]);
+    let roundtrip = &*unmarshaller
+        .unmarshall(Cursor::new(message.serialize()))
+        .unwrap();
+    assert_eq!(&message, roundtrip);
+
+    // TLV extension is a part of the payload
+    let mut stream = tlv::Stream::new();
+    stream.insert(1u64.into(), b"ab");
+    let message = Request::Extended(1, stream);
+    assert_eq!(
+        unmarshaller
+            .unmarshall(Cursor::new(message.serialize()))
+            .unwrap_err(),
+        Error::PayloadTooLarge(TypeId::with(0x0014), 4)
+    );
+}
+
+#[test]
+fn global_limit() {
+    let mut unmarshaller = Request::create_unmarshaller();
+    unmarshaller.set_max_payload_len(Some(0x100));
+    assert_eq!(unmarshaller.max_payload_len(), Some(0x100));
+    assert_eq!(unmarshaller.type_max_len(TypeId::with(0x0010)), Some(8));
+    assert_eq!(unmarshaller.type_max_len(TypeId::with(0x0012)), Some(0x100));
+
+    let message = Request::Data(vec![0u8; 0x100]);
+    assert_eq!(
+        unmarshaller
+            .unmarshall(Cursor::new(message.serialize()))
+            .unwrap_err(),
+        Error::PayloadTooLarge(TypeId::with(0x0012), 0x100)
+    );
+
+    // Unknown odd messages are also limited
+    let mut data = vec![0x00, 0x13];
+    data.extend([0u8; 0x101]);
+    assert_eq!(
+        unmarshaller.unmarshall(Cursor::new(data)).unwrap_err(),
+        Error::PayloadTooLarge(TypeId::with(0x0013), 0x100)
+    );
+
+    // No more than a single byte past the limit is read
+    let mut data = vec![0x00, 0x13];
+    data.extend([0u8; 0x200]);
+    let mut reader = Cursor::new(data);
+    assert_eq!(
+        unmarshaller.unmarshall(&mut reader).unwrap_err(),
+        Error::PayloadTooLarge(TypeId::with(0x0013), 0x100)
+    );
+    assert_eq!(reader.position(), 2 + 0x101);
+
+    unmarshaller.set_type_max_len(TypeId::with(0x0012), 0x1000);
+    let roundtrip = &*unmarshaller
+        .unmarshall(Cursor::new(message.serialize()))
+        .unwrap();
+    assert_eq!(&message, roundtrip);
+}
+
+#[test]
+fn frame_limits() {
+    let mut unmarshaller = Request::create_unmarshaller();
+    assert_eq!(unmarshaller.max_frame_len(), None);
+    unmarshaller.set_max_payload_len(Some(0x100));
+    assert_eq!(unmarshaller.max_frame_len(), Some(2 + 0x100));
+    unmarshaller.set_type_max_len(TypeId::with(0x0012), 0x1000);
+    assert_eq!(unmarshaller.max_frame_len(), Some(2 + 0x1000));
+
+    let message = Request::Hello("world!".to_owned());
+    let roundtrip =
+        &*unmarshaller.unmarshall_frame(&message.serialize()).unwrap();
+    assert_eq!(&message, roundtrip);
+
+    // Frame payload is checked before parsing, so the string length prefix
+    // is never read
+    let message = Request::Hello("world!!".to_owned());
+    assert_eq!(
+        unmarshaller
+            .unmarshall_frame(&message.serialize())
+            .unwrap_err(),
+        Error::PayloadTooLarge(TypeId::with(0x0010), 8)
+    );
+    let mut data = vec![0x00, 0x13];
+    data.extend([0u8; 0x101]);
+    assert_eq!(
+        unmarshaller.unmarshall_frame(&data).unwrap_err(),
+        Error::PayloadTooLarge(TypeId::with(0x0013), 0x100)
+    );
+}
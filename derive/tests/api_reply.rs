@@ -0,0 +1,71 @@
+#[macro_use]
+extern crate inet2_derive;
+
+use internet2::{ExpectedReply, TypeId, TypedEnum};
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "strict")]
+pub enum Message {
+    #[api(type = 0x0010, required, reply = "Ack", error = "Failure")]
+    Hello(String),
+
+    #[api(type = 0x0012, reply = "Ack", reply = "Info", error = "Failure")]
+    Query,
+
+    #[api(type = 0x0020, reply = "Reply::Done")]
+    Shutdown,
+
+    #[api(type = 0x0001, optional)]
+    Ack,
+
+    #[api(type = 0x0003)]
+    Info(String),
+
+    #[api(type = 0x0005)]
+    Failure(String),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "strict")]
+pub enum Reply {
+    #[api(type = 0x0021)]
+    Done,
+}
+
+impl ExpectedReply for Reply {
+    fn expected_reply_types(&self) -> &[TypeId] {
+        const TYPES: &[TypeId] = &[TypeId::with(Message::MSG_TYPE_ACK)];
+        TYPES
+    }
+}
+
+#[test]
+fn reply_types() {
+    let message = Message::Hello("world".to_owned());
+    assert_eq!(message.expected_reply_types(), &[
+        TypeId::with(0x0001),
+        TypeId::with(0x0005)
+    ]);
+    assert!(message.is_expected_reply(Message::Ack.get_type()));
+    assert!(!message.is_expected_reply(Message::Info(String::new()).get_type()));
+
+    assert_eq!(Message::Query.expected_reply_types(), &[
+        TypeId::with(0x0001),
+        TypeId::with(0x0003),
+        TypeId::with(0x0005)
+    ]);
+
+    assert_eq!(Message::Shutdown.expected_reply_types(), &[TypeId::with(
+        Reply::MSG_TYPE_DONE
+    )]);
+    assert!(Message::Shutdown.is_expected_reply(Reply::Done.get_type()));
+
+    // Messages without declared replies accept any reply
+    assert!(Message::Ack.expected_reply_types().is_empty());
+    assert!(Message::Ack.is_expected_reply(Reply::Done.get_type()));
+
+    // Enums without declared replies may have their own implementation
+    assert_eq!(Reply::Done.expected_reply_types(), &[TypeId::with(
+        Message::MSG_TYPE_ACK
+    )]);
+}
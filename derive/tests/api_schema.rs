@@ -0,0 +1,91 @@
+#[macro_use]
+extern crate inet2_derive;
+
+use internet2::presentation::{
+    schema, ApiSchema, DescribeApi, EncodingType, FieldSchema, MessageSchema,
+};
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "lightning", schema)]
+pub enum Request {
+    #[api(type = 0x0010, reply = "Ack")]
+    Hello(String),
+
+    #[api(type = 0x0012, since = 2, deprecated, max_len = 1024)]
+    Keys {
+        keys: Vec<secp256k1::PublicKey>,
+        data: [u8; 32],
+    },
+
+    #[api(type = 0x0001)]
+    Ack,
+}
+
+fn field(name: Option<&str>, type_name: &str) -> FieldSchema {
+    FieldSchema {
+        name: name.map(str::to_owned),
+        type_name: type_name.to_owned(),
+    }
+}
+
+#[test]
+fn schema() {
+    let schema = Request::api_schema();
+    assert_eq!(schema, ApiSchema {
+        name: "Request".to_owned(),
+        encoding: EncodingType::Lightning,
+        messages: vec![
+            MessageSchema {
+                name: "Hello".to_owned(),
+                type_id: 0x0010,
+                fields: vec![field(None, "String")],
+                tlv: false,
+                replies: vec![0x0001],
+                errors: vec![],
+                since: 0,
+                deprecated: false,
+                max_len: None,
+            },
+            MessageSchema {
+                name: "Keys".to_owned(),
+                type_id: 0x0012,
+                fields: vec![
+                    field(Some("keys"), "Vec<secp256k1::PublicKey>"),
+                    field(Some("data"), "[u8;32]"),
+                ],
+                tlv: false,
+                replies: vec![],
+                errors: vec![],
+                since: 2,
+                deprecated: true,
+                max_len: Some(1024),
+            },
+            MessageSchema {
+                name: "Ack".to_owned(),
+                type_id: 0x0001,
+                fields: vec![],
+                tlv: false,
+                replies: vec![],
+                errors: vec![],
+                since: 0,
+                deprecated: false,
+                max_len: None,
+            }
+        ]
+    });
+    assert_eq!(schema.message(0x0012).unwrap().name, "Keys");
+    assert!(schema.message(0x0014).is_none());
+}
+
+#[test]
+fn json_dump() {
+    let schemas = [ApiSchema::of::<Request>()];
+    assert_eq!(schemas[0], Request::api_schema());
+
+    let mut json = vec![];
+    schema::write_json(&schemas, &mut json).unwrap();
+    assert_eq!(
+        serde_json::from_slice::<Vec<ApiSchema>>(&json).unwrap(),
+        schemas
+    );
+}
@@ -0,0 +1,96 @@
+#[macro_use]
+extern crate inet2_derive;
+
+use internet2::presentation::json::{self, JsonTranscoder};
+use internet2::TypedEnum;
+use serde_json::json;
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "strict", serde)]
+pub enum Request {
+    #[api(type = 0x0001)]
+    Hello(String),
+
+    #[api(type = 0x0003)]
+    AddKeys { keys: Vec<u8>, force: bool },
+
+    #[api(type = 0x0005)]
+    Pair(u16, String),
+
+    #[api(type = 0x0007)]
+    Ping,
+}
+
+#[test]
+fn json_representation() {
+    let message = Request::Hello("world".to_owned());
+    let value = serde_json::to_value(&message).unwrap();
+    assert_eq!(
+        value,
+        json!({ "type": "hello", "type_id": 1, "payload": "world" })
+    );
+    assert_eq!(serde_json::from_value::<Request>(value).unwrap(), message);
+
+    let message = Request::AddKeys {
+        keys: vec![1, 2],
+        force: true,
+    };
+    let value = serde_json::to_value(&message).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "add_keys",
+            "type_id": 3,
+            "payload": { "keys": [1, 2], "force": true }
+        })
+    );
+    assert_eq!(serde_json::from_value::<Request>(value).unwrap(), message);
+
+    let message = Request::Pair(7, "seven".to_owned());
+    let value = serde_json::to_value(&message).unwrap();
+    assert_eq!(
+        value,
+        json!({ "type": "pair", "type_id": 5, "payload": [7, "seven"] })
+    );
+    assert_eq!(serde_json::from_value::<Request>(value).unwrap(), message);
+
+    let message = Request::Ping;
+    let value = serde_json::to_value(&message).unwrap();
+    assert_eq!(value, json!({ "type": "ping", "type_id": 7 }));
+    assert_eq!(serde_json::from_value::<Request>(value).unwrap(), message);
+
+    // Type id is optional, but must match the type if present
+    assert_eq!(
+        serde_json::from_value::<Request>(json!({ "type": "ping" })).unwrap(),
+        Request::Ping
+    );
+    assert!(serde_json::from_value::<Request>(
+        json!({ "type": "ping", "type_id": 1 })
+    )
+    .is_err());
+    assert!(
+        serde_json::from_value::<Request>(json!({ "type": "pong" })).is_err()
+    );
+}
+
+#[test]
+fn transcoder() {
+    let transcoder = JsonTranscoder::<Request>::new();
+    let message = Request::Pair(7, "seven".to_owned());
+    let wire = message.serialize();
+
+    let json = transcoder.wire_to_json(&wire).unwrap();
+    assert_eq!(transcoder.json_to_wire(&json).unwrap(), wire);
+    let value = transcoder.wire_to_value(&wire).unwrap();
+    assert_eq!(value["type"], "pair");
+    assert_eq!(transcoder.value_to_wire(value).unwrap(), wire);
+
+    assert!(matches!(
+        transcoder.wire_to_json(b"\x02\x00"),
+        Err(json::Error::Presentation(_))
+    ));
+    assert!(matches!(
+        transcoder.json_to_wire(r#"{ "type": "hello" }"#),
+        Err(json::Error::Json(_))
+    ));
+}
@@ -0,0 +1,152 @@
+#[macro_use]
+extern crate inet2_derive;
+
+use std::io::Cursor;
+
+use amplify::Wrapper;
+use internet2::presentation::{tlv, Error, Extract};
+use internet2::{CreateUnmarshaller, TypedEnum, Unmarshall};
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "lightning")]
+pub enum Request {
+    #[api(type = 0x0010, tlv)]
+    Init(u16, tlv::Stream),
+
+    #[api(type = 0x0012, tlv)]
+    Named {
+        name: String,
+        extension: tlv::Stream,
+    },
+
+    #[api(type = 0x0014, tlv)]
+    Extension(tlv::Stream),
+
+    #[api(type = 0x0016)]
+    Plain(u16),
+}
+
+fn stream(records: &[(u64, &[u8])]) -> tlv::Stream {
+    let mut stream = tlv::Stream::new();
+    for (ty, value) in records {
+        stream.insert(tlv::Type::from_inner(*ty), value);
+    }
+    stream
+}
+
+#[test]
+fn roundtrip() {
+    let unmarshaller = Request::create_unmarshaller();
+
+    let message = Request::Init(7, stream(&[(1, b"a"), (0xFD, b"bc")]));
+    let payload = message.serialize();
+    assert_eq!(
+        payload,
+        b"\x00\x10\x00\x07\x01\x01a\xfd\x00\xfd\x02bc".to_vec()
+    );
+    let roundtrip = &*unmarshaller.unmarshall(Cursor::new(payload)).unwrap();
+    assert_eq!(&message, roundtrip);
+    assert_eq!(
+        Extract::get_tlvs(&message),
+        stream(&[(1, b"a"), (0xFD, b"bc")])
+    );
+
+    let message = Request::Named {
+        name: "node".to_owned(),
+        extension: stream(&[(3, b"")]),
+    };
+    let payload = message.serialize();
+    assert_eq!(payload, b"\x00\x12\x00\x04node\x03\x00".to_vec());
+    let roundtrip = &*unmarshaller.unmarshall(Cursor::new(payload)).unwrap();
+    assert_eq!(&message, roundtrip);
+    assert_eq!(Extract::get_tlvs(&message), stream(&[(3, b"")]));
+
+    let message = Request::Extension(stream(&[(5, b"x")]));
+    let payload = message.serialize();
+    assert_eq!(payload, b"\x00\x14\x05\x01x".to_vec());
+    let roundtrip = &*unmarshaller.unmarshall(Cursor::new(payload)).unwrap();
+    assert_eq!(&message, roundtrip);
+
+    let message = Request::Plain(1);
+    assert!(Extract::get_tlvs(&message).is_empty());
+    assert_eq!(Extract::get_type(&message), TypedEnum::get_type(&message));
+}
+
+#[test]
+fn missing_extension() {
+    let unmarshaller = Request::create_unmarshaller();
+    let message = &*unmarshaller
+        .unmarshall(Cursor::new(b"\x00\x10\x00\x07"))
+        .unwrap();
+    assert_eq!(message, &Request::Init(7, tlv::Stream::new()));
+    assert_eq!(message.serialize(), b"\x00\x10\x00\x07".to_vec());
+}
+
+#[test]
+fn invalid_extension() {
+    let unmarshaller = Request::create_unmarshaller();
+    // Unknown even TLV records must fail the parsing
+    assert_eq!(
+        unmarshaller
+            .unmarshall(Cursor::new(b"\x00\x10\x00\x07\x02\x01a"))
+            .unwrap_err(),
+        Error::TlvRecordEvenType
+    );
+    // TLV records must be strictly increasing
+    assert_eq!(
+        unmarshaller
+            .unmarshall(Cursor::new(b"\x00\x10\x00\x07\x03\x00\x01\x00"))
+            .unwrap_err(),
+        Error::TlvStreamWrongOrder
+    );
+    assert_eq!(
+        unmarshaller
+            .unmarshall(Cursor::new(b"\x00\x10\x00\x07\x03\x00\x03\x00"))
+            .unwrap_err(),
+        Error::TlvStreamDuplicateItem
+    );
+    // Record length must not exceed the remaining data
+    assert!(unmarshaller
+        .unmarshall(Cursor::new(b"\x00\x10\x00\x07\x03\x05a"))
+        .is_err());
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "lightning", extract)]
+pub enum Extracted {
+    #[api(type = 0x0020)]
+    Plain(u16),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "lightning")]
+pub enum Custom {
+    #[api(type = 0x0030)]
+    Plain(u16),
+}
+
+impl amplify::AsAny for Custom {
+    fn as_any(&self) -> &dyn std::any::Any { self }
+}
+
+impl Extract for Custom {
+    fn get_type(&self) -> internet2::TypeId { internet2::TypeId::with(0) }
+
+    fn get_payload(&self) -> internet2::presentation::message::Source {
+        internet2::presentation::message::Source::from(vec![])
+    }
+
+    fn get_tlvs(&self) -> tlv::Stream { stream(&[(1, b"custom")]) }
+}
+
+#[test]
+fn extract_opt_in() {
+    let message = Extracted::Plain(1);
+    assert!(Extract::get_tlvs(&message).is_empty());
+    assert_eq!(Extract::get_type(&message), TypedEnum::get_type(&message));
+
+    // Enums without TLV extensions may have their own `Extract` implementation
+    let message = Custom::Plain(1);
+    assert_eq!(Extract::get_tlvs(&message), stream(&[(1, b"custom")]));
+    assert_eq!(Extract::get_type(&message), internet2::TypeId::with(0));
+}
@@ -0,0 +1,94 @@
+#[macro_use]
+extern crate inet2_derive;
+
+use std::io::Cursor;
+
+use internet2::presentation::{Error, MessageVersion};
+use internet2::{CreateUnmarshaller, TypeId, TypedEnum, Unmarshall};
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "strict")]
+pub enum Request {
+    #[api(type = 0x0001)]
+    Hello(String),
+
+    #[api(type = 0x0003, since = 2)]
+    Ping(u16),
+
+    #[api(type = 0x0005, deprecated)]
+    Legacy,
+
+    #[api(type = 0x0007, since = 3, deprecated)]
+    Experiment,
+}
+
+#[test]
+fn version_metadata() {
+    assert_eq!(
+        Request::Hello("world".to_owned()).get_version(),
+        MessageVersion::default()
+    );
+    assert_eq!(Request::Ping(1).get_version(), MessageVersion {
+        since: 2,
+        deprecated: false
+    });
+    assert_eq!(Request::Legacy.get_version(), MessageVersion {
+        since: 0,
+        deprecated: true
+    });
+    assert_eq!(Request::Experiment.get_version(), MessageVersion {
+        since: 3,
+        deprecated: true
+    });
+    assert!(Request::Ping(1).get_version().is_supported_by(2));
+    assert!(!Request::Ping(1).get_version().is_supported_by(1));
+}
+
+#[test]
+fn negotiated_version() {
+    let unmarshaller = Request::create_unmarshaller();
+    assert_eq!(unmarshaller.protocol_version(), None);
+    let ping = Request::Ping(7).serialize();
+    assert_eq!(
+        &*unmarshaller.unmarshall(Cursor::new(ping.clone())).unwrap(),
+        &Request::Ping(7)
+    );
+
+    let unmarshaller = Request::create_versioned_unmarshaller(1);
+    assert_eq!(unmarshaller.protocol_version(), Some(1));
+    assert_eq!(
+        unmarshaller
+            .unmarshall(Cursor::new(ping.clone()))
+            .unwrap_err(),
+        Error::UnsupportedMessageVersion(TypeId::with(0x0003))
+    );
+    let hello = Request::Hello("world".to_owned());
+    assert_eq!(
+        &*unmarshaller
+            .unmarshall(Cursor::new(hello.serialize()))
+            .unwrap(),
+        &hello
+    );
+
+    let unmarshaller = Request::create_versioned_unmarshaller(2);
+    assert_eq!(
+        &*unmarshaller.unmarshall(Cursor::new(ping)).unwrap(),
+        &Request::Ping(7)
+    );
+}
+
+#[test]
+fn deprecated() {
+    let legacy = Request::Legacy.serialize();
+    let mut unmarshaller = Request::create_versioned_unmarshaller(3);
+    let msg = unmarshaller
+        .unmarshall(Cursor::new(legacy.clone()))
+        .unwrap();
+    assert!(msg.get_version().deprecated);
+
+    unmarshaller.set_reject_deprecated(true);
+    assert_eq!(
+        unmarshaller.unmarshall(Cursor::new(legacy)).unwrap_err(),
+        Error::DeprecatedMessage(TypeId::with(0x0005))
+    );
+}
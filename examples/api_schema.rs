@@ -0,0 +1,52 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Dumps JSON schema of API enums, which can be used to diff protocol
+//! versions or to generate bindings for other languages.
+//!
+//! Protocol crates may use this example as a template for their own schema
+//! dumping binaries, listing their API enums in the `main` function.
+//!
+//! Run with `cargo run --example api_schema --features serde,derive`
+
+use internet2::presentation::{schema, ApiSchema};
+use internet2::Api;
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "strict", schema)]
+pub enum Request {
+    #[api(type = 0x0010, required, reply = "Reply::Pong")]
+    Ping(u16),
+
+    #[api(type = 0x0012, reply = "Reply::Info", error = "Reply::Failure")]
+    Query { key: String, limit: Option<u32> },
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "strict", schema)]
+pub enum Reply {
+    #[api(type = 0x0011)]
+    Pong(u16),
+
+    #[api(type = 0x0013)]
+    Info(Vec<(String, Vec<u8>)>),
+
+    #[api(type = 0x0015)]
+    Failure(u16, String),
+}
+
+fn main() {
+    let schemas = [ApiSchema::of::<Request>(), ApiSchema::of::<Reply>()];
+    schema::write_json(&schemas, std::io::stdout())
+        .expect("unable to write API schema");
+}
@@ -0,0 +1,383 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Enterprise service bus (ESB) working over ZMQ ROUTER sockets.
+//!
+//! Bus consists of a single [`Broker`], using [`ZmqSocketType::RouterBind`]
+//! session, and multiple services connected to it with
+//! [`ZmqSocketType::RouterConnect`] sessions wrapped into [`Client`]. Services
+//! may register with the broker under a name; the broker forwards each
+//! [`RoutedFrame`] to the service registered under the frame `dst` name or
+//! having `dst` socket identity. Since ROUTER socket learns identities of its
+//! peers only from the frames they send, a service which has not registered
+//! any name is reachable by its identity only after it has sent at least one
+//! frame to the broker. Frames addressed to the broker identity are
+//! [`Control`] messages.
+//!
+//! The broker checks that the frame `src` is either a name registered by the
+//! sending service or its socket identity, unless that identity is registered
+//! as a name by other service. Frames from services which socket identity
+//! matches a name registered by other service are refused altogether, such
+//! that services can't impersonate each other. Control messages are sent by the
+//! broker with its own identity as the frame destination; since all frames
+//! addressed to the broker are consumed by it, no forwarded frame can be
+//! mistaken for a control message.
+//!
+//! [`ZmqSocketType::RouterBind`]: crate::ZmqSocketType::RouterBind
+//! [`ZmqSocketType::RouterConnect`]: crate::ZmqSocketType::RouterConnect
+//! [`RoutedFrame`]: crate::RoutedFrame
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Cursor;
+
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::presentation::Error;
+use crate::session::{LocalSession, SendRecvMessage};
+use crate::transport::Pollable;
+
+/// Control messages exchanged between the bus broker and its clients
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[display(Debug)]
+pub enum Control {
+    /// Request to register the sending service under a given name
+    Register(Vec<u8>),
+
+    /// Request to remove all names registered by the sending service
+    Unregister,
+
+    /// Confirmation that the service was registered under a given name
+    Registered(Vec<u8>),
+
+    /// Name requested for registration is already taken by other service
+    NameTaken(Vec<u8>),
+
+    /// Frame was not delivered since its destination is not known to the
+    /// broker
+    UnknownDestination(Vec<u8>),
+
+    /// Presence notification that a new service has joined the bus
+    Joined(Vec<u8>),
+
+    /// Presence notification that a service has left the bus
+    Left(Vec<u8>),
+
+    /// Frame was not delivered since its source is neither the socket
+    /// identity of the sending service nor a name registered by it
+    InvalidSource(Vec<u8>),
+
+    /// Control message sent to the broker can't be decoded
+    Malformed,
+
+    /// Frame was refused since the socket identity of the sending service
+    /// is registered as a name by other service
+    IdentityTaken(Vec<u8>),
+}
+
+/// Data received from the bus by a [`Client`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Delivery {
+    /// Message forwarded by the broker from other service
+    Message {
+        /// Name (or socket identity) of the service sent the message
+        src: Vec<u8>,
+        /// Message data
+        msg: Vec<u8>,
+    },
+
+    /// Control message sent by the broker itself
+    Control(Control),
+}
+
+/// Bus broker forwarding frames between services connected to it
+pub struct Broker {
+    session: LocalSession,
+    identity: Vec<u8>,
+    services: BTreeMap<Vec<u8>, Vec<u8>>,
+    peers: BTreeSet<Vec<u8>>,
+    presence: bool,
+}
+
+impl Broker {
+    /// Constructs broker working over ZMQ ROUTER session which socket has
+    /// the given `identity`.
+    pub fn with(session: LocalSession, identity: impl AsRef<[u8]>) -> Self {
+        Broker {
+            session,
+            identity: identity.as_ref().to_vec(),
+            services: empty!(),
+            peers: empty!(),
+            presence: false,
+        }
+    }
+
+    /// Enables or disables [`Control::Joined`] and [`Control::Left`] presence
+    /// notifications, which are sent to all registered services each time
+    /// other service registers or unregisters. Disabled by default.
+    #[inline]
+    pub fn set_presence_notifications(&mut self, enabled: bool) {
+        self.presence = enabled;
+    }
+
+    /// Returns map of registered service names to their socket identities
+    #[inline]
+    pub fn services(&self) -> &BTreeMap<Vec<u8>, Vec<u8>> { &self.services }
+
+    /// Releases the underlying session
+    #[inline]
+    pub fn into_session(self) -> LocalSession { self.session }
+
+    /// Runs broker, processing incoming frames until a transport error
+    /// happens. Invalid frames sent by services are rejected with control
+    /// messages and do not stop the broker.
+    pub fn run(&mut self) -> Result<(), Error> {
+        loop {
+            self.process()?;
+        }
+    }
+
+    /// Receives and processes a single incoming frame, either forwarding it
+    /// to its destination or executing control command
+    pub fn process(&mut self) -> Result<(), Error> {
+        let frame = self.session.recv_routed_message()?;
+        // Peers which socket identity is registered as a name by other
+        // service would be able to impersonate it
+        let identity_taken = matches!(
+            self.services.get(&frame.hop),
+            Some(identity) if identity != &frame.hop
+        );
+        if identity_taken {
+            return self.send_control(
+                &frame.hop,
+                &Control::IdentityTaken(frame.hop.clone()),
+            );
+        }
+        self.peers.insert(frame.hop.clone());
+        if frame.dst == self.identity {
+            return match Control::strict_decode(Cursor::new(frame.msg)) {
+                Ok(control) => self.process_control(frame.hop, control),
+                Err(_) => self.send_control(&frame.hop, &Control::Malformed),
+            };
+        }
+
+        let valid_src = match self.services.get(&frame.src) {
+            Some(identity) => identity == &frame.hop,
+            None => frame.src == frame.hop,
+        };
+        if !valid_src {
+            return self
+                .send_control(&frame.hop, &Control::InvalidSource(frame.src));
+        }
+
+        let route = if let Some(identity) = self.services.get(&frame.dst) {
+            identity.clone()
+        } else if self.peers.contains(&frame.dst) {
+            frame.dst.clone()
+        } else {
+            return self.send_control(
+                &frame.hop,
+                &Control::UnknownDestination(frame.dst),
+            );
+        };
+        self.session
+            .send_routed_message(&frame.src, &route, &frame.dst, &frame.msg)?;
+        Ok(())
+    }
+
+    fn process_control(
+        &mut self,
+        peer: Vec<u8>,
+        control: Control,
+    ) -> Result<(), Error> {
+        match control {
+            // Names must not clash with the broker or other services
+            // identities, otherwise they may be used for impersonation
+            Control::Register(name)
+                if name == self.identity
+                    || (name != peer && self.peers.contains(&name)) =>
+            {
+                self.send_control(&peer, &Control::NameTaken(name))
+            }
+            Control::Register(name) => match self.services.get(&name) {
+                Some(identity) if identity != &peer => {
+                    self.send_control(&peer, &Control::NameTaken(name))
+                }
+                Some(_) => self.send_control(&peer, &Control::Registered(name)),
+                None => {
+                    self.services.insert(name.clone(), peer.clone());
+                    self.send_control(
+                        &peer,
+                        &Control::Registered(name.clone()),
+                    )?;
+                    self.notify(&peer, &Control::Joined(name))
+                }
+            },
+            Control::Unregister => {
+                let names = self
+                    .services
+                    .iter()
+                    .filter(|(_, identity)| *identity == &peer)
+                    .map(|(name, _)| name.clone())
+                    .collect::<Vec<_>>();
+                for name in names {
+                    self.services.remove(&name);
+                    self.notify(&peer, &Control::Left(name))?;
+                }
+                Ok(())
+            }
+            // Other control messages are sent only by the broker, so we
+            // ignore them
+            _ => Ok(()),
+        }
+    }
+
+    fn notify(
+        &mut self,
+        source: &[u8],
+        control: &Control,
+    ) -> Result<(), Error> {
+        if !self.presence {
+            return Ok(());
+        }
+        let peers = self
+            .services
+            .values()
+            .filter(|identity| identity.as_slice() != source)
+            .cloned()
+            .collect::<Vec<_>>();
+        for peer in peers {
+            self.send_control(&peer, control)?;
+        }
+        Ok(())
+    }
+
+    fn send_control(
+        &mut self,
+        peer: &[u8],
+        control: &Control,
+    ) -> Result<(), Error> {
+        let msg = control.strict_serialize()?;
+        // Broker identity as the destination marks control messages
+        self.session.send_routed_message(
+            &self.identity,
+            peer,
+            &self.identity,
+            &msg,
+        )?;
+        Ok(())
+    }
+}
+
+impl Pollable for Broker {
+    #[inline]
+    fn as_poll_item(&self) -> zmq::PollItem<'_> { self.session.as_poll_item() }
+}
+
+/// Bus client used by services to communicate through the [`Broker`]
+pub struct Client {
+    session: LocalSession,
+    identity: Vec<u8>,
+    broker: Vec<u8>,
+    name: Option<Vec<u8>>,
+}
+
+impl Client {
+    /// Constructs bus client working over ZMQ ROUTER session which socket
+    /// has the given `identity` and is connected to the broker with the
+    /// `broker` identity.
+    pub fn with(
+        session: LocalSession,
+        identity: impl AsRef<[u8]>,
+        broker: impl AsRef<[u8]>,
+    ) -> Self {
+        Client {
+            session,
+            identity: identity.as_ref().to_vec(),
+            broker: broker.as_ref().to_vec(),
+            name: None,
+        }
+    }
+
+    /// Returns name under which the service was registered, if any
+    #[inline]
+    pub fn name(&self) -> Option<&[u8]> { self.name.as_deref() }
+
+    /// Releases the underlying session
+    #[inline]
+    pub fn into_session(self) -> LocalSession { self.session }
+
+    /// Requests broker to register service under a given name. Broker replies
+    /// with either [`Control::Registered`] or [`Control::NameTaken`], which
+    /// can be received with [`Client::recv`].
+    pub fn register(&mut self, name: impl AsRef<[u8]>) -> Result<(), Error> {
+        self.send_control(&Control::Register(name.as_ref().to_vec()))
+    }
+
+    /// Requests broker to remove all names registered by the service.
+    pub fn unregister(&mut self) -> Result<(), Error> {
+        self.name = None;
+        self.send_control(&Control::Unregister)
+    }
+
+    /// Sends message to the service registered under `dst` name or having
+    /// `dst` socket identity (see module-level docs for the limitations of
+    /// identity-based routing). The message is sent on behalf of the name
+    /// under which this service is registered, or from its socket identity
+    /// if the service is not registered.
+    pub fn send(
+        &mut self,
+        dst: impl AsRef<[u8]>,
+        msg: &[u8],
+    ) -> Result<usize, Error> {
+        let src = self.name.as_ref().unwrap_or(&self.identity);
+        Ok(self.session.send_routed_message(
+            src,
+            &self.broker,
+            dst.as_ref(),
+            msg,
+        )?)
+    }
+
+    /// Receives next message or control notification from the bus
+    pub fn recv(&mut self) -> Result<Delivery, Error> {
+        let frame = self.session.recv_routed_message()?;
+        if frame.dst != self.broker {
+            return Ok(Delivery::Message {
+                src: frame.src,
+                msg: frame.msg,
+            });
+        }
+        let control = Control::strict_decode(Cursor::new(frame.msg))?;
+        if let Control::Registered(ref name) = control {
+            self.name = Some(name.clone());
+        }
+        Ok(Delivery::Control(control))
+    }
+
+    fn send_control(&mut self, control: &Control) -> Result<(), Error> {
+        let msg = control.strict_serialize()?;
+        self.session.send_routed_message(
+            &self.identity,
+            &self.broker,
+            &self.broker,
+            &msg,
+        )?;
+        Ok(())
+    }
+}
+
+impl Pollable for Client {
+    #[inline]
+    fn as_poll_item(&self) -> zmq::PollItem<'_> { self.session.as_poll_item() }
+}
@@ -27,6 +27,8 @@ extern crate amplify;
 #[macro_use]
 extern crate strict_encoding;
 
+#[cfg(feature = "bitcoin")]
+extern crate bitcoin;
 extern crate chacha20poly1305;
 #[cfg(feature = "url")]
 extern crate url_crate as url;
@@ -42,14 +44,32 @@ pub extern crate inet2_addr as addr;
 #[cfg(feature = "derive")]
 pub use inet2_derive::Api;
 
+/// Re-exports used by the code generated with `#[derive(Api)]`; not a part of
+/// the public API
+#[doc(hidden)]
+pub mod _private {
+    #[cfg(feature = "serde")]
+    pub use serde_crate as serde;
+}
+
+#[cfg(feature = "zmq")]
+pub mod esb;
 pub mod presentation;
+#[cfg(feature = "zmq")]
+pub mod pubsub;
+#[cfg(feature = "zmq")]
+pub mod relay;
+#[cfg(feature = "zmq")]
+pub mod rpc;
 pub mod session;
 pub mod transport;
 
 pub use presentation::{
-    sphinx, tlv, CreateUnmarshaller, Payload, TypeId, TypedEnum,
-    UnknownTypeError, Unmarshall, UnmarshallFn, Unmarshaller,
+    sphinx, tlv, CreateUnmarshaller, Dispatch, ExpectedReply, Payload, TypeId,
+    TypedEnum, UnknownTypeError, Unmarshall, UnmarshallFn, Unmarshaller,
 };
+#[cfg(feature = "zmq")]
+pub use session::SessionPoller;
 pub use session::{
     noise, Decrypt, Encrypt, NoiseDecryptor, NoiseEncryptor, NoiseTranscoder,
     PlainTranscoder, SendRecvMessage, Session, Split, Transcode,
@@ -58,7 +78,7 @@ pub use session::{
 pub use transport::zeromq;
 pub use transport::{DuplexConnection, RoutedFrame};
 #[cfg(feature = "zmq")]
-pub use transport::{ZmqConnectionType, ZmqSocketType};
+pub use transport::{Pollable, ZmqConnectionType, ZmqOptions, ZmqSocketType};
 
 /// Maximum message (packet payload) length for Brontide protocol
 pub const BRONTIDE_MSG_MAX_LEN: usize = u16::MAX as usize;
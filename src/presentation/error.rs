@@ -35,6 +35,16 @@ pub enum Error {
     /// unknown LNP protocol version
     UnknownProtocolVersion,
 
+    /// LNP message with type #{0} is not supported by the negotiated protocol
+    /// version
+    UnsupportedMessageVersion(TypeId),
+
+    /// LNP message with type #{0} is deprecated
+    DeprecatedMessage(TypeId),
+
+    /// payload of LNP message with type #{0} exceeds size limit of {1} bytes
+    PayloadTooLarge(TypeId, usize),
+
     /// Error in lightning-encoded data from LNP message
     #[display(inner)]
     #[from]
@@ -45,6 +55,9 @@ pub enum Error {
     #[from]
     StrictEncoding(strict_encoding::Error),
 
+    /// Error in bitcoin consensus-encoded data from LNP message: {0}
+    BitcoinEncoding(String),
+
     /// unknown data type in LNP message
     #[from(UnknownTypeError)]
     UnknownDataType,
@@ -84,8 +97,12 @@ impl From<Error> for u8 {
             Error::NoData => 0x10,
             Error::NoEncoder => 0x11,
             Error::UnknownProtocolVersion => 0x12,
+            Error::UnsupportedMessageVersion(_) => 0x13,
+            Error::DeprecatedMessage(_) => 0x14,
+            Error::PayloadTooLarge(..) => 0x15,
             Error::LightningEncoding(_) => 0x20,
             Error::StrictEncoding(_) => 0x21,
+            Error::BitcoinEncoding(_) => 0x22,
             Error::UnknownDataType => 0x23,
             Error::InvalidValue => 0x24,
             Error::MessageEvenType(_) => 0x30,
@@ -99,6 +116,16 @@ impl From<Error> for u8 {
     }
 }
 
+#[cfg(feature = "bitcoin")]
+impl From<bitcoin::consensus::encode::Error> for Error {
+    fn from(err: bitcoin::consensus::encode::Error) -> Self {
+        match err {
+            bitcoin::consensus::encode::Error::Io(err) => Error::Io(err.into()),
+            err => Error::BitcoinEncoding(err.to_string()),
+        }
+    }
+}
+
 /// Error representing unknown LNP message type
 #[derive(
     Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Error
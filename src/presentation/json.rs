@@ -0,0 +1,119 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! JSON representation of API messages, used for debugging and for bridging
+//! API messages to JSON-based services.
+//!
+//! API enums deriving `Api` with `serde` argument (i.e.
+//! `#[api(encoding = "strict", serde)]`) are serialized as
+//! `{ "type": "add_keys", "payload": ..., "type_id": 3 }`, where `type` is the
+//! snake-case name of the enum variant and `payload` is the variant data: the
+//! field value for single-field variants, an array for variants with
+//! multiple unnamed fields, an object for variants with named fields and
+//! nothing for unit variants. `type_id` is optional during deserialization;
+//! if present, it must match the message type. [`JsonTranscoder`] converts
+//! messages between their JSON and wire representations.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{CreateUnmarshaller, TypedEnum, Unmarshaller};
+
+/// Errors of transcoding API messages between JSON and wire representations
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum Error {
+    /// {0}
+    #[from]
+    Presentation(super::Error),
+
+    /// invalid JSON representation of API message: {0}
+    Json(String),
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self { Error::Json(err.to_string()) }
+}
+
+/// Transcoder converting API messages of type `T` between their wire and
+/// JSON representations
+pub struct JsonTranscoder<T>
+where
+    T: TypedEnum,
+{
+    unmarshaller: Unmarshaller<T>,
+}
+
+impl<T> Default for JsonTranscoder<T>
+where
+    T: TypedEnum + CreateUnmarshaller,
+{
+    fn default() -> Self {
+        JsonTranscoder {
+            unmarshaller: T::create_unmarshaller(),
+        }
+    }
+}
+
+impl<T> JsonTranscoder<T>
+where
+    T: TypedEnum + CreateUnmarshaller,
+{
+    /// Constructs transcoder using default unmarshaller for `T`
+    #[inline]
+    pub fn new() -> Self { Self::default() }
+}
+
+impl<T> JsonTranscoder<T>
+where
+    T: TypedEnum + Serialize + DeserializeOwned,
+{
+    /// Constructs transcoder using the provided unmarshaller, which may be
+    /// configured with protocol version or payload size limits
+    #[inline]
+    pub fn with(unmarshaller: Unmarshaller<T>) -> Self {
+        JsonTranscoder { unmarshaller }
+    }
+
+    /// Parses wire-encoded message and returns its JSON representation
+    pub fn wire_to_json(&self, data: &[u8]) -> Result<String, Error> {
+        let message = self.unmarshaller.unmarshall_frame(data)?;
+        Ok(serde_json::to_string(&*message)?)
+    }
+
+    /// Parses wire-encoded message and returns its JSON representation as a
+    /// [`serde_json::Value`]
+    pub fn wire_to_value(
+        &self,
+        data: &[u8],
+    ) -> Result<serde_json::Value, Error> {
+        let message = self.unmarshaller.unmarshall_frame(data)?;
+        Ok(serde_json::to_value(&*message)?)
+    }
+
+    /// Parses JSON representation of a message and returns its wire encoding
+    pub fn json_to_wire(&self, json: &str) -> Result<Vec<u8>, Error> {
+        let message: T = serde_json::from_str(json)?;
+        Ok(TypedEnum::serialize(&message))
+    }
+
+    /// Parses JSON representation of a message given as
+    /// [`serde_json::Value`] and returns its wire encoding
+    pub fn value_to_wire(
+        &self,
+        value: serde_json::Value,
+    ) -> Result<Vec<u8>, Error> {
+        let message: T = serde_json::from_value(value)?;
+        Ok(TypedEnum::serialize(&message))
+    }
+}
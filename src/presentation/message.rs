@@ -31,6 +31,15 @@ use super::{tlv, EvenOdd, UnknownTypeError};
 #[wrapper(LowerHex, UpperHex, Octal, FromStr)]
 pub struct TypeId(u16);
 
+impl TypeId {
+    /// Constructs type id from its numeric value; unlike
+    /// [`Wrapper::from_inner`] can be used in constant expressions.
+    ///
+    /// [`Wrapper::from_inner`]: amplify::Wrapper::from_inner
+    #[inline]
+    pub const fn with(type_id: u16) -> TypeId { TypeId(type_id) }
+}
+
 impl strict_encoding::Strategy for TypeId {
     type Strategy = strict_encoding::strategies::Wrapped;
 }
@@ -54,9 +63,30 @@ impl LightningDecode for TypeId {
     }
 }
 
+#[cfg(feature = "bitcoin")]
+impl bitcoin::consensus::Encodable for TypeId {
+    #[inline]
+    fn consensus_encode<W: io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, io::Error> {
+        self.0.consensus_encode(writer)
+    }
+}
+
+#[cfg(feature = "bitcoin")]
+impl bitcoin::consensus::Decodable for TypeId {
+    #[inline]
+    fn consensus_decode<R: io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        u16::consensus_decode(reader).map(Self)
+    }
+}
+
 impl EvenOdd for TypeId {}
 
-#[derive(Clone, Debug, Display)]
+#[derive(Clone, Debug, Display, From)]
 #[display(Debug)]
 pub struct Source(Vec<Arc<dyn Any>>);
 
@@ -119,6 +149,24 @@ impl LightningEncode for Payload {
     }
 }
 
+/// Protocol version metadata of a message
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct MessageVersion {
+    /// Protocol version starting from which the message is supported
+    pub since: u16,
+    /// Whether the message is deprecated and should not be used by new
+    /// protocol implementations
+    pub deprecated: bool,
+}
+
+impl MessageVersion {
+    /// Checks whether the message is supported by a given protocol version
+    #[inline]
+    pub fn is_supported_by(&self, version: u16) -> bool {
+        version >= self.since
+    }
+}
+
 pub trait TypedEnum
 where
     Self: Sized + Clone,
@@ -130,6 +178,70 @@ where
     fn get_type(&self) -> TypeId;
     fn get_payload(&self) -> Vec<u8>;
     fn serialize(&self) -> Vec<u8>;
+
+    /// Returns protocol version metadata for the message. Messages without
+    /// version metadata are supported by all protocol versions.
+    fn get_version(&self) -> MessageVersion { MessageVersion::default() }
+}
+
+/// Typed message enums declaring which messages are expected in reply to
+/// each of their variants. Derived by `#[derive(Api)]` from `reply` and `error`
+/// arguments of the variant `#[api(...)]` attributes for enums having at least
+/// one of these arguments.
+pub trait ExpectedReply: TypedEnum {
+    /// Returns type ids of the messages which are valid replies (including
+    /// failure replies) to this message. Empty list means that the reply type
+    /// is not restricted.
+    fn expected_reply_types(&self) -> &[TypeId];
+
+    /// Checks whether a message with `type_id` is a valid reply to this
+    /// message
+    fn is_expected_reply(&self, type_id: TypeId) -> bool {
+        let types = self.expected_reply_types();
+        types.is_empty() || types.contains(&type_id)
+    }
+}
+
+/// Replies which message handlers generated by `#[derive(Api)]` return for
+/// the messages they do not implement
+pub trait UnsupportedReply {
+    /// Constructs reply to a message with `type_id` which is not supported by
+    /// the handler
+    fn unsupported(type_id: TypeId) -> Self;
+}
+
+impl UnsupportedReply for () {
+    #[inline]
+    fn unsupported(_: TypeId) -> Self {}
+}
+
+impl<T> UnsupportedReply for Option<T> {
+    #[inline]
+    fn unsupported(_: TypeId) -> Self { None }
+}
+
+impl<T, E> UnsupportedReply for Result<T, E>
+where
+    E: UnsupportedReply,
+{
+    #[inline]
+    fn unsupported(type_id: TypeId) -> Self { Err(E::unsupported(type_id)) }
+}
+
+/// Typed message enums which can pass their messages to a handler `H`,
+/// calling a handler method specific for the message type. Derived by
+/// `#[derive(Api)]` for the handler trait named by `handler` argument of the
+/// enum `#[api(...)]` attribute.
+pub trait Dispatch<H>: TypedEnum
+where
+    H: ?Sized,
+{
+    /// Reply returned by the handler
+    type Reply;
+
+    /// Calls handler method corresponding to the message type, returning its
+    /// reply
+    fn dispatch(&self, handler: &mut H) -> Self::Reply;
 }
 
 impl<T> From<T> for Payload
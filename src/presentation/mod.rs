@@ -12,7 +12,10 @@
 // If not, see <https://opensource.org/licenses/MIT>.
 
 mod error;
+#[cfg(feature = "serde")]
+pub mod json;
 pub mod message;
+pub mod schema;
 pub mod sphinx;
 pub mod tlv;
 mod unmarshall;
@@ -21,7 +24,13 @@ use std::ops::Rem;
 
 use amplify::Wrapper;
 pub use error::{Error, UnknownTypeError};
-pub use message::{Payload, TypeId, TypedEnum};
+pub use message::{
+    Dispatch, ExpectedReply, Extract, MessageVersion, Payload, TypeId,
+    TypedEnum, UnsupportedReply,
+};
+pub use schema::{ApiSchema, DescribeApi, FieldSchema, MessageSchema};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 pub use unmarshall::{
     CreateUnmarshaller, Unmarshall, UnmarshallFn, Unmarshaller,
 };
@@ -40,10 +49,20 @@ where
 }
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "snake_case")
+)]
 pub enum EncodingType {
     #[display("lightning-encoding")]
     Lightning,
 
     #[display("strict-encoding")]
     Strict,
+
+    /// Bitcoin consensus encoding, using little-endian type ids. Decoding
+    /// requires compilation with `bitcoin` feature.
+    #[display("bitcoin-consensus")]
+    Bitcoin,
 }
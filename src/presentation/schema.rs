@@ -0,0 +1,119 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Machine-readable description of the API messages, generated by
+//! `#[derive(Api)]` as [`DescribeApi`] implementation for enums having
+//! `schema` argument in their `#[api(...)]` attribute. With `serde` feature the
+//! schema can be serialized (for instance into JSON) for diffing protocol
+//! versions or generating bindings in other languages; protocol crates may
+//! dump schemas of all their API enums with [`write_json`] from a small
+//! companion binary (see `examples/api_schema.rs`).
+
+#[cfg(feature = "serde")]
+use std::io;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::EncodingType;
+
+/// API enums providing machine-readable schema of their messages. Implemented
+/// by `#[derive(Api)]` for enums with `#[api(schema)]` argument.
+pub trait DescribeApi {
+    /// Returns machine-readable schema of the API messages
+    fn api_schema() -> ApiSchema;
+}
+
+/// Writes pretty-printed JSON array with `schemas` of API enums into the
+/// `writer`.
+///
+/// Requires compilation with `serde` feature.
+#[cfg(feature = "serde")]
+pub fn write_json(
+    schemas: &[ApiSchema],
+    mut writer: impl io::Write,
+) -> Result<(), serde_json::Error> {
+    serde_json::to_writer_pretty(&mut writer, schemas)?;
+    writeln!(writer).map_err(serde_json::Error::io)
+}
+
+/// Schema of an API represented by a [`super::TypedEnum`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct ApiSchema {
+    /// Name of the API enum
+    pub name: String,
+    /// Encoding used for all API messages
+    pub encoding: EncodingType,
+    /// API messages, in order of the enum variants
+    pub messages: Vec<MessageSchema>,
+}
+
+impl ApiSchema {
+    /// Returns schema of the API enum `T`
+    #[inline]
+    pub fn of<T: DescribeApi>() -> ApiSchema { T::api_schema() }
+
+    /// Returns schema of a message with a given type id
+    pub fn message(&self, type_id: u16) -> Option<&MessageSchema> {
+        self.messages.iter().find(|msg| msg.type_id == type_id)
+    }
+}
+
+/// Schema of a single API message
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct MessageSchema {
+    /// Name of the enum variant representing the message
+    pub name: String,
+    /// Message type id
+    pub type_id: u16,
+    /// Payload fields, in order of their encoding
+    pub fields: Vec<FieldSchema>,
+    /// Whether the payload is followed by TLV extension stream, which is
+    /// represented by the last of the payload fields
+    pub tlv: bool,
+    /// Type ids of the messages expected as successful replies
+    pub replies: Vec<u16>,
+    /// Type ids of the messages expected as failure replies
+    pub errors: Vec<u16>,
+    /// Protocol version starting from which the message is supported
+    pub since: u16,
+    /// Whether the message is deprecated
+    pub deprecated: bool,
+    /// Maximum size of the message payload, if limited
+    pub max_len: Option<usize>,
+}
+
+/// Schema of a message payload field
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct FieldSchema {
+    /// Field name, if the variant has named fields
+    pub name: Option<String>,
+    /// Rust type of the field, as written in the enum definition
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    pub type_name: String,
+}
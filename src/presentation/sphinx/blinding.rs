@@ -0,0 +1,426 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Route blinding as described in BOLT-4 "Route Blinding" section.
+//!
+//! The recipient constructs [`BlindedPath`] from the real node ids of the path
+//! nodes and the data it wants each of them to receive. The path exposes only
+//! the introduction node id; the rest of the nodes are represented by their
+//! blinded ids, which are used by the sender as onion hop node ids. Each of
+//! the path nodes uses [`BlindedNode`] constructed from the received path key
+//! to unfold the onion packet addressed to its blinded id, decrypt data left
+//! for it by the recipient and derive the path key for the next node.
+
+use addr::NodeId;
+use bitcoin_hashes::{sha256, Hash};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use lightning_encoding::{LightningDecode, LightningEncode};
+use secp256k1::ecdh::SharedSecret;
+use secp256k1::{PublicKey, Secp256k1, SecretKey, Signing, Verification};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use super::{
+    blinding_factor, generate_key, OnionPacket, SphinxPayload, RHO_KEY,
+};
+
+const BLINDED_NODE_ID_KEY: &[u8] = b"blinded_node_id";
+
+/// Errors in processing blinded routes
+#[derive(
+    Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Error
+)]
+#[display(doc_comments)]
+pub enum BlindingError {
+    /// blinded path must contain at least one node
+    EmptyPath,
+
+    /// encrypted recipient data can't be decrypted with the provided path key
+    Decryption,
+}
+
+/// Node of the blinded path, as seen by the sender
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(LightningEncode, LightningDecode)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct BlindedHop {
+    /// Blinded node id, which should be used as a node id of the onion hop
+    pub blinded_node_id: NodeId,
+
+    /// Recipient data encrypted for the node, which must be provided to the
+    /// node inside its onion hop payload
+    pub encrypted_data: Vec<u8>,
+}
+
+/// Blinded path constructed by the recipient
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(LightningEncode, LightningDecode)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct BlindedPath {
+    /// Real id of the first node of the path, to which the sender must build
+    /// a route
+    pub introduction_node: NodeId,
+
+    /// Path key (blinding point) for the introduction node
+    pub path_key: PublicKey,
+
+    /// Blinded path nodes, starting with the introduction node
+    pub hops: Vec<BlindedHop>,
+}
+
+impl BlindedPath {
+    /// Constructs blinded path from the real node ids and the per-node
+    /// recipient data, using `blinding_seed` as the first path key secret.
+    ///
+    /// NB: The blinding seed must not be re-used between different paths.
+    pub fn with<C>(
+        secp: &Secp256k1<C>,
+        blinding_seed: SecretKey,
+        path: impl IntoIterator<Item = (NodeId, Vec<u8>)>,
+    ) -> Result<BlindedPath, BlindingError>
+    where
+        C: Signing + Verification,
+    {
+        let path_key = PublicKey::from_secret_key(secp, &blinding_seed);
+        let mut ephemeral_key = blinding_seed;
+        let mut ephemeral_pk = path_key;
+        let mut introduction_node = None;
+        let mut hops = vec![];
+
+        for (node_id, data) in path {
+            introduction_node.get_or_insert(node_id);
+
+            let shared_secret = ecdh(&node_id.public_key(), &ephemeral_key);
+            let node_id_tweak = blinded_node_id_tweak(shared_secret);
+            let blinded_node_id = node_id
+                .public_key()
+                .mul_tweak(secp, &node_id_tweak)
+                .expect("negligible probability of exceeding group size");
+            hops.push(BlindedHop {
+                blinded_node_id: blinded_node_id.into(),
+                encrypted_data: encrypt(shared_secret, &data),
+            });
+
+            let tweak = blinding_factor(ephemeral_pk, shared_secret);
+            ephemeral_key = ephemeral_key
+                .mul_tweak(&tweak)
+                .expect("negligible probability of exceeding group size");
+            ephemeral_pk = PublicKey::from_secret_key(secp, &ephemeral_key);
+        }
+
+        Ok(BlindedPath {
+            introduction_node: introduction_node
+                .ok_or(BlindingError::EmptyPath)?,
+            path_key,
+            hops,
+        })
+    }
+
+    /// Returns blinded node ids of the path, which can be used as onion
+    /// route node ids
+    pub fn blinded_node_ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.hops.iter().map(|hop| hop.blinded_node_id)
+    }
+}
+
+/// Node of the blinded path, as seen by the node itself upon receiving path
+/// key from the previous node (or from the sender for the introduction node).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BlindedNode {
+    node_secret: SecretKey,
+    path_key: PublicKey,
+    shared_secret: sha256::Hash,
+}
+
+impl BlindedNode {
+    /// Constructs blinded node from the node private key and the path key
+    /// received for the node
+    pub fn with(node_secret: SecretKey, path_key: PublicKey) -> BlindedNode {
+        BlindedNode {
+            node_secret,
+            path_key,
+            shared_secret: ecdh(&path_key, &node_secret),
+        }
+    }
+
+    /// Returns path key which the node has received
+    #[inline]
+    pub fn path_key(&self) -> PublicKey { self.path_key }
+
+    /// Returns private key corresponding to the blinded node id. This key
+    /// must be used to unfold onion packets addressed to the blinded node
+    /// id (see [`BlindedNode::unfold`]).
+    pub fn blinded_secret(&self) -> SecretKey {
+        self.node_secret
+            .mul_tweak(&blinded_node_id_tweak(self.shared_secret))
+            .expect("negligible probability of exceeding group size")
+    }
+
+    /// Returns blinded node id under which the node is known to the sender
+    pub fn blinded_node_id<C>(&self, secp: &Secp256k1<C>) -> NodeId
+    where
+        C: Signing,
+    {
+        PublicKey::from_secret_key(secp, &self.blinded_secret()).into()
+    }
+
+    /// Decrypts data left for the node by the recipient
+    pub fn decrypt(
+        &self,
+        encrypted_data: &[u8],
+    ) -> Result<Vec<u8>, BlindingError> {
+        let rho_key = generate_key(RHO_KEY, self.shared_secret);
+        ChaCha20Poly1305::new(Key::from_slice(&rho_key))
+            .decrypt(Nonce::from_slice(&[0u8; 12]), encrypted_data)
+            .map_err(|_| BlindingError::Decryption)
+    }
+
+    /// Derives path key which must be passed to the next node of the blinded
+    /// path
+    pub fn next_path_key<C>(&self, secp: &Secp256k1<C>) -> PublicKey
+    where
+        C: Verification,
+    {
+        let tweak = blinding_factor(self.path_key, self.shared_secret);
+        self.path_key
+            .mul_tweak(secp, &tweak)
+            .expect("negligible probability of exceeding group size")
+    }
+
+    /// Checks HMAC of the onion packet addressed to the blinded node id with
+    /// optional associated data.
+    pub fn check_hmac<const PACKET_LEN: usize>(
+        &self,
+        onion: &OnionPacket<PACKET_LEN>,
+        assoc_data: &[u8],
+    ) -> bool {
+        onion.check_hmac(self.blinded_secret(), assoc_data)
+    }
+
+    /// Unfolds one layer of the onion packet addressed to the blinded node
+    /// id. See [`OnionPacket::unfold`] for the details.
+    pub fn unfold<Payload, const PACKET_LEN: usize>(
+        &self,
+        onion: &mut OnionPacket<PACKET_LEN>,
+    ) -> Result<Payload, Payload::DecodeError>
+    where
+        Payload: SphinxPayload,
+    {
+        onion.unfold(self.blinded_secret())
+    }
+}
+
+fn ecdh(point: &PublicKey, secret: &SecretKey) -> sha256::Hash {
+    let shared_secret = SharedSecret::new(point, secret);
+    sha256::Hash::from_slice(shared_secret.as_ref())
+        .expect("ECDH result is not a 32-byte hash")
+}
+
+fn blinded_node_id_tweak(shared_secret: sha256::Hash) -> secp256k1::Scalar {
+    let tweak = generate_key(BLINDED_NODE_ID_KEY, shared_secret);
+    secp256k1::Scalar::from_be_bytes(tweak).expect("negligible probability")
+}
+
+fn encrypt(shared_secret: sha256::Hash, data: &[u8]) -> Vec<u8> {
+    let rho_key = generate_key(RHO_KEY, shared_secret);
+    ChaCha20Poly1305::new(Key::from_slice(&rho_key))
+        .encrypt(Nonce::from_slice(&[0u8; 12]), data)
+        .expect("ChaCha20Poly1305 encryption of in-memory data")
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use amplify::hex::FromHex;
+
+    use super::super::Hop;
+    use super::*;
+
+    const PACKET_LEN: usize = 20 * 65;
+
+    fn node_key(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    #[test]
+    fn blinded_path() {
+        let secp = Secp256k1::new();
+        let nodes = [node_key(1), node_key(2), node_key(3)];
+        let path = nodes.iter().enumerate().map(|(index, key)| {
+            let node_id = PublicKey::from_secret_key(&secp, key).into();
+            (node_id, vec![index as u8; 8 + index])
+        });
+        let blinded =
+            BlindedPath::with(&secp, node_key(0x41), path.clone()).unwrap();
+
+        assert_eq!(blinded.hops.len(), 3);
+        assert_eq!(
+            blinded.introduction_node,
+            PublicKey::from_secret_key(&secp, &nodes[0]).into()
+        );
+
+        let mut path_key = blinded.path_key;
+        for ((node_secret, hop), (node_id, data)) in
+            nodes.iter().zip(&blinded.hops).zip(path)
+        {
+            assert_ne!(hop.blinded_node_id, node_id);
+            let node = BlindedNode::with(*node_secret, path_key);
+            assert_eq!(node.blinded_node_id(&secp), hop.blinded_node_id);
+            assert_eq!(node.decrypt(&hop.encrypted_data), Ok(data));
+            path_key = node.next_path_key(&secp);
+        }
+    }
+
+    /// Test vectors from BOLT-4 `route-blinding-test.json`: the route is a
+    /// concatenation of Bob -> Carol path (with `0x02..02` session key) and
+    /// Dave -> Eve path (with `0x01..01` session key).
+    #[test]
+    fn bolt4_vectors() {
+        struct Vector {
+            node_key: u8,
+            node_id: &'static str,
+            path_key: &'static str,
+            blinded_node_id: &'static str,
+        }
+        let bob_tlvs = Vec::<u8>::from_hex(
+            "011a0000000000000000000000000000000000000000000000000000020800000000000006c10a0800240000009627100c06000b69e505dc0e00fd023103123456",
+        )
+        .unwrap();
+        let bob_encrypted_data = Vec::<u8>::from_hex(
+            "cd4100ff9c09ed28102b210ac73aa12d63e90852cebc496c49f57c49982088b49f2e70b99287fdee0aa58aa39913ab405813b999f66783aa2fe637b3cda91ffc0913c30324e2c6ce327e045183e4bffecb",
+        )
+        .unwrap();
+        let paths = [(0x02, [
+            Vector {
+                node_key: 0x42,
+                node_id: "0324653eac434488002cc06bbfb7f10fe18991e35f9fe4302dbea6d2353dc0ab1c",
+                path_key: "024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766",
+                blinded_node_id: "03da173ad2aee2f701f17e59fbd16cb708906d69838a5f088e8123fb36e89a2c25",
+            },
+            Vector {
+                node_key: 0x43,
+                node_id: "027f31ebc5462c1fdce1b737ecff52d37d75dea43ce11c74d25aa297165faa2007",
+                path_key: "034e09f450a80c3d252b258aba0a61215bf60dda3b0dc78ffb0736ea1259dfd8a0",
+                blinded_node_id: "02e466727716f044290abf91a14a6d90e87487da160c2a3cbd0d465d7a78eb83a7",
+            },
+        ]), (0x01, [
+            Vector {
+                node_key: 0x44,
+                node_id: "032c0b7cf95324a07d05398b240174dc0c2be444d96b159aa6c7f7b1e668680991",
+                path_key: "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+                blinded_node_id: "036861b366f284f0a11738ffbf7eda46241a8977592878fe3175ae1d1e4754eccf",
+            },
+            Vector {
+                node_key: 0x45,
+                node_id: "02edabbd16b41c8371b92ef2f04c1185b4f03b6dcd52ba9b78d9d7c89c8f221145",
+                path_key: "03e09038ee76e50f444b19abf0a555e8697e035f62937168b80adf0931b31ce52a",
+                blinded_node_id: "021982a48086cb8984427d3727fe35a03d396b234f0701f5249daa12e8105c8dae",
+            },
+        ])];
+
+        let secp = Secp256k1::new();
+        for (session_key, vectors) in paths {
+            let path = vectors.iter().map(|vector| {
+                let node_id = PublicKey::from_str(vector.node_id).unwrap();
+                assert_eq!(
+                    PublicKey::from_secret_key(
+                        &secp,
+                        &node_key(vector.node_key)
+                    ),
+                    node_id
+                );
+                let data = match vector.node_key {
+                    0x42 => bob_tlvs.clone(),
+                    _ => vec![],
+                };
+                (node_id.into(), data)
+            });
+            let blinded =
+                BlindedPath::with(&secp, node_key(session_key), path).unwrap();
+
+            let mut path_key = blinded.path_key;
+            for (vector, hop) in vectors.iter().zip(&blinded.hops) {
+                assert_eq!(
+                    path_key,
+                    PublicKey::from_str(vector.path_key).unwrap()
+                );
+                let blinded_node_id =
+                    PublicKey::from_str(vector.blinded_node_id).unwrap().into();
+                assert_eq!(hop.blinded_node_id, blinded_node_id);
+
+                let node =
+                    BlindedNode::with(node_key(vector.node_key), path_key);
+                assert_eq!(node.blinded_node_id(&secp), blinded_node_id);
+                if vector.node_key == 0x42 {
+                    assert_eq!(hop.encrypted_data, bob_encrypted_data);
+                    assert_eq!(
+                        node.decrypt(&hop.encrypted_data),
+                        Ok(bob_tlvs.clone())
+                    );
+                }
+                path_key = node.next_path_key(&secp);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_path() {
+        let secp = Secp256k1::new();
+        assert_eq!(
+            BlindedPath::with(&secp, node_key(0x41), vec![]),
+            Err(BlindingError::EmptyPath)
+        );
+
+        let node_id = PublicKey::from_secret_key(&secp, &node_key(1)).into();
+        let blinded =
+            BlindedPath::with(&secp, node_key(0x41), vec![(node_id, vec![1])])
+                .unwrap();
+        let node = BlindedNode::with(node_key(2), blinded.path_key);
+        assert_eq!(
+            node.decrypt(&blinded.hops[0].encrypted_data),
+            Err(BlindingError::Decryption)
+        );
+    }
+
+    #[test]
+    fn blinded_onion() {
+        let secp = Secp256k1::new();
+        let node_id = PublicKey::from_secret_key(&secp, &node_key(1)).into();
+        let blinded =
+            BlindedPath::with(&secp, node_key(0x41), vec![(node_id, vec![])])
+                .unwrap();
+
+        let payload = Vec::<u8>::from_hex(
+            "00000067000001000100000000000003e90000007b000000000000000000000000000000000000000000000000",
+        )
+        .unwrap();
+        let hops = blinded
+            .blinded_node_ids()
+            .map(|node_id| Hop::with(node_id, payload.clone()))
+            .collect::<Vec<_>>();
+        let mut onion = OnionPacket::<PACKET_LEN>::with_session_key(
+            &secp,
+            node_key(0x42),
+            &hops,
+            &[],
+        )
+        .unwrap();
+
+        let node = BlindedNode::with(node_key(1), blinded.path_key);
+        assert!(!onion.check_hmac(node_key(1), &[]));
+        assert!(node.check_hmac(&onion, &[]));
+        let unfolded: Vec<u8> = node.unfold(&mut onion).unwrap();
+        assert_eq!(unfolded, payload);
+    }
+}
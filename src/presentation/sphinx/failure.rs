@@ -0,0 +1,252 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Onion failure messages returned from the erring node back to the origin of
+//! the onion packet, as described in BOLT-4 "Returning Errors" section.
+//!
+//! The erring node creates [`FailurePacket`] with the shared secret of the
+//! onion it was unable to process; each of the preceding hops obfuscates the
+//! packet once more with [`FailurePacket::wrap`] when returning it back. The
+//! origin node, knowing shared secrets for all route hops, unwraps the packet
+//! with [`FailurePacket::attribute`], finding out which hop has failed.
+
+use bitcoin_hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
+use lightning_encoding::{LightningDecode, LightningEncode};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use super::{apply_cipher_stream, generate_key, hmac_eq, UM_KEY};
+
+const AMMAG_KEY: &[u8] = &[0x61, 0x6d, 0x6d, 0x61, 0x67];
+
+/// Minimal length of the failure message with its padding; BOLT-4 requires
+/// erring node to pad failure messages up to this length, such that the
+/// failure message length is not leaked to the intermediate nodes.
+pub const FAILURE_MSG_LEN: usize = 256;
+
+/// Errors processing onion failure packets
+#[derive(
+    Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Error
+)]
+#[display(doc_comments)]
+pub enum FailureError {
+    /// failure message of {0} bytes does not fit into a failure packet
+    MessageTooLarge(usize),
+
+    /// failure packet HMAC does not match any of the route hops, so the
+    /// failure can't be attributed
+    Unattributable,
+
+    /// failure packet from hop #{0} has invalid structure
+    InvalidStructure(usize),
+}
+
+/// Failure message attributed to a specific hop of the onion route
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct AttributedFailure {
+    /// Index of the failed hop in the route
+    pub hop_index: usize,
+    /// Failure message provided by the failed hop
+    pub message: Vec<u8>,
+}
+
+/// Obfuscated failure packet returned along the onion route.
+///
+/// Unobfuscated packet consists of 32-byte HMAC, followed by a 16-bit
+/// failure message length, the message itself, 16-bit padding length and
+/// zero-filled padding. HMAC commits to the rest of the packet with the `um`
+/// key derived from the shared secret of the erring node.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(LightningEncode, LightningDecode)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct FailurePacket(Vec<u8>);
+
+impl AsRef<[u8]> for FailurePacket {
+    #[inline]
+    fn as_ref(&self) -> &[u8] { &self.0 }
+}
+
+impl FailurePacket {
+    /// Constructs failure packet at the erring node for the onion with a given
+    /// `shared_secret` (see [`super::OnionPacket::shared_secret`]). The
+    /// message is padded to [`FAILURE_MSG_LEN`] bytes; the returned packet is
+    /// already obfuscated and can be sent to the previous hop.
+    pub fn with(
+        shared_secret: sha256::Hash,
+        message: &[u8],
+    ) -> Result<FailurePacket, FailureError> {
+        if message.len() > u16::MAX as usize - 4 {
+            return Err(FailureError::MessageTooLarge(message.len()));
+        }
+        let pad_len = FAILURE_MSG_LEN.saturating_sub(message.len());
+        let mut data = Vec::with_capacity(32 + 4 + message.len() + pad_len);
+        data.extend((message.len() as u16).to_be_bytes());
+        data.extend(message);
+        data.extend((pad_len as u16).to_be_bytes());
+        data.resize(data.len() + pad_len, 0);
+
+        let um_key = generate_key(UM_KEY, shared_secret);
+        let mut packet = failure_hmac(um_key, &data).into_inner().to_vec();
+        packet.extend(data);
+
+        let mut packet = FailurePacket(packet);
+        packet.wrap(shared_secret);
+        Ok(packet)
+    }
+
+    /// Obfuscates failure packet with the `ammag` key derived from the shared
+    /// secret of the onion forwarded by the node. Must be called by each
+    /// intermediate node returning the failure packet to the previous hop.
+    pub fn wrap(&mut self, shared_secret: sha256::Hash) {
+        let ammag_key = generate_key(AMMAG_KEY, shared_secret);
+        apply_cipher_stream(ammag_key, &mut self.0);
+    }
+
+    /// Unwraps failure packet at the origin node, finding out the hop which
+    /// has created it. `shared_secrets` must be provided for all route hops,
+    /// in the order of the route (see [`super::route_shared_secrets`]).
+    pub fn attribute(
+        mut self,
+        shared_secrets: &[sha256::Hash],
+    ) -> Result<AttributedFailure, FailureError> {
+        if self.0.len() < 32 + 4 {
+            return Err(FailureError::Unattributable);
+        }
+        for (hop_index, shared_secret) in shared_secrets.iter().enumerate() {
+            self.wrap(*shared_secret);
+            let um_key = generate_key(UM_KEY, shared_secret);
+            let (hmac, data) = self.0.split_at(32);
+            let hmac =
+                Hmac::<sha256::Hash>::from_slice(hmac).expect("32-byte slice");
+            if !hmac_eq(&failure_hmac(um_key, data), &hmac) {
+                continue;
+            }
+            let message_len = u16::from_be_bytes([data[0], data[1]]) as usize;
+            let message = data
+                .get(2..2 + message_len)
+                .ok_or(FailureError::InvalidStructure(hop_index))?;
+            return Ok(AttributedFailure {
+                hop_index,
+                message: message.to_vec(),
+            });
+        }
+        Err(FailureError::Unattributable)
+    }
+}
+
+fn failure_hmac(um_key: [u8; 32], data: &[u8]) -> Hmac<sha256::Hash> {
+    let mut engine = HmacEngine::<sha256::Hash>::new(&um_key);
+    engine.input(data);
+    Hmac::from_engine(engine)
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::hex::{FromHex, ToHex};
+    use secp256k1::{Secp256k1, SecretKey};
+
+    use super::super::route_shared_secrets;
+    use super::*;
+
+    // Test vectors are taken from BOLT-4 "Returning errors" section: a
+    // five-hop route where the final node fails with `temporary_node_failure`
+    // (0x2002) message.
+
+    const FINAL_PACKET: &str = "9c5add3963fc7f6ed7f148623c84134b5647e1306419dbe2174e523fa9e2fbed3a06a19f899145610741c83ad40b7712aefaddec8c6baf7325d92ea4ca4d1df8bce517f7e54554608bf2bd8071a4f52a7a2f7ffbb1413edad81eeea5785aa9d990f2865dc23b4bc3c301a94eec4eabebca66be5cf638f693ec256aec514620cc28ee4a94bd9565bc4d4962b9d3641d4278fb319ed2b84de5b665f307a2db0f7fbb757366067d88c50f7e829138fde4f78d39b5b5802f1b92a8a820865af5cc79f9f30bc3f461c66af95d13e5e1f0381c184572a91dee1c849048a647a1158cf884064deddbf1b0b88dfe2f791428d0ba0f6fb2f04e14081f69165ae66d9297c118f0907705c9c4954a199bae0bb96fad763d690e7daa6cfda59ba7f2c8d11448b604d12d";
+
+    fn shared_secrets() -> Vec<sha256::Hash> {
+        let session_key = SecretKey::from_slice(&[0x41; 32]).unwrap();
+        let route = [
+            "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619",
+            "0324653eac434488002cc06bbfb7f10fe18991e35f9fe4302dbea6d2353dc0ab1c",
+            "027f31ebc5462c1fdce1b737ecff52d37d75dea43ce11c74d25aa297165faa2007",
+            "032c0b7cf95324a07d05398b240174dc0c2be444d96b159aa6c7f7b1e668680991",
+            "02edabbd16b41c8371b92ef2f04c1185b4f03b6dcd52ba9b78d9d7c89c8f221145",
+        ]
+        .map(|node_id| node_id.parse().unwrap());
+        route_shared_secrets(&Secp256k1::new(), route, session_key)
+    }
+
+    #[test]
+    fn route_secrets() {
+        let shared_secrets = shared_secrets();
+        assert_eq!(
+            shared_secrets.iter().map(|s| s.to_hex()).collect::<Vec<_>>(),
+            vec![
+                "53eb63ea8a3fec3b3cd433b85cd62a4b145e1dda09391b348c4e1cd36a03ea66",
+                "a6519e98832a0b179f62123b3567c106db99ee37bef036e783263602f3488fae",
+                "3a6b412548762f0dbccce5c7ae7bb8147d1caf9b5471c34120b30bc9c04891cc",
+                "21e13c2d7cfe7e18836df50872466117a295783ab8aab0e7ecc8c725503ad02d",
+                "b5756b9b542727dbafc6765a49488b023a725d631af688fc031217e90770c328",
+            ]
+        );
+        assert_eq!(
+            generate_key(UM_KEY, shared_secrets[4]).to_hex(),
+            "4da7f2923edce6c2d85987d1d9fa6d88023e6c3a9c3d20f07d3b10b61a78d646"
+        );
+        assert_eq!(
+            generate_key(AMMAG_KEY, shared_secrets[4]).to_hex(),
+            "2f36bb8822e1f0d04c27b7d8bb7d7dd586e032a3218b8d414afbba6f169a4d68"
+        );
+    }
+
+    #[test]
+    fn failure_creation() {
+        let shared_secrets = shared_secrets();
+        let mut packet =
+            FailurePacket::with(shared_secrets[4], &[0x20, 0x02]).unwrap();
+        assert_eq!(packet.as_ref().len(), 32 + 4 + FAILURE_MSG_LEN);
+
+        // Removing obfuscation gives raw failure packet
+        let mut raw = packet.clone();
+        raw.wrap(shared_secrets[4]);
+        assert_eq!(
+            raw.as_ref()[..32].to_hex(),
+            "4c2fc8bc08510334b6833ad9c3e79cd1b52ae59dfe5c2a4b23ead50f09f7ee0b"
+        );
+        assert_eq!(raw.as_ref()[32..36], [0x00, 0x02, 0x20, 0x02]);
+        assert_eq!(raw.as_ref()[36..38], [0x00, 0xfe]);
+
+        for shared_secret in shared_secrets[..4].iter().rev() {
+            packet.wrap(*shared_secret);
+        }
+        assert_eq!(packet.as_ref().to_hex(), FINAL_PACKET);
+    }
+
+    #[test]
+    fn failure_attribution() {
+        let shared_secrets = shared_secrets();
+        let packet = FailurePacket(Vec::from_hex(FINAL_PACKET).unwrap());
+        assert_eq!(
+            packet.clone().attribute(&shared_secrets),
+            Ok(AttributedFailure {
+                hop_index: 4,
+                message: vec![0x20, 0x02]
+            })
+        );
+        assert_eq!(
+            packet.attribute(&shared_secrets[..4]),
+            Err(FailureError::Unattributable)
+        );
+
+        let mut packet =
+            FailurePacket::with(shared_secrets[1], b"failure").unwrap();
+        packet.wrap(shared_secrets[0]);
+        assert_eq!(
+            packet.attribute(&shared_secrets),
+            Ok(AttributedFailure {
+                hop_index: 1,
+                message: b"failure".to_vec()
+            })
+        );
+    }
+}
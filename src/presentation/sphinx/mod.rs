@@ -15,7 +15,15 @@
 //! in BOLT-4, based on Secp256k1 curve. The lightning-network-specific part of
 //! BOLT-4 is implemented in LNP Core library and not here.
 
-use std::fmt::Debug;
+mod blinding;
+mod failure;
+mod nested;
+mod payload;
+mod replay;
+mod route;
+mod surb;
+
+use std::fmt::{self, Debug, Display, Formatter};
 use std::io::{self, Cursor, Read, Write};
 
 use addr::NodeId;
@@ -24,8 +32,19 @@ use chacha20::cipher::{KeyIvInit, StreamCipher};
 use chacha20::ChaCha20;
 use lightning_encoding::{LightningDecode, LightningEncode};
 use secp256k1::ecdh::SharedSecret;
-use secp256k1::{PublicKey, Secp256k1, SecretKey, Signing};
+use secp256k1::{PublicKey, Secp256k1, SecretKey, Signing, Verification};
 use strict_encoding::{StrictDecode, StrictEncode};
+use subtle::ConstantTimeEq;
+
+pub use self::blinding::{BlindedHop, BlindedNode, BlindedPath, BlindingError};
+pub use self::failure::{
+    AttributedFailure, FailureError, FailurePacket, FAILURE_MSG_LEN,
+};
+pub use self::nested::NestedError;
+pub use self::payload::{TlvHopPayload, NEXT_NODE_TLV_TYPE};
+pub use self::replay::{ReplayCache, ReplayError, ReplayStore, ReplayTag};
+pub use self::route::RouteKeys;
+pub use self::surb::{Reply, ReplyBlock, ReplyError, ReplyKeys};
 
 const MU_KEY: &[u8] = &[0x6d, 0x75];
 const RHO_KEY: &[u8] = &[0x72, 0x68, 0x6f];
@@ -48,6 +67,13 @@ pub enum EncodeError {
     /// cumulative payload and HMAC sizes size exceeds packet size
     /// {packet_size}
     NotFittingData { packet_size: usize },
+
+    /// onion route must contain at least one hop
+    EmptyRoute,
+
+    /// onion hops do not match the route for which the route keys were
+    /// computed
+    RouteMismatch,
 }
 
 /// Sphinx is abstracted from a specific encoding used by a packed payload:
@@ -125,9 +151,13 @@ where
 /// put everywhere in your data structures where [`OnionPacket`] can be. It will
 /// encode/decode from the lightning- or strict-encoded stream the same way
 /// as the onion packet - unless you explicitly unfold the outer layer. Then
-/// it will insert a special flag byte 0x66 at the secod position (right after
-/// version) which will indicate that the following data are prefixed with
-/// addition plain payload.
+/// it will insert a special flag byte at the second position (right after
+/// version), where the onion packet has the first byte of a compressed public
+/// key (0x02 or 0x03):
+/// - 0x66 for [`Onion::Unfolded`], indicating that the following data are the
+///   plain payload followed by the full onion packet for the next hop;
+/// - 0x67 for [`Onion::Final`], indicating that the following data are the
+///   plain payload of the final hop, with no remaining onion packet.
 #[derive(Clone, PartialEq, Eq, Hash, Debug, From)]
 pub enum Onion<Payload: SphinxPayload, const PACKET_LEN: usize> {
     /// Sphinx packet as it was received/will be transmitted over the wire in
@@ -142,6 +172,78 @@ pub enum Onion<Payload: SphinxPayload, const PACKET_LEN: usize> {
         /// next host
         onion: OnionPacket<PACKET_LEN>,
     },
+    /// Sphinx packet with its outer layer unfolded at the final hop, which
+    /// has no remaining onion packet to forward
+    Final {
+        /// Hop payload stored in the last layer of the packet
+        payload: Payload,
+    },
+}
+
+impl<Payload, const PACKET_LEN: usize> From<Peeled<Payload, PACKET_LEN>>
+    for Onion<Payload, PACKET_LEN>
+where
+    Payload: SphinxPayload,
+{
+    fn from(peeled: Peeled<Payload, PACKET_LEN>) -> Self {
+        match peeled {
+            Peeled::Forward {
+                payload,
+                next_onion,
+            } => Onion::Unfolded {
+                payload,
+                onion: next_onion,
+            },
+            Peeled::Final { payload } => Onion::Final { payload },
+        }
+    }
+}
+
+impl<Payload, const PACKET_LEN: usize> Onion<Payload, PACKET_LEN>
+where
+    Payload: SphinxPayload,
+{
+    /// Peels the outer layer of the onion packet with [`OnionPacket::peel`];
+    /// does nothing if the packet was already peeled.
+    pub fn peel<C>(
+        self,
+        secp: &Secp256k1<C>,
+        node_secret: SecretKey,
+        assoc_data: &[u8],
+    ) -> Result<Self, PeelError<Payload::DecodeError>>
+    where
+        C: Verification,
+    {
+        match self {
+            Onion::Onion(onion) => {
+                onion.peel(secp, node_secret, assoc_data).map(Onion::from)
+            }
+            unfolded => Ok(unfolded),
+        }
+    }
+
+    /// Returns hop payload if the outer layer of the onion was unfolded
+    pub fn payload(&self) -> Option<&Payload> {
+        match self {
+            Onion::Onion(_) => None,
+            Onion::Unfolded { payload, .. } | Onion::Final { payload } => {
+                Some(payload)
+            }
+        }
+    }
+
+    /// Returns onion packet which must be forwarded to the next hop, if the
+    /// outer layer was unfolded and the node is not the final hop
+    pub fn next_onion(&self) -> Option<&OnionPacket<PACKET_LEN>> {
+        match self {
+            Onion::Unfolded { onion, .. } => Some(onion),
+            Onion::Onion(_) | Onion::Final { .. } => None,
+        }
+    }
+
+    /// Detects whether the onion was unfolded by the final hop
+    #[inline]
+    pub fn is_final(&self) -> bool { matches!(self, Onion::Final { .. }) }
 }
 
 impl<Payload: SphinxPayload, const PACKET_LEN: usize> LightningEncode
@@ -166,6 +268,13 @@ impl<Payload: SphinxPayload, const PACKET_LEN: usize> LightningEncode
                 // version byte
                 onion.lightning_encode(e)
             }
+            Onion::Final { payload } => {
+                // Final hop payload uses a separate flag and has no remaining
+                // onion package
+                0u8.lightning_encode(&mut e)?;
+                0x67u8.lightning_encode(&mut e)?;
+                payload.lightning_encode(e)
+            }
         }
     }
 }
@@ -182,6 +291,9 @@ impl<Payload: SphinxPayload, const PACKET_LEN: usize> LightningDecode
             let payload = Payload::lightning_decode(&mut d)?;
             let onion = OnionPacket::lightning_decode(d)?;
             Ok(Onion::Unfolded { payload, onion })
+        } else if ver == 0x00 && flag == 0x67 {
+            let payload = Payload::lightning_decode(d)?;
+            Ok(Onion::Final { payload })
         } else {
             let cursor = Cursor::new(vec![ver, flag]);
             let reader = cursor.chain(d);
@@ -213,6 +325,13 @@ impl<Payload: SphinxPayload, const PACKET_LEN: usize> StrictEncode
                 // version byte
                 onion.strict_encode(e)
             }
+            Onion::Final { payload } => {
+                // Final hop payload uses a separate flag and has no remaining
+                // onion package
+                0u8.strict_encode(&mut e)?;
+                0x67u8.strict_encode(&mut e)?;
+                payload.strict_encode(e)
+            }
         }
     }
 }
@@ -229,6 +348,9 @@ impl<Payload: SphinxPayload, const PACKET_LEN: usize> StrictDecode
             let payload = Payload::strict_decode(&mut d)?;
             let onion = OnionPacket::strict_decode(d)?;
             Ok(Onion::Unfolded { payload, onion })
+        } else if ver == 0x00 && flag == 0x67 {
+            let payload = Payload::strict_decode(d)?;
+            Ok(Onion::Final { payload })
         } else {
             let cursor = Cursor::new(vec![ver, flag]);
             let reader = cursor.chain(d);
@@ -238,6 +360,85 @@ impl<Payload: SphinxPayload, const PACKET_LEN: usize> StrictDecode
     }
 }
 
+/// Result of peeling the outer layer of the onion packet with
+/// [`OnionPacket::peel`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Peeled<Payload: SphinxPayload, const PACKET_LEN: usize> {
+    /// The processing node is an intermediate hop and must forward
+    /// `next_onion` to the next node
+    Forward {
+        /// Hop payload for the processing node
+        payload: Payload,
+        /// Onion packet for the next hop
+        next_onion: OnionPacket<PACKET_LEN>,
+    },
+    /// The processing node is the final hop of the onion route
+    Final {
+        /// Hop payload for the final node
+        payload: Payload,
+    },
+}
+
+impl<Payload, const PACKET_LEN: usize> Peeled<Payload, PACKET_LEN>
+where
+    Payload: SphinxPayload,
+{
+    /// Returns hop payload for the processing node
+    pub fn payload(&self) -> &Payload {
+        match self {
+            Peeled::Forward { payload, .. } | Peeled::Final { payload } => {
+                payload
+            }
+        }
+    }
+
+    /// Detects whether the processing node is the final hop
+    #[inline]
+    pub fn is_final(&self) -> bool { matches!(self, Peeled::Final { .. }) }
+}
+
+/// Errors peeling onion packet
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum PeelError<E> {
+    /// Onion packet version is not supported
+    UnsupportedVersion(u8),
+
+    /// Onion packet HMAC does not match the packet data
+    InvalidHmac,
+
+    /// Hop payload decoding error
+    Decode(E),
+}
+
+impl<E> Display for PeelError<E>
+where
+    E: Display,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PeelError::UnsupportedVersion(ver) => {
+                write!(f, "unsupported onion packet version {}", ver)
+            }
+            PeelError::InvalidHmac => {
+                f.write_str("onion packet HMAC does not match packet data")
+            }
+            PeelError::Decode(err) => Display::fmt(err, f),
+        }
+    }
+}
+
+impl<E> std::error::Error for PeelError<E>
+where
+    E: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PeelError::Decode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
 /// Cyphered raw sphinx packet as it is sent over the wire
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 #[derive(LightningEncode, LightningDecode)]
@@ -279,11 +480,33 @@ impl<const PACKET_LEN: usize> SphinxPacket<PACKET_LEN> {
         C: Signing,
         Payload: SphinxPayload,
     {
+        if hops.is_empty() {
+            return Err(EncodeError::EmptyRoute);
+        }
         let shared_secrets = construct_shared_secrets(secp, hops, session_key);
+        SphinxPacket::with_shared_secrets(
+            session_key,
+            hops,
+            &shared_secrets,
+            assoc_data,
+        )
+    }
+
+    /// Constructs Sphinx packet using shared secrets pre-computed for each of
+    /// the `hops` from the `session_key`.
+    fn with_shared_secrets<Payload>(
+        session_key: SecretKey,
+        hops: &[Hop<Payload>],
+        shared_secrets: &[sha256::Hash],
+        assoc_data: &[u8],
+    ) -> Result<(Self, Hmac<sha256::Hash>), EncodeError>
+    where
+        Payload: SphinxPayload,
+    {
+        debug_assert_eq!(hops.len(), shared_secrets.len());
 
         // Generate the padding, called "filler strings" in the paper.
-        let filler =
-            generate_filler(RHO_KEY, PACKET_LEN, hops, &shared_secrets);
+        let filler = generate_filler(RHO_KEY, PACKET_LEN, hops, shared_secrets);
 
         // Allocate and initialize fields to zero-filled slices
         let mut mix_header = [0u8; PACKET_LEN];
@@ -291,17 +514,13 @@ impl<const PACKET_LEN: usize> SphinxPacket<PACKET_LEN> {
         let mut next_hmac = Hmac::<sha256::Hash>::all_zeros();
 
         let padding_key = generate_key(PAD_KEY, &session_key[..]);
-        let padding_bytes = generate_cipher_stream(padding_key, PACKET_LEN);
-        mix_header.copy_from_slice(&padding_bytes);
+        apply_cipher_stream(padding_key, &mut mix_header);
 
         let mut last_hop = true;
         for (hop, shared_secret) in hops.iter().zip(shared_secrets).rev() {
             let rho_key = generate_key(RHO_KEY, shared_secret);
             let mu_key = generate_key(MU_KEY, shared_secret);
 
-            // Shift and obfuscate routing information
-            let stream_bytes = generate_cipher_stream(rho_key, PACKET_LEN);
-
             let shift_size = hop.payload_size();
             if shift_size > PACKET_LEN {
                 return Err(EncodeError::PayloadTooLarge {
@@ -338,12 +557,8 @@ impl<const PACKET_LEN: usize> SphinxPacket<PACKET_LEN> {
                 });
             }
 
-            mix_header
-                .iter_mut()
-                .zip(stream_bytes)
-                .for_each(|(byte, mask)| {
-                    *byte ^= mask;
-                });
+            // Obfuscate routing information
+            apply_cipher_stream(rho_key, &mut mix_header);
 
             // These need to be overwritten, so every node generates a correct
             // padding
@@ -378,9 +593,9 @@ impl<const PACKET_LEN: usize> SphinxPacket<PACKET_LEN> {
     /// Unfolds one layer of the onion, returning decrypted data from the sphinx
     /// packet.
     ///
-    /// # Panics
-    ///
-    /// If `Payload::PACKET_LEN` <= 32 bytes.
+    /// The payload must fit into the packet data; otherwise a decoding error
+    /// is returned. Decryption happens in place over stack buffers, without
+    /// heap allocations.
     pub fn unfold<Payload>(
         &mut self,
         shared_secret: sha256::Hash,
@@ -389,30 +604,35 @@ impl<const PACKET_LEN: usize> SphinxPacket<PACKET_LEN> {
         Payload: SphinxPayload,
     {
         let rho_key = generate_key(RHO_KEY, shared_secret);
-        let stream_bytes = generate_cipher_stream(rho_key, PACKET_LEN * 2);
-
-        let mut packet = self.0.to_vec();
-        packet.resize(PACKET_LEN * 2, 0);
-
-        let data = packet
-            .iter()
-            .zip(&stream_bytes)
-            .map(|(b, m)| b ^ m)
-            .collect::<Vec<u8>>();
-        let mut cursor = Cursor::new(data);
-
-        let payload = Payload::decode(&mut cursor)?;
+        let mut cypher = cipher(rho_key);
+
+        let mut data = self.0;
+        cypher.apply_keystream(&mut data);
+        let mut reader = &data[..];
+        let payload = Payload::decode(&mut reader)?;
+
+        // The packet for the next hop is shifted by the payload and HMAC
+        // size, so we need to decrypt only that many bytes of the zero-filled
+        // tail following the packet data
+        let shift = PACKET_LEN - reader.len() + 32;
+        let mut tail = [0u8; PACKET_LEN];
+        let mut hmac_tail = [0u8; 32];
+        cypher.apply_keystream(&mut tail[..shift.min(PACKET_LEN)]);
+        if shift > PACKET_LEN {
+            cypher.apply_keystream(&mut hmac_tail[..shift - PACKET_LEN]);
+        }
+        let mut reader = reader.chain(&tail[..]).chain(&hmac_tail[..]);
 
         let mut hmac_slice = [0u8; 32];
-        cursor
+        reader
             .read_exact(&mut hmac_slice)
-            .expect("Payload::PACKET_LEN must be greater than 32");
+            .expect("tail buffers are always larger than HMAC");
         let hmac = Hmac::<sha256::Hash>::from_slice(&hmac_slice)
             .expect("32-byte slice");
 
-        cursor
+        reader
             .read_exact(&mut self.0)
-            .expect("Payload::PACKET_LEN must be greater than 32");
+            .expect("tail buffers are always larger than the packet");
 
         Ok((payload, hmac))
     }
@@ -444,6 +664,10 @@ pub struct OnionPacket<const PACKET_LEN: usize> {
 }
 
 impl<const PACKET_LEN: usize> OnionPacket<PACKET_LEN> {
+    /// Length of the encoded onion packet: version byte, public key, sphinx
+    /// packet data and HMAC.
+    pub const SERIALIZED_LEN: usize = 1 + 33 + PACKET_LEN + 32;
+
     /// Assembles onion packed from the provided hop data, generating random
     /// session key from a standard randomness source.
     ///
@@ -469,6 +693,8 @@ impl<const PACKET_LEN: usize> OnionPacket<PACKET_LEN> {
     ///
     /// NB: The session keys must not be re-used! See [`OnionPacket::with`] for
     /// convenience generating new session key per each packet.
+    /// For computing keys of many packets along the same route ahead of their
+    /// construction use [`RouteKeys`] and [`OnionPacket::with_route_keys`].
     pub fn with_session_key<C, Payload>(
         secp: &Secp256k1<C>,
         session_key: SecretKey,
@@ -491,16 +717,23 @@ impl<const PACKET_LEN: usize> OnionPacket<PACKET_LEN> {
     }
 
     /// Checks that HMAC commits to the inner sphinx packet with optional
-    /// associate data.
+    /// associate data. HMACs are compared in constant time.
     pub fn check_hmac(
         &self,
         node_secret: SecretKey,
         assoc_data: &[u8],
     ) -> bool {
-        let shared_secret = self.shared_secret(node_secret);
+        self.hmac_matches(self.shared_secret(node_secret), assoc_data)
+    }
+
+    fn hmac_matches(
+        &self,
+        shared_secret: sha256::Hash,
+        assoc_data: &[u8],
+    ) -> bool {
         let mu_key = generate_key(MU_KEY, shared_secret);
         let hmac = self.packet.hmac(mu_key, assoc_data);
-        self.hmac == hmac
+        hmac_eq(&self.hmac, &hmac)
     }
 
     /// Computes shared secret between the sender and the local node private key
@@ -515,7 +748,9 @@ impl<const PACKET_LEN: usize> OnionPacket<PACKET_LEN> {
     /// node.
     ///
     /// NB: Does not check for the package integrity; use
-    /// [`OnionPacket::check_hmac`] first!
+    /// [`OnionPacket::check_hmac`] first! Does not update ephemeral public
+    /// key of the packet either; relaying nodes should use
+    /// [`OnionPacket::peel`] instead.
     pub fn unfold<Payload>(
         &mut self,
         node_secret: SecretKey,
@@ -528,6 +763,107 @@ impl<const PACKET_LEN: usize> OnionPacket<PACKET_LEN> {
         self.hmac = hmac;
         Ok(payload)
     }
+
+    /// Peels outer layer of the onion, returning payload for the local node
+    /// together with the onion packet for the next hop - or detecting that the
+    /// local node is the final hop of the onion route.
+    ///
+    /// Unlike [`OnionPacket::unfold`], checks packet version and HMAC before
+    /// decryption and computes ephemeral public key for the next hop.
+    pub fn peel<C, Payload>(
+        &self,
+        secp: &Secp256k1<C>,
+        node_secret: SecretKey,
+        assoc_data: &[u8],
+    ) -> Result<Peeled<Payload, PACKET_LEN>, PeelError<Payload::DecodeError>>
+    where
+        C: Verification,
+        Payload: SphinxPayload,
+    {
+        let shared_secret = self.shared_secret(node_secret);
+        self.peel_with_shared_secret(secp, shared_secret, assoc_data)
+    }
+
+    /// Peels outer layer of the onion like [`OnionPacket::peel`], using shared
+    /// secret of the packet with the local node, which was already computed
+    /// with [`OnionPacket::shared_secret`] (for instance, to check the packet
+    /// against the replay store).
+    pub fn peel_with_shared_secret<C, Payload>(
+        &self,
+        secp: &Secp256k1<C>,
+        shared_secret: sha256::Hash,
+        assoc_data: &[u8],
+    ) -> Result<Peeled<Payload, PACKET_LEN>, PeelError<Payload::DecodeError>>
+    where
+        C: Verification,
+        Payload: SphinxPayload,
+    {
+        if self.version != 0 {
+            return Err(PeelError::UnsupportedVersion(self.version));
+        }
+        if !self.hmac_matches(shared_secret, assoc_data) {
+            return Err(PeelError::InvalidHmac);
+        }
+
+        let mut packet = self.packet;
+        let (payload, hmac) =
+            packet.unfold(shared_secret).map_err(PeelError::Decode)?;
+
+        if hmac[..] == [0u8; 32] {
+            return Ok(Peeled::Final { payload });
+        }
+
+        let blinding_factor = blinding_factor(self.point, shared_secret);
+        let point = self
+            .point
+            .mul_tweak(secp, &blinding_factor)
+            .expect("negligible probability of exceeding group size");
+        Ok(Peeled::Forward {
+            payload,
+            next_onion: OnionPacket {
+                version: self.version,
+                point,
+                packet,
+                hmac,
+            },
+        })
+    }
+
+    /// Unfolds one layer of the onion like [`OnionPacket::unfold`], refusing
+    /// to process the packet if it was already processed before according to
+    /// the provided replay `store`.
+    ///
+    /// Unlike [`OnionPacket::unfold`], checks the packet HMAC with optional
+    /// associated data first, and registers the packet in the store only
+    /// after it was authenticated, such that forged packets can't take over
+    /// replay tags of the valid ones. Authenticated packets are registered
+    /// before the unfolding, so packets failing to unfold can't be replayed
+    /// either.
+    pub fn unfold_once<Payload, Store>(
+        &mut self,
+        node_secret: SecretKey,
+        assoc_data: &[u8],
+        store: &mut Store,
+    ) -> Result<Payload, ReplayError<Payload::DecodeError, Store::Error>>
+    where
+        Payload: SphinxPayload,
+        Store: ReplayStore,
+    {
+        let shared_secret = self.shared_secret(node_secret);
+        if !self.hmac_matches(shared_secret, assoc_data) {
+            return Err(ReplayError::InvalidHmac);
+        }
+        let tag = ReplayTag::with(shared_secret);
+        if !store.register(tag).map_err(ReplayError::Store)? {
+            return Err(ReplayError::Replay(tag));
+        }
+        let (payload, hmac) = self
+            .packet
+            .unfold(shared_secret)
+            .map_err(ReplayError::Decode)?;
+        self.hmac = hmac;
+        Ok(payload)
+    }
 }
 
 fn construct_shared_secrets<C, Payload>(
@@ -539,13 +875,46 @@ where
     C: Signing,
     Payload: SphinxPayload,
 {
-    let mut shared_secrets = Vec::<sha256::Hash>::with_capacity(hops.len());
+    let route = hops.iter().map(|hop| hop.node_id);
+    route_shared_secrets(secp, route, session_key)
+}
+
+/// Computes shared secrets for each of the nodes in the onion `route` using
+/// the `session_key` of the onion packet. These secrets are required by the
+/// onion origin to attribute failure packets (see
+/// [`FailurePacket::attribute`]).
+pub fn route_shared_secrets<C>(
+    secp: &Secp256k1<C>,
+    route: impl IntoIterator<Item = NodeId>,
+    session_key: SecretKey,
+) -> Vec<sha256::Hash>
+where
+    C: Signing,
+{
+    route_keys(secp, route, session_key).0
+}
+
+/// Computes shared secrets and ephemeral public keys for each of the nodes in
+/// the onion `route`.
+fn route_keys<C>(
+    secp: &Secp256k1<C>,
+    route: impl IntoIterator<Item = NodeId>,
+    session_key: SecretKey,
+) -> (Vec<sha256::Hash>, Vec<PublicKey>)
+where
+    C: Signing,
+{
+    let route = route.into_iter();
+    let mut shared_secrets =
+        Vec::<sha256::Hash>::with_capacity(route.size_hint().0);
+    let mut ephemeral_keys =
+        Vec::<PublicKey>::with_capacity(shared_secrets.capacity());
     let mut ephemeral_key = session_key;
 
-    for hop in hops {
+    for node_id in route {
         // Perform ECDH
         let shared_secret =
-            SharedSecret::new(&hop.node_id.public_key(), &ephemeral_key);
+            SharedSecret::new(&node_id.public_key(), &ephemeral_key);
         let shared_secret = sha256::Hash::from_slice(shared_secret.as_ref())
             .expect("ECDH result is not a 32-byte hash");
         shared_secrets.push(shared_secret);
@@ -553,15 +922,10 @@ where
         // Derive ephemeral public key from private key
         let ephemeral_sk = ephemeral_key;
         let ephemeral_pk = PublicKey::from_secret_key(secp, &ephemeral_sk);
+        ephemeral_keys.push(ephemeral_pk);
 
         // Compute blinding factor
-        let mut engine = sha256::Hash::engine();
-        engine.input(&ephemeral_pk.serialize());
-        engine.input(&shared_secret);
-        let blinding_factor = sha256::Hash::from_engine(engine);
-        let blinding_factor =
-            secp256k1::Scalar::from_be_bytes(blinding_factor.into_inner())
-                .expect("negligible probability");
+        let blinding_factor = blinding_factor(ephemeral_pk, shared_secret);
 
         // Blind ephemeral key for next loop
         ephemeral_key = ephemeral_key
@@ -569,7 +933,24 @@ where
             .expect("negligible probability of exceeding group size");
     }
 
-    shared_secrets
+    (shared_secrets, ephemeral_keys)
+}
+
+fn blinding_factor(
+    ephemeral_pk: PublicKey,
+    shared_secret: sha256::Hash,
+) -> secp256k1::Scalar {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&ephemeral_pk.serialize());
+    engine.input(&shared_secret);
+    let blinding_factor = sha256::Hash::from_engine(engine);
+    secp256k1::Scalar::from_be_bytes(blinding_factor.into_inner())
+        .expect("negligible probability")
+}
+
+/// Compares HMACs in constant time
+fn hmac_eq(hmac1: &Hmac<sha256::Hash>, hmac2: &Hmac<sha256::Hash>) -> bool {
+    hmac1[..].ct_eq(&hmac2[..]).into()
 }
 
 fn generate_key(key: &[u8], shared_secret: impl AsRef<[u8]>) -> [u8; 32] {
@@ -578,14 +959,16 @@ fn generate_key(key: &[u8], shared_secret: impl AsRef<[u8]>) -> [u8; 32] {
     Hmac::from_engine(engine).into_inner()
 }
 
-fn generate_cipher_stream(prng_seed: [u8; 32], len: usize) -> Vec<u8> {
-    let mut stream = vec![0u8; len];
-    let mut cypher = ChaCha20::new_from_slices(&prng_seed, &[0u8; 12])
-        .expect("incorrect ChaCha20 initialization");
-    cypher
-        .try_apply_keystream(&mut stream)
+fn cipher(prng_seed: [u8; 32]) -> ChaCha20 {
+    ChaCha20::new_from_slices(&prng_seed, &[0u8; 12])
+        .expect("incorrect ChaCha20 initialization")
+}
+
+/// XORs `data` in place with the cipher stream generated from `prng_seed`
+fn apply_cipher_stream(prng_seed: [u8; 32], data: &mut [u8]) {
+    cipher(prng_seed)
+        .try_apply_keystream(data)
         .expect("cypher stream implementation diverged from BOLT-4");
-    stream
 }
 
 fn generate_filler_stream<Payload>(
@@ -605,14 +988,9 @@ where
         // Zero-fill the last hop
         filler[packet_len..].fill(0);
 
-        // Generate pseudo-random byte stream
+        // Obfuscate with pseudo-random byte stream
         let stream_key = generate_key(key, secret);
-        let stream_bytes = generate_cipher_stream(stream_key, packet_len * 2);
-
-        filler
-            .iter_mut()
-            .zip(stream_bytes)
-            .for_each(|(byte, mask)| *byte ^= mask);
+        apply_cipher_stream(stream_key, &mut filler);
 
         filler.rotate_left(hop_size);
     }
@@ -642,6 +1020,13 @@ mod test {
     use lightning_encoding::LightningDecode;
 
     use super::*;
+    use crate::presentation::tlv;
+
+    fn generate_cipher_stream(prng_seed: [u8; 32], len: usize) -> Vec<u8> {
+        let mut stream = vec![0u8; len];
+        apply_cipher_stream(prng_seed, &mut stream);
+        stream
+    }
 
     const PACKET_LEN: usize = 20 * 65;
 
@@ -1233,4 +1618,131 @@ mod test {
             Vec::from_hex("00000067000003000100000000000003e800000075000000000000000000000000000000000000000000000000").unwrap()
         );
     }
+
+    /// Constructs onion route over test nodes, which secret keys are filled
+    /// with `keys` bytes. Hop payloads contain the key byte under TLV type 2.
+    pub(super) fn test_route(
+        secp: &Secp256k1<secp256k1::All>,
+        keys: impl IntoIterator<Item = u8>,
+    ) -> (Vec<SecretKey>, Vec<Hop<TlvHopPayload>>) {
+        keys.into_iter()
+            .map(|byte| {
+                let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+                let node_id = PublicKey::from_secret_key(secp, &secret).into();
+                let mut stream = tlv::Stream::new();
+                stream.insert(2usize.into(), [byte]);
+                (secret, Hop::with(node_id, TlvHopPayload::with(stream)))
+            })
+            .unzip()
+    }
+
+    #[test]
+    fn peel_onion() {
+        let secp = Secp256k1::new();
+        let (node_secrets, hops) = test_route(&secp, 1..=3);
+        let session_key = SecretKey::from_slice(&[0x41; 32]).unwrap();
+        let mut onion = OnionPacket::<PACKET_LEN>::with_session_key(
+            &secp,
+            session_key,
+            &hops,
+            b"assoc",
+        )
+        .unwrap();
+
+        for (index, node_secret) in node_secrets.iter().enumerate() {
+            let peeled = onion
+                .peel::<_, TlvHopPayload>(&secp, *node_secret, b"assoc")
+                .unwrap();
+            assert_eq!(
+                onion
+                    .peel_with_shared_secret(
+                        &secp,
+                        onion.shared_secret(*node_secret),
+                        b"assoc"
+                    )
+                    .unwrap(),
+                peeled
+            );
+            assert_eq!(peeled.payload(), &hops[index].payload);
+            match peeled {
+                Peeled::Forward { next_onion, .. } => {
+                    assert!(index < 2);
+                    onion = next_onion;
+                }
+                Peeled::Final { .. } => assert_eq!(index, 2),
+            }
+        }
+    }
+
+    #[test]
+    fn peel_errors() {
+        let secp = Secp256k1::new();
+        let (node_secrets, hops) = test_route(&secp, 1..=3);
+        let session_key = SecretKey::from_slice(&[0x41; 32]).unwrap();
+        let mut onion = OnionPacket::<PACKET_LEN>::with_session_key(
+            &secp,
+            session_key,
+            &hops,
+            &[],
+        )
+        .unwrap();
+
+        assert!(matches!(
+            onion.peel::<_, TlvHopPayload>(&secp, node_secrets[1], &[]),
+            Err(PeelError::InvalidHmac)
+        ));
+        assert!(matches!(
+            onion.peel::<_, TlvHopPayload>(&secp, node_secrets[0], b"assoc"),
+            Err(PeelError::InvalidHmac)
+        ));
+        onion.version = 1;
+        assert!(matches!(
+            onion.peel::<_, TlvHopPayload>(&secp, node_secrets[0], &[]),
+            Err(PeelError::UnsupportedVersion(1))
+        ));
+    }
+
+    #[test]
+    fn onion_peel_encoding() {
+        let secp = Secp256k1::new();
+        let (node_secrets, hops) = test_route(&secp, 1..=3);
+        let session_key = SecretKey::from_slice(&[0x41; 32]).unwrap();
+        let packet = OnionPacket::<PACKET_LEN>::with_session_key(
+            &secp,
+            session_key,
+            &hops[2..],
+            &[],
+        )
+        .unwrap();
+
+        let onion = Onion::<TlvHopPayload, PACKET_LEN>::from(packet);
+        assert_eq!(onion.payload(), None);
+        let onion = onion.peel(&secp, node_secrets[2], &[]).unwrap();
+        assert!(onion.is_final());
+        assert_eq!(onion.payload(), Some(&hops[2].payload));
+        assert_eq!(onion.next_onion(), None);
+
+        let data = onion.lightning_serialize().unwrap();
+        assert_eq!(data[..2], [0x00, 0x67]);
+        assert_eq!(Onion::lightning_deserialize(&data), Ok(onion.clone()));
+        let data = onion.strict_serialize().unwrap();
+        assert_eq!(Onion::strict_deserialize(&data), Ok(onion));
+    }
+
+    #[test]
+    fn unfold_oversized_payload() {
+        // Test payloads take 45 bytes, which does not fit into the packet
+        let mut packet = SphinxPacket::<40>([0u8; 40]);
+        let shared_secret = sha256::Hash::hash(b"sphinx");
+        assert!(packet.unfold::<Vec<u8>>(shared_secret).is_err());
+    }
+
+    #[test]
+    fn hmac_comparison() {
+        let hmac = Hmac::<sha256::Hash>::hash(b"sphinx");
+        let mut other = hmac.into_inner();
+        assert!(hmac_eq(&hmac, &Hmac::from_inner(other)));
+        other[31] ^= 1;
+        assert!(!hmac_eq(&hmac, &Hmac::from_inner(other)));
+    }
 }
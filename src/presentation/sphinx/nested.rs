@@ -0,0 +1,232 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Nested onions, where a smaller inner onion is carried inside TLV hop
+//! payload of the last node of an outer onion route, as in trampoline
+//! routing.
+//!
+//! Both onions are built with the same associated data. This binds the inner
+//! onion to the context identified by the associated data (for instance, a
+//! payment hash), but not to the outer onion itself: the node unfolding the
+//! outer onion may detach the inner one and send it within a different outer
+//! onion carrying the same associated data. The inner onion can't be bound to
+//! the outer packet, since the latter is constructed from the payload
+//! containing the inner onion; applications requiring such binding must do it
+//! at the application level.
+
+use lightning_encoding::{LightningDecode, LightningEncode};
+use secp256k1::{Secp256k1, SecretKey, Signing};
+
+use super::{EncodeError, Hop, OnionPacket, SphinxPayload, TlvHopPayload};
+use crate::presentation::tlv;
+
+/// Errors composing and decomposing nested onions
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum NestedError {
+    /// {0}
+    #[from]
+    Encode(EncodeError),
+
+    /// hop payload does not contain inner onion under TLV type {0}
+    NoInnerOnion(tlv::Type),
+
+    /// inner onion data under TLV type {0} has length {1}, while onion packets
+    /// with the expected packet size must have length {2}
+    InnerLenMismatch(tlv::Type, usize, usize),
+
+    /// invalid inner onion: {0}
+    #[from]
+    InvalidInnerOnion(lightning_encoding::Error),
+}
+
+impl TlvHopPayload {
+    /// Embeds onion packet into the payload under a given TLV type, replacing
+    /// existing record with the same type.
+    pub fn insert_onion<const PACKET_LEN: usize>(
+        &mut self,
+        type_id: tlv::Type,
+        onion: &OnionPacket<PACKET_LEN>,
+    ) {
+        let data = onion
+            .lightning_serialize()
+            .expect("memory encoders does not error");
+        self.stream_mut().insert(type_id, data);
+    }
+
+    /// Extracts onion packet embedded into the payload under a given TLV type
+    pub fn onion<const PACKET_LEN: usize>(
+        &self,
+        type_id: tlv::Type,
+    ) -> Result<OnionPacket<PACKET_LEN>, NestedError> {
+        let data = self
+            .stream()
+            .get(&type_id)
+            .ok_or(NestedError::NoInnerOnion(type_id))?;
+        let expected_len = OnionPacket::<PACKET_LEN>::SERIALIZED_LEN;
+        if data.len() != expected_len {
+            return Err(NestedError::InnerLenMismatch(
+                type_id,
+                data.len(),
+                expected_len,
+            ));
+        }
+        OnionPacket::lightning_deserialize(data).map_err(NestedError::from)
+    }
+}
+
+impl<const PACKET_LEN: usize> OnionPacket<PACKET_LEN> {
+    /// Assembles onion packet for the `outer_hops` route, carrying inner
+    /// onion for the `inner_hops` route inside the payload of the last outer
+    /// hop under `onion_type` TLV type.
+    ///
+    /// Both onions commit to the same `assoc_data`, and must use different
+    /// session keys, which must not be re-used (see [`OnionPacket::with`]).
+    /// The last outer hop extracts the inner onion with
+    /// [`TlvHopPayload::onion`] and peels it with the same associated data.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_nested<C, Payload, const INNER_LEN: usize>(
+        secp: &Secp256k1<C>,
+        session_key: SecretKey,
+        inner_session_key: SecretKey,
+        outer_hops: &[Hop<TlvHopPayload>],
+        inner_hops: &[Hop<Payload>],
+        onion_type: tlv::Type,
+        assoc_data: &[u8],
+    ) -> Result<Self, NestedError>
+    where
+        C: Signing,
+        Payload: SphinxPayload,
+    {
+        let inner = OnionPacket::<INNER_LEN>::with_session_key(
+            secp,
+            inner_session_key,
+            inner_hops,
+            assoc_data,
+        )?;
+
+        let mut outer_hops = outer_hops.to_vec();
+        let last = outer_hops.last_mut().ok_or(EncodeError::EmptyRoute)?;
+        last.payload.insert_onion(onion_type, &inner);
+        last.payload.check_capacity(PACKET_LEN)?;
+
+        OnionPacket::with_session_key(
+            secp,
+            session_key,
+            &outer_hops,
+            assoc_data,
+        )
+        .map_err(NestedError::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::test_route;
+    use super::super::{PeelError, Peeled};
+    use super::*;
+
+    const OUTER_LEN: usize = 1300;
+    const INNER_LEN: usize = 400;
+    const ONION_TYPE: u64 = 66100;
+
+    fn peel_route<const PACKET_LEN: usize>(
+        secp: &Secp256k1<secp256k1::All>,
+        mut onion: OnionPacket<PACKET_LEN>,
+        secrets: &[SecretKey],
+        assoc_data: &[u8],
+    ) -> TlvHopPayload {
+        for (index, secret) in secrets.iter().enumerate() {
+            match onion.peel(secp, *secret, assoc_data).unwrap() {
+                Peeled::Forward { next_onion, .. } => onion = next_onion,
+                Peeled::Final { payload } => {
+                    assert_eq!(index, secrets.len() - 1);
+                    return payload;
+                }
+            }
+        }
+        unreachable!("onion route does not end with the final hop")
+    }
+
+    #[test]
+    fn nested_onion() {
+        let secp = Secp256k1::new();
+        let (outer_secrets, outer_hops) = test_route(&secp, 1..=3);
+        let (inner_secrets, inner_hops) = test_route(&secp, 4..=5);
+        let onion = OnionPacket::<OUTER_LEN>::with_nested::<_, _, INNER_LEN>(
+            &secp,
+            SecretKey::from_slice(&[0x41; 32]).unwrap(),
+            SecretKey::from_slice(&[0x42; 32]).unwrap(),
+            &outer_hops,
+            &inner_hops,
+            ONION_TYPE.into(),
+            b"payment hash",
+        )
+        .unwrap();
+
+        let payload = peel_route(&secp, onion, &outer_secrets, b"payment hash");
+        let inner = payload.onion::<INNER_LEN>(ONION_TYPE.into()).unwrap();
+        let amount: &[u8] =
+            payload.stream().get(&2usize.into()).unwrap().as_ref();
+        assert_eq!(amount, &[3]);
+
+        assert!(matches!(
+            inner.peel::<_, TlvHopPayload>(&secp, inner_secrets[0], b"other"),
+            Err(PeelError::InvalidHmac)
+        ));
+        let payload = peel_route(&secp, inner, &inner_secrets, b"payment hash");
+        assert_eq!(payload, inner_hops[1].payload);
+    }
+
+    #[test]
+    fn nested_errors() {
+        let secp = Secp256k1::new();
+        let (_, outer_hops) = test_route(&secp, 1..=3);
+        let (_, inner_hops) = test_route(&secp, 4..=5);
+
+        assert_eq!(
+            OnionPacket::<OUTER_LEN>::with_nested::<_, _, INNER_LEN>(
+                &secp,
+                SecretKey::from_slice(&[0x41; 32]).unwrap(),
+                SecretKey::from_slice(&[0x42; 32]).unwrap(),
+                &[],
+                &inner_hops,
+                ONION_TYPE.into(),
+                &[],
+            ),
+            Err(NestedError::Encode(EncodeError::EmptyRoute))
+        );
+        assert!(matches!(
+            OnionPacket::<OUTER_LEN>::with_nested::<_, _, OUTER_LEN>(
+                &secp,
+                SecretKey::from_slice(&[0x41; 32]).unwrap(),
+                SecretKey::from_slice(&[0x42; 32]).unwrap(),
+                &outer_hops,
+                &inner_hops,
+                ONION_TYPE.into(),
+                &[],
+            ),
+            Err(NestedError::Encode(EncodeError::PayloadTooLarge { .. }))
+        ));
+
+        let payload = &outer_hops[0].payload;
+        assert_eq!(
+            payload.onion::<INNER_LEN>(ONION_TYPE.into()),
+            Err(NestedError::NoInnerOnion(ONION_TYPE.into()))
+        );
+        assert_eq!(
+            payload.onion::<INNER_LEN>(2usize.into()),
+            Err(NestedError::InnerLenMismatch(2usize.into(), 1, 466))
+        );
+    }
+}
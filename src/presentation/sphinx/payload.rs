@@ -0,0 +1,251 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Ready-made Sphinx payload types.
+
+use std::io::{self, Read, Write};
+
+use addr::NodeId;
+use amplify::Wrapper;
+use lightning_encoding::{BigSize, LightningDecode, LightningEncode};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use super::{EncodeError, SphinxPayload};
+use crate::presentation::{tlv, Error};
+
+/// TLV type under which [`TlvHopPayload`] keeps node id of the next hop for
+/// the relayed onion packets
+pub const NEXT_NODE_TLV_TYPE: u64 = 4;
+
+/// BOLT-4 TLV hop payload: TLV stream prefixed with its BigSize-encoded
+/// length.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default, From)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct TlvHopPayload(tlv::Stream);
+
+impl TlvHopPayload {
+    /// Constructs hop payload from a TLV stream
+    #[inline]
+    pub fn with(stream: tlv::Stream) -> TlvHopPayload { TlvHopPayload(stream) }
+
+    /// Returns TLV stream of the payload
+    #[inline]
+    pub fn stream(&self) -> &tlv::Stream { &self.0 }
+
+    /// Returns mutable TLV stream of the payload
+    #[inline]
+    pub fn stream_mut(&mut self) -> &mut tlv::Stream { &mut self.0 }
+
+    /// Releases TLV stream of the payload
+    #[inline]
+    pub fn into_stream(self) -> tlv::Stream { self.0 }
+
+    /// Sets id of the node to which the relay must forward the onion packet
+    pub fn set_next_hop(&mut self, node_id: NodeId) {
+        self.0.insert(
+            NEXT_NODE_TLV_TYPE.into(),
+            node_id.public_key().serialize(),
+        );
+    }
+
+    /// Checks that the payload together with its HMAC fits into Sphinx packet
+    /// of `packet_len` bytes.
+    pub fn check_capacity(&self, packet_len: usize) -> Result<(), EncodeError> {
+        let payload_size = self.serialized_len();
+        if payload_size + 32 > packet_len {
+            return Err(EncodeError::PayloadTooLarge {
+                payload_size,
+                packet_size: packet_len,
+            });
+        }
+        Ok(())
+    }
+
+    fn stream_len(&self) -> usize {
+        (&self.0)
+            .into_iter()
+            .map(|(ty, value)| {
+                BigSize::from(ty.into_inner()).len()
+                    + BigSize::from(value.len()).len()
+                    + value.len()
+            })
+            .sum()
+    }
+}
+
+impl LightningEncode for TlvHopPayload {
+    fn lightning_encode<E: Write>(
+        &self,
+        mut e: E,
+    ) -> Result<usize, lightning_encoding::Error> {
+        let len = BigSize::from(self.stream_len()).lightning_encode(&mut e)?;
+        Ok(len + self.0.lightning_encode(e)?)
+    }
+}
+
+impl LightningDecode for TlvHopPayload {
+    fn lightning_decode<D: Read>(
+        d: D,
+    ) -> Result<Self, lightning_encoding::Error> {
+        TlvHopPayload::decode(d).map_err(|err| match err {
+            Error::Io(err) => lightning_encoding::Error::Io(err),
+            err => {
+                lightning_encoding::Error::DataIntegrityError(err.to_string())
+            }
+        })
+    }
+}
+
+impl SphinxPayload for TlvHopPayload {
+    type DecodeError = Error;
+
+    fn serialized_len(&self) -> usize {
+        let stream_len = self.stream_len();
+        BigSize::from(stream_len).len() + stream_len
+    }
+
+    fn encode(&self, writer: impl Write) -> Result<usize, io::Error> {
+        self.lightning_encode(writer).map_err(|err| match err {
+            lightning_encoding::Error::Io(err) => err.into(),
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        })
+    }
+
+    fn decode(mut reader: impl Read) -> Result<Self, Self::DecodeError> {
+        let len = BigSize::lightning_decode(&mut reader)
+            .map_err(|_| Error::BadLengthDescriptor)?
+            .into_inner();
+        let mut data = Vec::new();
+        reader.take(len).read_to_end(&mut data)?;
+        if data.len() as u64 != len {
+            return Err(Error::BadLengthDescriptor);
+        }
+        tlv::Stream::read_records(&data[..]).map(TlvHopPayload)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::hex::{FromHex, ToHex};
+    use secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+    use super::super::test::test_route;
+    use super::super::{Hop, OnionPacket, Peeled};
+    use super::*;
+
+    const PACKET_LEN: usize = 20 * 65;
+
+    fn payload(amount: u8, cltv: u8) -> TlvHopPayload {
+        let mut stream = tlv::Stream::new();
+        stream.insert(2usize.into(), [amount]);
+        stream.insert(4usize.into(), [cltv]);
+        TlvHopPayload::with(stream)
+    }
+
+    #[test]
+    fn encoding() {
+        let payload = payload(0x01, 0x02);
+        assert_eq!(payload.serialized_len(), 7);
+        assert_eq!(payload.serialize().to_hex(), "06020101040102");
+        assert_eq!(
+            TlvHopPayload::decode(&payload.serialize()[..]).unwrap(),
+            payload
+        );
+        assert_eq!(
+            TlvHopPayload::lightning_deserialize(
+                payload.lightning_serialize().unwrap()
+            )
+            .unwrap(),
+            payload
+        );
+        assert_eq!(
+            TlvHopPayload::strict_deserialize(
+                payload.strict_serialize().unwrap()
+            )
+            .unwrap(),
+            payload
+        );
+    }
+
+    #[test]
+    fn decoding_errors() {
+        let data = Vec::from_hex("08020101040102").unwrap();
+        assert_eq!(
+            TlvHopPayload::decode(&data[..]),
+            Err(Error::BadLengthDescriptor)
+        );
+        let data = Vec::from_hex("06040102020101").unwrap();
+        assert_eq!(
+            TlvHopPayload::decode(&data[..]),
+            Err(Error::TlvStreamWrongOrder)
+        );
+    }
+
+    #[test]
+    fn capacity() {
+        let mut stream = tlv::Stream::new();
+        stream.insert(2usize.into(), vec![0u8; PACKET_LEN - 32 - 7]);
+        let payload = TlvHopPayload::with(stream);
+        assert_eq!(payload.serialized_len(), PACKET_LEN - 32);
+        assert_eq!(payload.check_capacity(PACKET_LEN), Ok(()));
+
+        let mut stream = tlv::Stream::new();
+        stream.insert(2usize.into(), vec![0u8; PACKET_LEN]);
+        let payload = TlvHopPayload::with(stream);
+        let err = EncodeError::PayloadTooLarge {
+            payload_size: PACKET_LEN + 7,
+            packet_size: PACKET_LEN,
+        };
+        assert_eq!(payload.check_capacity(PACKET_LEN), Err(err));
+
+        let secp = Secp256k1::new();
+        let node_secret = SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let node_id = PublicKey::from_secret_key(&secp, &node_secret).into();
+        let session_key = SecretKey::from_slice(&[0x41; 32]).unwrap();
+        assert_eq!(
+            OnionPacket::<PACKET_LEN>::with_session_key(
+                &secp,
+                session_key,
+                &[Hop::with(node_id, payload)],
+                &[],
+            ),
+            Err(err)
+        );
+    }
+
+    #[test]
+    fn tlv_onion() {
+        let secp = Secp256k1::new();
+        let (node_secrets, hops) = test_route(&secp, 1..=3);
+        let session_key = SecretKey::from_slice(&[0x41; 32]).unwrap();
+        let mut onion = OnionPacket::<PACKET_LEN>::with_session_key(
+            &secp,
+            session_key,
+            &hops,
+            &[],
+        )
+        .unwrap();
+
+        for (index, node_secret) in node_secrets.iter().enumerate() {
+            let peeled = onion
+                .peel::<_, TlvHopPayload>(&secp, *node_secret, &[])
+                .unwrap();
+            assert_eq!(peeled.payload(), &hops[index].payload);
+            if let Peeled::Forward { next_onion, .. } = peeled {
+                onion = next_onion;
+            } else {
+                assert_eq!(index, 2);
+            }
+        }
+    }
+}
@@ -0,0 +1,314 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Replay protection for the processed onion packets.
+//!
+//! Each onion packet produces a unique shared secret with the processing node,
+//! so the node may detect packet replays by remembering [`ReplayTag`]s derived
+//! from the shared secrets of already processed packets. The tags are kept by
+//! a [`ReplayStore`], which may be persistent; [`ReplayCache`] provides
+//! in-memory store with bounded capacity and optional tag expiry.
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::error::Error as StdError;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+use amplify::Wrapper;
+use bitcoin_hashes::{sha256, Hash};
+use lightning_encoding::{LightningDecode, LightningEncode};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+/// Tag identifying processed onion packet, computed as a hash of the shared
+/// secret between the packet sender and the processing node
+#[derive(
+    Wrapper, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display,
+    From
+)]
+#[derive(LightningEncode, LightningDecode)]
+#[derive(StrictEncode, StrictDecode)]
+#[display(inner)]
+pub struct ReplayTag(sha256::Hash);
+
+impl ReplayTag {
+    /// Computes replay tag for the onion packet with a given shared secret
+    /// (see [`super::OnionPacket::shared_secret`])
+    pub fn with(shared_secret: sha256::Hash) -> ReplayTag {
+        ReplayTag(sha256::Hash::hash(&shared_secret[..]))
+    }
+}
+
+/// Storage of the replay tags for the already processed packets
+pub trait ReplayStore {
+    /// Error happening during storage access
+    type Error: StdError;
+
+    /// Registers tag of a newly processed packet, returning `false` if the tag
+    /// was already known, i.e. the packet is replayed.
+    fn register(&mut self, tag: ReplayTag) -> Result<bool, Self::Error>;
+}
+
+/// Errors from unfolding onion packet with replay protection
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ReplayError<D, S> {
+    /// Onion packet HMAC is invalid
+    InvalidHmac,
+
+    /// Onion packet was already processed
+    Replay(ReplayTag),
+
+    /// Onion payload decoding error
+    Decode(D),
+
+    /// Replay store access error
+    Store(S),
+}
+
+impl<D, S> Display for ReplayError<D, S>
+where
+    D: Display,
+    S: Display,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::InvalidHmac => {
+                f.write_str("invalid onion packet HMAC")
+            }
+            ReplayError::Replay(tag) => {
+                write!(f, "replay of already processed onion packet {}", tag)
+            }
+            ReplayError::Decode(err) => Display::fmt(err, f),
+            ReplayError::Store(err) => {
+                write!(f, "onion replay store failure: {}", err)
+            }
+        }
+    }
+}
+
+impl<D, S> StdError for ReplayError<D, S>
+where
+    D: StdError + 'static,
+    S: StdError + 'static,
+{
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            ReplayError::InvalidHmac | ReplayError::Replay(_) => None,
+            ReplayError::Decode(err) => Some(err),
+            ReplayError::Store(err) => Some(err),
+        }
+    }
+}
+
+/// In-memory replay store keeping a bounded number of the most recent replay
+/// tags, optionally forgetting tags after a given time-to-live.
+///
+/// When the capacity is exhausted the oldest tags are evicted first, so the
+/// capacity must be selected such that it covers all packets which may be
+/// received during the time the packets remain valid for the application.
+/// The capacity must be non-zero, since a cache which can't keep any tags
+/// would accept all replayed packets.
+#[derive(Clone, Debug)]
+pub struct ReplayCache {
+    capacity: usize,
+    ttl: Option<Duration>,
+    tags: HashMap<ReplayTag, Instant>,
+    queue: VecDeque<ReplayTag>,
+}
+
+impl ReplayCache {
+    /// Constructs replay cache keeping at most `capacity` tags without expiry
+    ///
+    /// # Panics
+    /// If `capacity` is zero, since such cache would not provide any replay
+    /// protection
+    pub fn with_capacity(capacity: usize) -> ReplayCache {
+        assert!(capacity > 0, "replay cache capacity must be non-zero");
+        ReplayCache {
+            capacity,
+            ttl: None,
+            tags: empty!(),
+            queue: empty!(),
+        }
+    }
+
+    /// Constructs replay cache keeping at most `capacity` tags, each for no
+    /// longer than `ttl`
+    ///
+    /// # Panics
+    /// If `capacity` is zero, since such cache would not provide any replay
+    /// protection
+    pub fn with_expiry(capacity: usize, ttl: Duration) -> ReplayCache {
+        ReplayCache {
+            ttl: Some(ttl),
+            ..ReplayCache::with_capacity(capacity)
+        }
+    }
+
+    /// Returns maximum number of tags kept by the cache
+    #[inline]
+    pub fn capacity(&self) -> usize { self.capacity }
+
+    /// Returns number of tags currently kept by the cache
+    #[inline]
+    pub fn len(&self) -> usize { self.tags.len() }
+
+    /// Detects whether the cache does not contain any tags
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.tags.is_empty() }
+
+    /// Checks whether the tag is known to the cache and has not expired yet
+    #[inline]
+    pub fn contains(&self, tag: &ReplayTag) -> bool {
+        self.contains_at(tag, Instant::now())
+    }
+
+    fn contains_at(&self, tag: &ReplayTag, now: Instant) -> bool {
+        match (self.tags.get(tag), self.ttl) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(time), Some(ttl)) => now.duration_since(*time) < ttl,
+        }
+    }
+
+    fn register_at(&mut self, tag: ReplayTag, now: Instant) -> bool {
+        self.evict_expired(now);
+        if self.tags.contains_key(&tag) {
+            return false;
+        }
+        if self.queue.len() >= self.capacity {
+            if let Some(evicted) = self.queue.pop_front() {
+                self.tags.remove(&evicted);
+            }
+        }
+        self.tags.insert(tag, now);
+        self.queue.push_back(tag);
+        true
+    }
+
+    fn evict_expired(&mut self, now: Instant) {
+        if self.ttl.is_none() {
+            return;
+        }
+        // Tags are queued in the order of their registration time, so we stop
+        // on the first non-expired one
+        while let Some(tag) = self.queue.front() {
+            if self.contains_at(tag, now) {
+                break;
+            }
+            self.tags.remove(tag);
+            self.queue.pop_front();
+        }
+    }
+}
+
+impl ReplayStore for ReplayCache {
+    type Error = Infallible;
+
+    #[inline]
+    fn register(&mut self, tag: ReplayTag) -> Result<bool, Self::Error> {
+        Ok(self.register_at(tag, Instant::now()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin_hashes::Hmac;
+    use secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+    use super::super::{Hop, OnionPacket};
+    use super::*;
+
+    fn tag(byte: u8) -> ReplayTag {
+        ReplayTag::with(sha256::Hash::from_inner([byte; 32]))
+    }
+
+    #[test]
+    fn cache_capacity() {
+        let mut cache = ReplayCache::with_capacity(2);
+        assert_eq!(cache.register(tag(1)), Ok(true));
+        assert_eq!(cache.register(tag(2)), Ok(true));
+        assert_eq!(cache.register(tag(1)), Ok(false));
+        assert_eq!(cache.len(), 2);
+
+        assert_eq!(cache.register(tag(3)), Ok(true));
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains(&tag(1)));
+        assert!(cache.contains(&tag(2)));
+        assert!(cache.contains(&tag(3)));
+        assert_eq!(cache.register(tag(1)), Ok(true));
+    }
+
+    #[test]
+    #[should_panic(expected = "replay cache capacity must be non-zero")]
+    fn cache_zero_capacity() { ReplayCache::with_capacity(0); }
+
+    #[test]
+    fn cache_expiry() {
+        let ttl = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut cache = ReplayCache::with_expiry(16, ttl);
+        assert!(cache.register_at(tag(1), start));
+        assert!(!cache.register_at(tag(1), start + ttl / 2));
+        assert!(cache.contains_at(&tag(1), start + ttl / 2));
+
+        let expired = start + ttl;
+        assert!(!cache.contains_at(&tag(1), expired));
+        assert!(cache.register_at(tag(2), expired));
+        assert_eq!(cache.len(), 1);
+        assert!(cache.register_at(tag(1), expired));
+        assert!(!cache.register_at(tag(2), expired + ttl / 2));
+    }
+
+    #[test]
+    fn unfold_once() {
+        let secp = Secp256k1::new();
+        let node_secret = SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let node_id = PublicKey::from_secret_key(&secp, &node_secret).into();
+        let session_key = SecretKey::from_slice(&[0x41; 32]).unwrap();
+        let hops = vec![Hop::with(node_id, vec![0xA5u8; 45])];
+        let onion = OnionPacket::<{ 20 * 65 }>::with_session_key(
+            &secp,
+            session_key,
+            &hops,
+            b"assoc",
+        )
+        .unwrap();
+
+        let mut cache = ReplayCache::with_capacity(16);
+
+        // Forged packet with the same ephemeral key must not take over the
+        // replay tag of the valid one
+        let mut forged = onion;
+        forged.hmac = Hmac::from_inner([0xFF; 32]);
+        let err = forged
+            .unfold_once::<Vec<u8>, _>(node_secret, b"assoc", &mut cache)
+            .unwrap_err();
+        assert!(matches!(err, ReplayError::InvalidHmac));
+        assert!(cache.is_empty());
+
+        let mut first = onion;
+        let payload: Vec<u8> = first
+            .unfold_once(node_secret, b"assoc", &mut cache)
+            .unwrap();
+        assert_eq!(payload, vec![0xA5u8; 45]);
+
+        let mut replay = onion;
+        let tag = ReplayTag::with(onion.shared_secret(node_secret));
+        let err = replay
+            .unfold_once::<Vec<u8>, _>(node_secret, b"assoc", &mut cache)
+            .unwrap_err();
+        assert!(matches!(err, ReplayError::Replay(t) if t == tag));
+        assert_eq!(replay, onion);
+    }
+}
@@ -0,0 +1,305 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Route keys for constructing a batch of onion packets along the same route.
+//!
+//! Each onion packet must use its own session key: packets sharing a session
+//! key produce the same shared secrets at every hop, so their encrypted hop
+//! data are XORed with the same cipher streams and route nodes see them as
+//! replays of each other. For the same reason the per-hop blinding can't be
+//! cached and re-used across packets. Thus each packet still requires ECDH and
+//! key blinding with every node of the route, and [`RouteKeys`] do not reduce
+//! the total cost of constructing a batch of packets: they only allow to
+//! perform the elliptic curve operations ahead of time, off the packet
+//! construction path. Keys for the following packets are derived with
+//! [`RouteKeys::next`], and each of the keys is consumed by
+//! [`OnionPacket::with_route_keys`], which uses only symmetric cryptography.
+
+use std::mem;
+
+use addr::NodeId;
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use secp256k1::{PublicKey, Secp256k1, SecretKey, Signing};
+
+use super::{
+    route_keys, EncodeError, Hop, OnionPacket, SphinxPacket, SphinxPayload,
+};
+
+/// Tag used in derivation of the next session key of [`RouteKeys`]
+const NEXT_SESSION_KEY_TAG: &[u8] = b"inet2:sphinx:next-session-key";
+
+/// Shared secrets and ephemeral public keys for each of the onion route hops,
+/// derived from the session key of a single onion packet.
+///
+/// The keys are consumed by [`OnionPacket::with_route_keys`], so they can't be
+/// used for constructing more than one packet.
+#[derive(PartialEq, Eq, Hash, Debug)]
+pub struct RouteKeys {
+    session_key: SecretKey,
+    route: Vec<NodeId>,
+    shared_secrets: Vec<sha256::Hash>,
+    ephemeral_keys: Vec<PublicKey>,
+}
+
+impl RouteKeys {
+    /// Computes route keys generating random session key from a standard
+    /// randomness source.
+    ///
+    /// Requires compilation with `keygen` feature.
+    #[cfg(feature = "keygen")]
+    pub fn with<C>(
+        secp: &Secp256k1<C>,
+        route: impl IntoIterator<Item = NodeId>,
+    ) -> Result<Self, EncodeError>
+    where
+        C: Signing,
+    {
+        let mut rng = secp256k1::rand::thread_rng();
+        let session_key = SecretKey::new(&mut rng);
+
+        RouteKeys::with_session_key(secp, session_key, route)
+    }
+
+    /// Computes route keys using provided pre-generated session key.
+    pub fn with_session_key<C>(
+        secp: &Secp256k1<C>,
+        session_key: SecretKey,
+        route: impl IntoIterator<Item = NodeId>,
+    ) -> Result<Self, EncodeError>
+    where
+        C: Signing,
+    {
+        let route = route.into_iter().collect::<Vec<_>>();
+        if route.is_empty() {
+            return Err(EncodeError::EmptyRoute);
+        }
+        let (shared_secrets, ephemeral_keys) =
+            route_keys(secp, route.iter().copied(), session_key);
+        Ok(RouteKeys {
+            session_key,
+            route,
+            shared_secrets,
+            ephemeral_keys,
+        })
+    }
+
+    /// Returns session key of the onion packet, from which the route keys
+    /// were derived
+    #[inline]
+    pub fn session_key(&self) -> SecretKey { self.session_key }
+
+    /// Returns node ids of the onion route
+    #[inline]
+    pub fn route(&self) -> &[NodeId] { &self.route }
+
+    /// Returns shared secrets of the onion packet with each of the route nodes
+    #[inline]
+    pub fn shared_secrets(&self) -> &[sha256::Hash] { &self.shared_secrets }
+
+    /// Returns ephemeral public keys which will be seen by each of the route
+    /// nodes in the onion packet
+    #[inline]
+    pub fn ephemeral_keys(&self) -> &[PublicKey] { &self.ephemeral_keys }
+
+    /// Returns route keys for the next onion packet, replacing them with the
+    /// keys derived from a new session key, which is a tagged hash of the
+    /// current one.
+    ///
+    /// Derivation of the new keys performs ECDH and key blinding for each of
+    /// the route nodes, so it costs exactly as much as
+    /// [`RouteKeys::with_session_key`]: there is no amortized saving across
+    /// packets. The function is intended only for preparing keys for a batch
+    /// of packets ahead of their construction.
+    pub fn next<C>(&mut self, secp: &Secp256k1<C>) -> RouteKeys
+    where
+        C: Signing,
+    {
+        let mut engine = sha256::Hash::engine();
+        engine.input(NEXT_SESSION_KEY_TAG);
+        engine.input(&self.session_key.secret_bytes());
+        let session_key =
+            SecretKey::from_slice(&sha256::Hash::from_engine(engine)[..])
+                .expect("negligible probability");
+        let (shared_secrets, ephemeral_keys) =
+            route_keys(secp, self.route.iter().copied(), session_key);
+        let next = RouteKeys {
+            session_key,
+            route: self.route.clone(),
+            shared_secrets,
+            ephemeral_keys,
+        };
+        mem::replace(self, next)
+    }
+}
+
+impl<const PACKET_LEN: usize> OnionPacket<PACKET_LEN> {
+    /// Assembles onion packet for the route of the `keys` from the provided
+    /// hop data, which node ids must match the route. Returns the packet
+    /// together with its shared secrets with each of the route nodes, which
+    /// are required for attributing failure packets (see
+    /// [`super::FailurePacket::attribute`]).
+    ///
+    /// Since all elliptic curve operations were performed when the keys were
+    /// computed, the construction involves only symmetric cryptography. The
+    /// keys are consumed, such that they can't be re-used for another packet;
+    /// use [`RouteKeys::next`] to prepare keys for a batch of packets.
+    pub fn with_route_keys<Payload>(
+        keys: RouteKeys,
+        hops: &[Hop<Payload>],
+        assoc_data: &[u8],
+    ) -> Result<(Self, Vec<sha256::Hash>), EncodeError>
+    where
+        Payload: SphinxPayload,
+    {
+        if !hops.iter().map(|hop| &hop.node_id).eq(&keys.route) {
+            return Err(EncodeError::RouteMismatch);
+        }
+
+        let (sphinx_packet, hmac) = SphinxPacket::with_shared_secrets(
+            keys.session_key,
+            hops,
+            &keys.shared_secrets,
+            assoc_data,
+        )?;
+
+        let onion = OnionPacket {
+            version: 0,
+            point: keys.ephemeral_keys[0],
+            packet: sphinx_packet,
+            hmac,
+        };
+        Ok((onion, keys.shared_secrets))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::test_route;
+    use super::super::{Peeled, TlvHopPayload};
+    use super::*;
+    use crate::presentation::tlv;
+
+    const PACKET_LEN: usize = 400;
+
+    fn payload(byte: u8) -> TlvHopPayload {
+        let mut stream = tlv::Stream::new();
+        stream.insert(2usize.into(), [byte]);
+        TlvHopPayload::with(stream)
+    }
+
+    fn route_keys() -> (Vec<SecretKey>, RouteKeys) {
+        let secp = Secp256k1::new();
+        let (secrets, hops) = test_route(&secp, 1..=3);
+        let route = hops.iter().map(|hop| hop.node_id);
+        let session_key = SecretKey::from_slice(&[0x41; 32]).unwrap();
+        let keys =
+            RouteKeys::with_session_key(&secp, session_key, route).unwrap();
+        (secrets, keys)
+    }
+
+    #[test]
+    fn route_keys_onion() {
+        let secp = Secp256k1::new();
+        let (_, keys) = route_keys();
+        let hops = keys
+            .route()
+            .iter()
+            .zip(1u8..)
+            .map(|(node_id, byte)| Hop::with(*node_id, payload(byte)))
+            .collect::<Vec<_>>();
+
+        let session_key = keys.session_key();
+        let shared_secrets = super::super::route_shared_secrets(
+            &secp,
+            keys.route().iter().copied(),
+            session_key,
+        );
+        assert_eq!(keys.shared_secrets(), shared_secrets);
+        assert_eq!(
+            OnionPacket::<PACKET_LEN>::with_route_keys(keys, &hops, b"assoc"),
+            OnionPacket::with_session_key(&secp, session_key, &hops, b"assoc")
+                .map(|onion| (onion, shared_secrets))
+        );
+    }
+
+    #[test]
+    fn batch_onions() {
+        let secp = Secp256k1::new();
+        let (secrets, mut keys) = route_keys();
+        // Keys for the whole batch are prepared before packet construction
+        let batch = (0u8..4).map(|_| keys.next(&secp)).collect::<Vec<_>>();
+
+        let mut all_secrets = vec![];
+        for (part, batch_keys) in (0u8..).zip(batch) {
+            let hops = batch_keys
+                .route()
+                .iter()
+                .zip(part..)
+                .map(|(node_id, byte)| Hop::with(*node_id, payload(byte)))
+                .collect::<Vec<_>>();
+            let ephemeral_keys = batch_keys.ephemeral_keys().to_vec();
+            let (mut onion, shared_secrets) =
+                OnionPacket::<PACKET_LEN>::with_route_keys(
+                    batch_keys,
+                    &hops,
+                    &[part],
+                )
+                .unwrap();
+            for (index, secret) in secrets.iter().enumerate() {
+                assert_eq!(onion.point, ephemeral_keys[index]);
+                let shared_secret = onion.shared_secret(*secret);
+                assert_eq!(shared_secret, shared_secrets[index]);
+                // Packets of the batch must not share keys at any hop
+                assert!(!all_secrets.contains(&shared_secret));
+                all_secrets.push(shared_secret);
+                let peeled = onion
+                    .peel::<_, TlvHopPayload>(&secp, *secret, &[part])
+                    .unwrap();
+                assert_eq!(peeled.payload(), &hops[index].payload);
+                match peeled {
+                    Peeled::Forward { next_onion, .. } => onion = next_onion,
+                    Peeled::Final { .. } => assert_eq!(index, 2),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn route_keys_errors() {
+        let secp = Secp256k1::new();
+        let session_key = SecretKey::from_slice(&[0x41; 32]).unwrap();
+        assert_eq!(
+            RouteKeys::with_session_key(&secp, session_key, None),
+            Err(EncodeError::EmptyRoute)
+        );
+
+        let (_, keys) = route_keys();
+        let hops = vec![Hop::with(keys.route()[0], payload(1))];
+        assert_eq!(
+            OnionPacket::<PACKET_LEN>::with_route_keys(keys, &hops, &[]),
+            Err(EncodeError::RouteMismatch)
+        );
+
+        let (_, keys) = route_keys();
+        let mut hops = keys
+            .route()
+            .iter()
+            .map(|node_id| Hop::with(*node_id, payload(1)))
+            .collect::<Vec<_>>();
+        hops.swap(0, 1);
+        assert_eq!(
+            OnionPacket::<PACKET_LEN>::with_route_keys(keys, &hops, &[]),
+            Err(EncodeError::RouteMismatch)
+        );
+    }
+}
@@ -0,0 +1,364 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Single-use reply blocks (SURBs), allowing the recipient of an onion
+//! message to reply without learning the route back to the sender.
+//!
+//! The sender constructs [`ReplyBlock`] for a route ending at itself and
+//! sends it to the recipient, keeping [`ReplyKeys`] locally. The recipient
+//! seals reply data with [`ReplyBlock::reply`] and sends the produced
+//! [`Reply`] to the first hop of the block. Each of the intermediate hops
+//! processes the reply with [`Reply::peel`], obfuscating its data, and the
+//! sender finally decrypts the data with [`ReplyKeys::decrypt`].
+//!
+//! Reply data are padded before sealing, such that all replies have
+//! `PACKET_LEN` bytes of data regardless of the actual data size, and can't be
+//! linked across the hops by their size.
+
+use addr::NodeId;
+use bitcoin_hashes::sha256;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use lightning_encoding::{LightningDecode, LightningEncode};
+use secp256k1::{Secp256k1, SecretKey, Signing, Verification};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use super::{
+    apply_cipher_stream, construct_shared_secrets, generate_key, EncodeError,
+    Hop, OnionPacket, PeelError, Peeled, ReplayTag, SphinxPayload,
+};
+
+const REPLY_KEY: &[u8] = b"reply";
+const SURB_KEY: &[u8] = b"surb";
+
+/// Size of the authentication tag of the sealed reply data
+const REPLY_TAG_LEN: usize = 16;
+
+/// Size of the data length prefix and authentication tag added to the padded
+/// reply data
+const REPLY_OVERHEAD: usize = 4 + REPLY_TAG_LEN;
+
+/// Errors sealing and decrypting reply data
+#[derive(
+    Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Error
+)]
+#[display(doc_comments)]
+pub enum ReplyError {
+    /// reply data can't be decrypted with the reply block keys
+    Decryption,
+
+    /// reply data size {data_len} exceeds maximum of {max_len} bytes
+    DataTooLarge { data_len: usize, max_len: usize },
+}
+
+/// Single-use reply block given by the sender to the recipient
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(LightningEncode, LightningDecode)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct ReplyBlock<const PACKET_LEN: usize> {
+    /// First node of the reply route, to which the reply must be sent
+    pub first_hop: NodeId,
+
+    /// Onion header for the reply route
+    pub onion: OnionPacket<PACKET_LEN>,
+
+    /// Key which must be used by the recipient to seal reply data
+    pub payload_key: [u8; 32],
+}
+
+/// Keys kept by the sender for decrypting reply created from a
+/// [`ReplyBlock`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(LightningEncode, LightningDecode)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct ReplyKeys {
+    tag: ReplayTag,
+    payload_key: [u8; 32],
+    shared_secrets: Vec<sha256::Hash>,
+}
+
+/// Reply travelling back to the sender over the reply route
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(LightningEncode, LightningDecode)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct Reply<const PACKET_LEN: usize> {
+    /// Onion header for the rest of the reply route
+    pub onion: OnionPacket<PACKET_LEN>,
+
+    /// Obfuscated reply data, which are always `PACKET_LEN` bytes long
+    pub data: Vec<u8>,
+}
+
+impl<const PACKET_LEN: usize> ReplyBlock<PACKET_LEN> {
+    /// Constructs reply block for the route, generating random session key
+    /// from a standard randomness source.
+    ///
+    /// Requires compilation with `keygen` feature.
+    #[cfg(feature = "keygen")]
+    pub fn with<C, Payload>(
+        secp: &Secp256k1<C>,
+        hops: &[Hop<Payload>],
+        assoc_data: &[u8],
+    ) -> Result<(Self, ReplyKeys), EncodeError>
+    where
+        C: Signing,
+        Payload: SphinxPayload,
+    {
+        let mut rng = secp256k1::rand::thread_rng();
+        let session_key = SecretKey::new(&mut rng);
+
+        ReplyBlock::with_session_key(secp, session_key, hops, assoc_data)
+    }
+
+    /// Constructs reply block for the route, which must end with the sender
+    /// node, using provided pre-generated session key. Returns the block for
+    /// the recipient together with the keys which must be kept by the sender
+    /// to decrypt the reply.
+    ///
+    /// NB: The session keys must not be re-used!
+    pub fn with_session_key<C, Payload>(
+        secp: &Secp256k1<C>,
+        session_key: SecretKey,
+        hops: &[Hop<Payload>],
+        assoc_data: &[u8],
+    ) -> Result<(Self, ReplyKeys), EncodeError>
+    where
+        C: Signing,
+        Payload: SphinxPayload,
+    {
+        let first_hop = hops
+            .first()
+            .map(|hop| hop.node_id)
+            .ok_or(EncodeError::EmptyRoute)?;
+        let onion =
+            OnionPacket::with_session_key(secp, session_key, hops, assoc_data)?;
+        let payload_key = generate_key(SURB_KEY, &session_key[..]);
+        let shared_secrets = construct_shared_secrets(secp, hops, session_key);
+
+        let block = ReplyBlock {
+            first_hop,
+            onion,
+            payload_key,
+        };
+        let keys = ReplyKeys {
+            tag: ReplayTag::with(
+                *shared_secrets
+                    .last()
+                    .expect("route is checked to be non-empty"),
+            ),
+            payload_key,
+            shared_secrets,
+        };
+        Ok((block, keys))
+    }
+
+    /// Seals reply data with the block keys, padding them to `PACKET_LEN`
+    /// bytes. The block is consumed, since it must not be used more than once.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ReplyError::DataTooLarge`] if the data exceed
+    /// [`Reply::MAX_DATA_LEN`] bytes.
+    pub fn reply(self, data: &[u8]) -> Result<Reply<PACKET_LEN>, ReplyError> {
+        let max_len = Reply::<PACKET_LEN>::MAX_DATA_LEN;
+        let data_len = data.len();
+        if data_len > max_len {
+            return Err(ReplyError::DataTooLarge { data_len, max_len });
+        }
+        let mut padded = Vec::with_capacity(PACKET_LEN);
+        padded.extend((data_len as u32).to_be_bytes());
+        padded.extend(data);
+        padded.resize(PACKET_LEN - REPLY_TAG_LEN, 0);
+        let data = ChaCha20Poly1305::new(Key::from_slice(&self.payload_key))
+            .encrypt(Nonce::from_slice(&[0u8; 12]), &padded[..])
+            .expect("ChaCha20Poly1305 encryption of in-memory data");
+        Ok(Reply {
+            onion: self.onion,
+            data,
+        })
+    }
+}
+
+impl<const PACKET_LEN: usize> Reply<PACKET_LEN> {
+    /// Maximum size of the data which can be sent in a reply
+    pub const MAX_DATA_LEN: usize = PACKET_LEN - REPLY_OVERHEAD;
+
+    /// Peels outer layer of the reply onion (see [`OnionPacket::peel`]).
+    /// Intermediate hops must forward the returned reply to the next node;
+    /// the final hop (i.e. the reply block creator) receives the reply data
+    /// which must be decrypted with [`ReplyKeys::decrypt`].
+    pub fn peel<C, Payload>(
+        self,
+        secp: &Secp256k1<C>,
+        node_secret: SecretKey,
+        assoc_data: &[u8],
+    ) -> Result<(Payload, Self), PeelError<Payload::DecodeError>>
+    where
+        C: Verification,
+        Payload: SphinxPayload,
+    {
+        let Reply { onion, mut data } = self;
+        match onion.peel(secp, node_secret, assoc_data)? {
+            Peeled::Forward {
+                payload,
+                next_onion,
+            } => {
+                obfuscate(&mut data, onion.shared_secret(node_secret));
+                Ok((payload, Reply {
+                    onion: next_onion,
+                    data,
+                }))
+            }
+            Peeled::Final { payload } => Ok((payload, Reply { onion, data })),
+        }
+    }
+
+    /// Returns replay tag of the reply for the node, which can be matched
+    /// against [`ReplyKeys::tag`] by the final hop to find keys for the reply
+    pub fn tag(&self, node_secret: SecretKey) -> ReplayTag {
+        ReplayTag::with(self.onion.shared_secret(node_secret))
+    }
+}
+
+impl ReplyKeys {
+    /// Returns replay tag of the final reply onion, which identifies reply
+    /// created from the corresponding reply block (see [`Reply::tag`]).
+    #[inline]
+    pub fn tag(&self) -> ReplayTag { self.tag }
+
+    /// Decrypts reply data received by the final hop. The keys are consumed,
+    /// since each reply block can be used only once.
+    pub fn decrypt(self, mut data: Vec<u8>) -> Result<Vec<u8>, ReplyError> {
+        let intermediate = self.shared_secrets.len().saturating_sub(1);
+        for shared_secret in &self.shared_secrets[..intermediate] {
+            obfuscate(&mut data, *shared_secret);
+        }
+        let mut padded =
+            ChaCha20Poly1305::new(Key::from_slice(&self.payload_key))
+                .decrypt(Nonce::from_slice(&[0u8; 12]), &data[..])
+                .map_err(|_| ReplyError::Decryption)?;
+        if padded.len() < 4 {
+            return Err(ReplyError::Decryption);
+        }
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&padded[..4]);
+        let len = u32::from_be_bytes(len) as usize;
+        if len > padded.len() - 4 {
+            return Err(ReplyError::Decryption);
+        }
+        padded.truncate(4 + len);
+        Ok(padded.split_off(4))
+    }
+}
+
+fn obfuscate(data: &mut [u8], shared_secret: sha256::Hash) {
+    let reply_key = generate_key(REPLY_KEY, shared_secret);
+    apply_cipher_stream(reply_key, data);
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::test_route;
+    use super::super::TlvHopPayload;
+    use super::*;
+
+    const PACKET_LEN: usize = 400;
+
+    fn reply_route() -> (Vec<SecretKey>, ReplyBlock<PACKET_LEN>, ReplyKeys) {
+        let secp = Secp256k1::new();
+        let (secrets, hops) = test_route(&secp, 1..=3);
+        let session_key = SecretKey::from_slice(&[0x41; 32]).unwrap();
+        let (block, keys) =
+            ReplyBlock::with_session_key(&secp, session_key, &hops, &[])
+                .unwrap();
+        assert_eq!(block.first_hop, hops[0].node_id);
+        (secrets, block, keys)
+    }
+
+    #[test]
+    fn reply_roundtrip() {
+        let secp = Secp256k1::new();
+        let (secrets, block, keys) = reply_route();
+
+        let mut reply = block.reply(b"pong").unwrap();
+        assert_eq!(reply.data.len(), PACKET_LEN);
+        for secret in &secrets {
+            let (_, next) =
+                reply.peel::<_, TlvHopPayload>(&secp, *secret, &[]).unwrap();
+            reply = next;
+            assert_eq!(reply.data.len(), PACKET_LEN);
+        }
+
+        assert_eq!(reply.tag(secrets[2]), keys.tag());
+        assert_eq!(keys.decrypt(reply.data), Ok(b"pong".to_vec()));
+    }
+
+    #[test]
+    fn reply_padding() {
+        let max_len = Reply::<PACKET_LEN>::MAX_DATA_LEN;
+        for data in [vec![], vec![0xAB; max_len]] {
+            let (_, block, _) = reply_route();
+            let reply = block.reply(&data).unwrap();
+            assert_eq!(reply.data.len(), PACKET_LEN);
+        }
+
+        let (_, block, _) = reply_route();
+        assert_eq!(
+            block.reply(&vec![0u8; max_len + 1]),
+            Err(ReplyError::DataTooLarge {
+                data_len: max_len + 1,
+                max_len
+            })
+        );
+    }
+
+    #[test]
+    fn reply_tampering() {
+        let secp = Secp256k1::new();
+        let (secrets, block, keys) = reply_route();
+
+        let mut reply = block.reply(b"pong").unwrap();
+        for secret in &secrets {
+            let (_, next) =
+                reply.peel::<_, TlvHopPayload>(&secp, *secret, &[]).unwrap();
+            reply = next;
+        }
+        reply.data[0] ^= 1;
+        assert_eq!(keys.decrypt(reply.data), Err(ReplyError::Decryption));
+    }
+
+    #[test]
+    fn reply_block_encoding() {
+        let (_, block, keys) = reply_route();
+
+        let data = block.strict_serialize().unwrap();
+        assert_eq!(ReplyBlock::strict_deserialize(&data), Ok(block.clone()));
+        let data = block.lightning_serialize().unwrap();
+        assert_eq!(
+            data.len(),
+            33 + OnionPacket::<PACKET_LEN>::SERIALIZED_LEN + 32
+        );
+        assert_eq!(ReplyBlock::lightning_deserialize(&data), Ok(block.clone()));
+
+        let data = keys.strict_serialize().unwrap();
+        assert_eq!(ReplyKeys::strict_deserialize(&data), Ok(keys.clone()));
+        let data = keys.lightning_serialize().unwrap();
+        assert_eq!(ReplyKeys::lightning_deserialize(&data), Ok(keys));
+
+        let reply = block.reply(b"pong").unwrap();
+        let data = reply.lightning_serialize().unwrap();
+        assert_eq!(Reply::lightning_deserialize(&data), Ok(reply.clone()));
+        let data = reply.strict_serialize().unwrap();
+        assert_eq!(Reply::strict_deserialize(&data), Ok(reply));
+    }
+}
@@ -18,7 +18,7 @@ use std::io::{Read, Write};
 use std::sync::Arc;
 
 use amplify::Wrapper;
-use lightning_encoding::{self, BigSize, LightningDecode};
+use lightning_encoding::{self, BigSize, LightningDecode, LightningEncode};
 use strict_encoding::TlvError;
 
 use super::{Error, EvenOdd, Unmarshall, UnmarshallFn};
@@ -88,9 +88,9 @@ impl lightning_encoding::LightningEncode for RawValue {
         mut e: E,
     ) -> Result<usize, lightning_encoding::Error> {
         let count = BigSize::from(self.len());
-        count.lightning_encode(&mut e)?;
+        let len = count.lightning_encode(&mut e)?;
         e.write_all(&self.0)?;
-        Ok(self.len() + count.into_inner() as usize)
+        Ok(self.len() + len)
     }
 }
 
@@ -148,6 +148,42 @@ impl Stream {
 
     #[inline]
     pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// Reads TLV records until the end of the data, checking that they are
+    /// ordered and not repeated. Unlike [`Stream::read_extension`], records
+    /// with even types are allowed.
+    pub fn read_records(reader: impl Read) -> Result<Stream, Error> {
+        Stream::lightning_decode(reader).map_err(|err| match err {
+            lightning_encoding::Error::Tlv(TlvError::Repeated(_)) => {
+                Error::TlvStreamDuplicateItem
+            }
+            lightning_encoding::Error::Tlv(TlvError::Order { .. }) => {
+                Error::TlvStreamWrongOrder
+            }
+            err => err.into(),
+        })
+    }
+
+    /// Reads TLV stream used as a message extension according to BOLT-1: the
+    /// stream occupies all bytes remaining in the message after its payload
+    /// and is empty if there are no such bytes.
+    ///
+    /// All records of the extension stream are treated as unknown, so the
+    /// function fails with [`Error::TlvRecordEvenType`] if the stream contains
+    /// records with even type ids.
+    pub fn read_extension(reader: impl Read) -> Result<Stream, Error> {
+        let stream = Stream::read_records(reader)?;
+        if stream.0.keys().any(Type::is_even) {
+            return Err(Error::TlvRecordEvenType);
+        }
+        Ok(stream)
+    }
+
+    /// Writes TLV stream as a message extension according to BOLT-1, i.e.
+    /// without the length prefix. Empty stream produces no data.
+    pub fn write_extension(&self, writer: impl Write) -> Result<usize, Error> {
+        self.lightning_encode(writer).map_err(Error::from)
+    }
 }
 
 impl strict_encoding::StrictEncode for Stream {
@@ -13,7 +13,7 @@
 
 use std::any::Any;
 use std::collections::BTreeMap;
-use std::io;
+use std::io::{self, Read};
 use std::marker::PhantomData;
 use std::sync::Arc;
 
@@ -21,7 +21,12 @@ use amplify::Wrapper;
 use lightning_encoding::LightningDecode;
 use strict_encoding::{self, StrictDecode};
 
-use super::{EncodingType, Error, EvenOdd, Payload, TypeId, TypedEnum};
+use super::{
+    EncodingType, Error, EvenOdd, MessageVersion, Payload, TypeId, TypedEnum,
+};
+
+/// Length of the encoded message type id, which is the same for all encodings
+const TYPE_ID_LEN: usize = 2;
 
 pub trait Unmarshall {
     type Data;
@@ -37,6 +42,14 @@ pub type UnmarshallFn<E> =
 
 pub trait CreateUnmarshaller: Sized + TypedEnum {
     fn create_unmarshaller() -> Unmarshaller<Self>;
+
+    /// Creates unmarshaller accepting only messages supported by the
+    /// negotiated protocol `version`
+    fn create_versioned_unmarshaller(version: u16) -> Unmarshaller<Self> {
+        let mut unmarshaller = Self::create_unmarshaller();
+        unmarshaller.set_protocol_version(version);
+        unmarshaller
+    }
 }
 
 pub struct Unmarshaller<T>
@@ -44,6 +57,11 @@ where
     T: TypedEnum,
 {
     known_types: BTreeMap<TypeId, UnmarshallFn<Error>>,
+    versions: BTreeMap<TypeId, MessageVersion>,
+    max_lens: BTreeMap<TypeId, usize>,
+    max_payload_len: Option<usize>,
+    protocol_version: Option<u16>,
+    reject_deprecated: bool,
     encoding: EncodingType,
     _phantom: PhantomData<T>,
 }
@@ -59,24 +77,8 @@ where
         &self,
         mut reader: impl io::Read,
     ) -> Result<Self::Data, Self::Error> {
-        let type_id = match self.encoding {
-            EncodingType::Lightning => TypeId::lightning_decode(&mut reader)?,
-            EncodingType::Strict => TypeId::strict_decode(&mut reader)?,
-        };
-        match self.known_types.get(&type_id) {
-            None if type_id.is_even() => Err(Error::MessageEvenType(type_id)),
-            None => {
-                let mut payload = Vec::new();
-                reader.read_to_end(&mut payload)?;
-                Ok(Arc::new(T::try_from_type(type_id, &Payload {
-                    type_id,
-                    payload,
-                })?))
-            }
-            Some(parser) => parser(&mut reader).and_then(|data| {
-                Ok(Arc::new(T::try_from_type(type_id, &*data)?))
-            }),
-        }
+        let type_id = self.unmarshall_type(&mut reader)?;
+        self.unmarshall_payload(type_id, reader)
     }
 }
 
@@ -93,8 +95,245 @@ where
                 .into_iter()
                 .map(|(t, f)| (TypeId::from_inner(t), f))
                 .collect(),
+            versions: empty!(),
+            max_lens: empty!(),
+            max_payload_len: None,
+            protocol_version: None,
+            reject_deprecated: false,
             encoding,
             _phantom: PhantomData,
         }
     }
+
+    /// Adds protocol version metadata for the known message types
+    pub fn with_versions(
+        mut self,
+        versions: BTreeMap<u16, MessageVersion>,
+    ) -> Self {
+        self.versions = versions
+            .into_iter()
+            .map(|(t, v)| (TypeId::from_inner(t), v))
+            .collect();
+        self
+    }
+
+    /// Adds maximum payload sizes for the known message types, which take
+    /// precedence over the global limit set with
+    /// [`Unmarshaller::set_max_payload_len`]
+    pub fn with_max_lens(mut self, max_lens: BTreeMap<u16, usize>) -> Self {
+        self.max_lens = max_lens
+            .into_iter()
+            .map(|(t, len)| (TypeId::from_inner(t), len))
+            .collect();
+        self
+    }
+
+    /// Returns maximum payload size applied to the messages without
+    /// type-specific limit, if any
+    #[inline]
+    pub fn max_payload_len(&self) -> Option<usize> { self.max_payload_len }
+
+    /// Sets maximum payload size applied to the messages without
+    /// type-specific limit. By default the payload size is not limited, which
+    /// is safe only for the transports capping the frame size.
+    ///
+    /// Messages exceeding the limit are rejected with
+    /// [`Error::PayloadTooLarge`]. Frames received into memory are checked
+    /// by [`Unmarshaller::unmarshall_frame`] before their payload is parsed;
+    /// messages read from a stream with [`Unmarshall::unmarshall`] are
+    /// checked while parsing, reading no more than a single byte past the
+    /// limit.
+    #[inline]
+    pub fn set_max_payload_len(&mut self, max_len: Option<usize>) {
+        self.max_payload_len = max_len
+    }
+
+    /// Sets maximum payload size for the messages of a given type
+    #[inline]
+    pub fn set_type_max_len(&mut self, type_id: TypeId, max_len: usize) {
+        self.max_lens.insert(type_id, max_len);
+    }
+
+    /// Returns maximum payload size applied to the messages of a given type,
+    /// if any
+    pub fn type_max_len(&self, type_id: TypeId) -> Option<usize> {
+        self.max_lens
+            .get(&type_id)
+            .copied()
+            .or(self.max_payload_len)
+    }
+
+    /// Returns maximum size of the message frame accepted by the
+    /// unmarshaller, consisting of the message type id and the payload. The
+    /// size is defined only if all payloads are limited, i.e. the global limit
+    /// is set with [`Unmarshaller::set_max_payload_len`].
+    ///
+    /// Transports receiving whole frames into memory, like ZMQ, should be
+    /// capped with this value (see
+    /// [`crate::session::LocalSession::connect_with_options`]).
+    pub fn max_frame_len(&self) -> Option<usize> {
+        let max_len = self
+            .max_lens
+            .values()
+            .copied()
+            .fold(self.max_payload_len?, usize::max);
+        Some(max_len.saturating_add(TYPE_ID_LEN))
+    }
+
+    /// Unmarshalls message from a frame which was already received into
+    /// memory. Unlike [`Unmarshall::unmarshall`], checks the payload size
+    /// against the limits before parsing it, such that oversized payloads
+    /// are rejected with [`Error::PayloadTooLarge`] without invoking the
+    /// decoders.
+    pub fn unmarshall_frame(&self, frame: &[u8]) -> Result<Arc<T>, Error> {
+        let mut reader = frame;
+        let type_id = self.unmarshall_type(&mut reader)?;
+        match self.type_max_len(type_id) {
+            Some(max_len) if reader.len() > max_len => {
+                Err(Error::PayloadTooLarge(type_id, max_len))
+            }
+            _ => self.unmarshall_payload(type_id, reader),
+        }
+    }
+
+    /// Returns protocol version negotiated with the remote peer, if any
+    #[inline]
+    pub fn protocol_version(&self) -> Option<u16> { self.protocol_version }
+
+    /// Sets protocol version negotiated with the remote peer. Once set,
+    /// messages introduced in later protocol versions are rejected with
+    /// [`Error::UnsupportedMessageVersion`].
+    #[inline]
+    pub fn set_protocol_version(&mut self, version: u16) {
+        self.protocol_version = Some(version)
+    }
+
+    /// Configures unmarshaller to reject deprecated messages with
+    /// [`Error::DeprecatedMessage`]. By default deprecated messages are
+    /// accepted; their status can be checked with [`TypedEnum::get_version`].
+    #[inline]
+    pub fn set_reject_deprecated(&mut self, reject: bool) {
+        self.reject_deprecated = reject
+    }
+}
+
+impl<T> Unmarshaller<T>
+where
+    T: TypedEnum,
+{
+    /// Decodes message type id and checks whether the message is supported
+    fn unmarshall_type(
+        &self,
+        mut reader: impl io::Read,
+    ) -> Result<TypeId, Error> {
+        let type_id = match self.encoding {
+            EncodingType::Lightning => TypeId::lightning_decode(&mut reader)?,
+            EncodingType::Strict => TypeId::strict_decode(&mut reader)?,
+            #[cfg(feature = "bitcoin")]
+            EncodingType::Bitcoin => {
+                use bitcoin::consensus::Decodable;
+                TypeId::consensus_decode(&mut reader)?
+            }
+            #[cfg(not(feature = "bitcoin"))]
+            EncodingType::Bitcoin => return Err(Error::NoEncoder),
+        };
+        if let Some(version) = self.versions.get(&type_id) {
+            if matches!(self.protocol_version, Some(v) if !version.is_supported_by(v))
+            {
+                return Err(Error::UnsupportedMessageVersion(type_id));
+            }
+            if self.reject_deprecated && version.deprecated {
+                return Err(Error::DeprecatedMessage(type_id));
+            }
+        }
+        Ok(type_id)
+    }
+
+    /// Parses payload of the message with a given type id
+    fn unmarshall_payload(
+        &self,
+        type_id: TypeId,
+        reader: impl io::Read,
+    ) -> Result<Arc<T>, Error> {
+        let max_len = self.type_max_len(type_id);
+        let mut reader = LimitedReader {
+            inner: reader,
+            remaining: max_len,
+            exceeded: false,
+        };
+        let result = match self.known_types.get(&type_id) {
+            None if type_id.is_even() => Err(Error::MessageEvenType(type_id)),
+            None => {
+                let mut payload = Vec::new();
+                reader
+                    .read_to_end(&mut payload)
+                    .map_err(Error::from)
+                    .and_then(|_| {
+                        Ok(Arc::new(T::try_from_type(type_id, &Payload {
+                            type_id,
+                            payload,
+                        })?))
+                    })
+            }
+            Some(parser) => parser(&mut reader).and_then(|data| {
+                Ok(Arc::new(T::try_from_type(type_id, &*data)?))
+            }),
+        };
+        // Parsers may swallow I/O errors (for instance, optional trailing
+        // data), so the limit is checked independently of the result
+        match max_len {
+            Some(max_len) if reader.exceeded => {
+                Err(Error::PayloadTooLarge(type_id, max_len))
+            }
+            _ => result,
+        }
+    }
+}
+
+/// Reader limiting the size of the message payload, which allows to detect
+/// oversized payloads while parsing them, without reading the whole payload
+/// into memory first.
+///
+/// Reads from the inner reader never exceed the data requested by the parser,
+/// and at most a single byte past the limit is read: it is passed to the
+/// parser as usual, but marks the payload as oversized, such that all further
+/// reads fail. Payloads of exactly the maximum size remain valid for the
+/// parsers reading till the end of the data.
+struct LimitedReader<R>
+where
+    R: io::Read,
+{
+    inner: R,
+    remaining: Option<usize>,
+    exceeded: bool,
+}
+
+impl<R> io::Read for LimitedReader<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.exceeded {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "message payload exceeds size limit",
+            ));
+        }
+        let remaining = match self.remaining {
+            None => return self.inner.read(buf),
+            Some(_) if buf.is_empty() => return Ok(0),
+            Some(remaining) => remaining,
+        };
+        if remaining == 0 {
+            // Payload of exactly the maximum size is valid, so we need to
+            // check whether there is more data
+            let read = self.inner.read(&mut buf[..1])?;
+            self.exceeded = read > 0;
+            return Ok(read);
+        }
+        let len = buf.len().min(remaining);
+        let read = self.inner.read(&mut buf[..len])?;
+        self.remaining = Some(remaining - read);
+        Ok(read)
+    }
 }
@@ -0,0 +1,244 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Publish/subscribe API working on top of ZMQ PUB/SUB [`LocalSession`]s.
+//!
+//! Each publication is sent as a two-part ZMQ message, where the first part
+//! is a topic and the second is a serialized [`TypedEnum`] message. ZMQ
+//! filters publications on the subscriber side by the topic prefix.
+//! Publications made with [`Publisher::publish_typed`] use a topic derived
+//! from the message [`TypeId`], so subscribers can filter them by the type.
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Display, Formatter};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use crate::presentation::{
+    CreateUnmarshaller, Error, TypeId, TypedEnum, Unmarshaller,
+};
+use crate::session::LocalSession;
+use crate::transport::{self, Pollable};
+
+/// Prefix used by topics derived from message type ids
+pub const TYPE_TOPIC_PREFIX: &str = "type/";
+
+/// Publication topic
+#[derive(
+    Wrapper, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug, From
+)]
+pub struct Topic(Vec<u8>);
+
+impl Topic {
+    /// Constructs topic for publications of a given message type. Topics of
+    /// this kind start with [`TYPE_TOPIC_PREFIX`] followed by four lowercase
+    /// hex digits of the type id.
+    pub fn with_type(type_id: TypeId) -> Topic {
+        Topic(format!("{}{:04x}", TYPE_TOPIC_PREFIX, type_id).into_bytes())
+    }
+}
+
+impl AsRef<[u8]> for Topic {
+    #[inline]
+    fn as_ref(&self) -> &[u8] { &self.0 }
+}
+
+impl From<&str> for Topic {
+    #[inline]
+    fn from(s: &str) -> Self { Topic(s.as_bytes().to_vec()) }
+}
+
+impl From<&[u8]> for Topic {
+    #[inline]
+    fn from(s: &[u8]) -> Self { Topic(s.to_vec()) }
+}
+
+impl From<TypeId> for Topic {
+    #[inline]
+    fn from(type_id: TypeId) -> Self { Topic::with_type(type_id) }
+}
+
+impl Display for Topic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.0))
+    }
+}
+
+/// Message received by [`Subscriber`] together with its topic
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Publication<T>
+where
+    T: TypedEnum,
+{
+    /// Topic under which the message was published
+    pub topic: Topic,
+    /// Published message
+    pub msg: Arc<T>,
+}
+
+/// Publisher of the messages of type `T` working over ZMQ PUB session
+pub struct Publisher<T>
+where
+    T: TypedEnum,
+{
+    session: LocalSession,
+    _phantom: PhantomData<T>,
+}
+
+impl<T> Publisher<T>
+where
+    T: TypedEnum,
+{
+    /// Constructs publisher working over ZMQ PUB session
+    pub fn with(session: LocalSession) -> Self {
+        Publisher {
+            session,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Releases the underlying session
+    #[inline]
+    pub fn into_session(self) -> LocalSession { self.session }
+
+    /// Publishes message under the given topic. Returns length of the
+    /// serialized message.
+    pub fn publish(
+        &mut self,
+        topic: impl Into<Topic>,
+        msg: &T,
+    ) -> Result<usize, Error> {
+        let topic = topic.into();
+        let data = msg.serialize();
+        self.session
+            .as_socket()
+            .send_multipart([topic.as_ref(), &data], 0)
+            .map_err(transport::Error::from)?;
+        Ok(data.len())
+    }
+
+    /// Publishes message under the topic derived from its type id (see
+    /// [`Topic::with_type`]).
+    #[inline]
+    pub fn publish_typed(&mut self, msg: &T) -> Result<usize, Error> {
+        self.publish(msg.get_type(), msg)
+    }
+}
+
+/// Subscriber to the messages of type `T` working over ZMQ SUB session
+pub struct Subscriber<T>
+where
+    T: TypedEnum,
+{
+    session: LocalSession,
+    unmarshaller: Unmarshaller<T>,
+    topics: BTreeSet<Topic>,
+}
+
+impl<T> Subscriber<T>
+where
+    T: TypedEnum + CreateUnmarshaller,
+{
+    /// Constructs subscriber working over ZMQ SUB session. Newly constructed
+    /// subscriber is not subscribed to any topic: existing session
+    /// subscriptions are removed.
+    pub fn with(mut session: LocalSession) -> Result<Self, Error> {
+        for topic in session.topics().clone() {
+            session.unsubscribe(topic)?;
+        }
+        Ok(Subscriber {
+            session,
+            unmarshaller: T::create_unmarshaller(),
+            topics: empty!(),
+        })
+    }
+}
+
+impl<T> Subscriber<T>
+where
+    T: TypedEnum,
+{
+    /// Releases the underlying session
+    #[inline]
+    pub fn into_session(self) -> LocalSession { self.session }
+
+    /// Returns set of topic prefixes the subscriber is subscribed to
+    #[inline]
+    pub fn topics(&self) -> &BTreeSet<Topic> { &self.topics }
+
+    /// Subscribes to all publications which topic starts with `prefix`. Empty
+    /// prefix subscribes to all publications.
+    pub fn subscribe(&mut self, prefix: impl Into<Topic>) -> Result<(), Error> {
+        let prefix = prefix.into();
+        self.session.subscribe(&prefix)?;
+        self.topics.insert(prefix);
+        Ok(())
+    }
+
+    /// Removes subscription previously made with [`Subscriber::subscribe`].
+    pub fn unsubscribe(
+        &mut self,
+        prefix: impl Into<Topic>,
+    ) -> Result<(), Error> {
+        let prefix = prefix.into();
+        self.session.unsubscribe(&prefix)?;
+        self.topics.remove(&prefix);
+        Ok(())
+    }
+
+    /// Subscribes to the messages of the given type published with
+    /// [`Publisher::publish_typed`].
+    #[inline]
+    pub fn subscribe_type(&mut self, type_id: TypeId) -> Result<(), Error> {
+        self.subscribe(type_id)
+    }
+
+    /// Removes subscription previously made with
+    /// [`Subscriber::subscribe_type`].
+    #[inline]
+    pub fn unsubscribe_type(&mut self, type_id: TypeId) -> Result<(), Error> {
+        self.unsubscribe(type_id)
+    }
+
+    /// Receives next publication, blocking until it arrives.
+    pub fn recv(&mut self) -> Result<Publication<T>, Error> {
+        let mut parts = self
+            .session
+            .as_socket()
+            .recv_multipart(0)
+            .map_err(transport::Error::from)?
+            .into_iter();
+        let (topic, data) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(topic), Some(data), None) => (topic, data),
+            _ => {
+                return Err(transport::Error::FrameBroken(
+                    "publication must consist of topic and message parts",
+                )
+                .into())
+            }
+        };
+        let msg = self.unmarshaller.unmarshall_frame(&data)?;
+        Ok(Publication {
+            topic: Topic(topic),
+            msg,
+        })
+    }
+}
+
+impl<T> Pollable for Subscriber<T>
+where
+    T: TypedEnum,
+{
+    #[inline]
+    fn as_poll_item(&self) -> zmq::PollItem<'_> { self.session.as_poll_item() }
+}
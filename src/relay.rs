@@ -0,0 +1,333 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Onion relay forwarding Sphinx onion packets between nodes connected with
+//! encrypted sessions (normally [`BrontideSession`]s).
+//!
+//! Each [`Relay`] keeps a registry of the sessions with its peers, indexed by
+//! the peer [`NodeId`]. Onion packets are sent over the sessions as raw
+//! messages containing lightning-encoded [`OnionPacket`]. Upon receiving a
+//! packet the relay peels it: if the local node is an intermediate hop, the
+//! next onion is forwarded to the peer specified by the hop payload (see
+//! [`RelayPayload`]); otherwise the final payload is delivered to the caller.
+//! Sessions failing to receive data are removed from the relay.
+//!
+//! Incoming packets are awaited on all peer sessions at once using
+//! [`SessionPoller`], so the sessions must be [`PollableSession`]s. Replay
+//! protection is provided by an optional [`ReplayStore`], which defaults to
+//! the in-memory [`ReplayCache`]; packets are registered in the store once
+//! authenticated, before their hop payload is processed (see
+//! [`Relay::process`]).
+//!
+//! The module requires `zmq` feature even for the relays working solely over
+//! TCP-based Brontide sessions: [`SessionPoller`] waits for the incoming data
+//! with [`zmq::poll`], which is the only multiplexing primitive of the crate
+//! and is also able to poll raw TCP sockets.
+
+use std::marker::PhantomData;
+
+use addr::NodeId;
+use lightning_encoding::{LightningDecode, LightningEncode};
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+pub use crate::presentation::sphinx::NEXT_NODE_TLV_TYPE;
+use crate::presentation::sphinx::{
+    OnionPacket, PeelError, Peeled, ReplayCache, ReplayStore, ReplayTag,
+    SphinxPayload, TlvHopPayload,
+};
+use crate::session::{BrontideSession, PollableSession, SessionPoller};
+use crate::transport;
+
+/// Hop payloads which can be used with the onion relay
+pub trait RelayPayload: SphinxPayload {
+    /// Returns id of the node to which the onion packet must be forwarded;
+    /// only required for the intermediate hops.
+    fn next_hop(&self) -> Option<NodeId>;
+}
+
+impl RelayPayload for TlvHopPayload {
+    fn next_hop(&self) -> Option<NodeId> {
+        self.stream()
+            .get(&NEXT_NODE_TLV_TYPE.into())
+            .and_then(|value| PublicKey::from_slice(value.as_ref()).ok())
+            .map(NodeId::from)
+    }
+}
+
+/// Onion relay errors
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum RelayError {
+    /// there is no session with node {0}
+    UnknownPeer(NodeId),
+
+    /// onion hop payload does not specify the next hop for the onion packet
+    NoNextHop,
+
+    /// onion packet {0} was already processed
+    Replay(ReplayTag),
+
+    /// replay store failure: {0}
+    ReplayStore(String),
+
+    /// there are no sessions with peer nodes to receive onion packets from
+    NoPeers,
+
+    /// session with node {0} has failed and was removed from the relay: {1}
+    PeerFailed(NodeId, transport::Error),
+
+    /// onion packet has unsupported version {0}
+    UnsupportedVersion(u8),
+
+    /// onion packet HMAC does not match packet data
+    InvalidHmac,
+
+    /// invalid onion hop payload: {0}
+    InvalidPayload(String),
+
+    /// {0}
+    #[from]
+    Transport(transport::Error),
+
+    /// invalid onion packet encoding: {0}
+    #[from]
+    Encoding(lightning_encoding::Error),
+}
+
+impl<E> From<PeelError<E>> for RelayError
+where
+    E: std::error::Error,
+{
+    fn from(err: PeelError<E>) -> Self {
+        match err {
+            PeelError::UnsupportedVersion(ver) => {
+                RelayError::UnsupportedVersion(ver)
+            }
+            PeelError::InvalidHmac => RelayError::InvalidHmac,
+            PeelError::Decode(err) => {
+                RelayError::InvalidPayload(err.to_string())
+            }
+        }
+    }
+}
+
+/// Result of processing onion packet by the relay
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Delivery<Payload: RelayPayload> {
+    /// Onion packet was forwarded to the next hop
+    Forwarded {
+        /// Hop payload for the local node
+        payload: Payload,
+        /// Node to which the onion packet was forwarded
+        next_hop: NodeId,
+    },
+
+    /// Local node is the final hop of the onion packet
+    Delivered {
+        /// Node from which the onion packet was received
+        from: NodeId,
+        /// Final hop payload
+        payload: Payload,
+    },
+}
+
+/// Session which can be registered with the relay
+pub type RelaySession = Box<dyn PollableSession + Send>;
+
+/// Onion relay working over a registry of peer sessions, using `Store` for
+/// the replay protection
+pub struct Relay<
+    Payload: RelayPayload,
+    const PACKET_LEN: usize,
+    Store: ReplayStore = ReplayCache,
+> {
+    node_secret: SecretKey,
+    secp: Secp256k1<secp256k1::All>,
+    sessions: SessionPoller<NodeId, RelaySession>,
+    assoc_data: Vec<u8>,
+    replay_store: Option<Store>,
+    _phantom: PhantomData<Payload>,
+}
+
+impl<Payload, const PACKET_LEN: usize, Store> Relay<Payload, PACKET_LEN, Store>
+where
+    Payload: RelayPayload,
+    Store: ReplayStore,
+{
+    /// Constructs relay for the local node with a given private key
+    pub fn with(node_secret: SecretKey) -> Self {
+        Relay {
+            node_secret,
+            secp: Secp256k1::new(),
+            sessions: empty!(),
+            assoc_data: empty!(),
+            replay_store: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns id of the local node
+    pub fn node_id(&self) -> NodeId {
+        PublicKey::from_secret_key(&self.secp, &self.node_secret).into()
+    }
+
+    /// Sets associated data which must be committed to by all onion packets
+    /// processed by the relay. Defaults to no data.
+    #[inline]
+    pub fn set_assoc_data(&mut self, assoc_data: impl AsRef<[u8]>) {
+        self.assoc_data = assoc_data.as_ref().to_vec();
+    }
+
+    /// Enables replay protection using a given store; processed packets which
+    /// tags are known to the store will fail with [`RelayError::Replay`]
+    #[inline]
+    pub fn set_replay_store(&mut self, store: Store) {
+        self.replay_store = Some(store);
+    }
+
+    /// Returns replay store used by the relay, if replay protection is enabled
+    #[inline]
+    pub fn replay_store(&self) -> Option<&Store> { self.replay_store.as_ref() }
+
+    /// Registers session with a peer node, returning the previous session
+    /// with the same node, if any.
+    pub fn add_session(
+        &mut self,
+        node_id: NodeId,
+        session: RelaySession,
+    ) -> Option<RelaySession> {
+        self.sessions.register(node_id, session)
+    }
+
+    /// Registers Brontide session with a peer node under its remote node id,
+    /// returning the previous session with the same node, if any.
+    pub fn add_brontide(
+        &mut self,
+        session: BrontideSession,
+    ) -> Option<RelaySession> {
+        self.add_session(session.remote_id(), Box::new(session))
+    }
+
+    /// Removes session with a peer node from the registry
+    #[inline]
+    pub fn remove_session(&mut self, node_id: &NodeId) -> Option<RelaySession> {
+        self.sessions.unregister(node_id)
+    }
+
+    /// Returns ids of the peer nodes with the registered sessions
+    pub fn peers(&self) -> impl Iterator<Item = &NodeId> {
+        self.sessions.keys()
+    }
+
+    /// Sends onion packet originated by the local node to the first hop of its
+    /// route
+    pub fn send(
+        &mut self,
+        first_hop: NodeId,
+        onion: &OnionPacket<PACKET_LEN>,
+    ) -> Result<(), RelayError> {
+        let data = onion.lightning_serialize()?;
+        self.session(first_hop)?.send_raw_message(&data)?;
+        Ok(())
+    }
+
+    /// Receives a single onion packet from any of the peers and processes it
+    /// with [`Relay::process`]. Blocks until a message is received from one
+    /// of the registered sessions.
+    ///
+    /// # Errors
+    ///
+    /// If the session with the peer fails to receive the message (for
+    /// instance, because the peer has disconnected), the session is removed
+    /// from the relay, such that it won't be polled again, and
+    /// [`RelayError::PeerFailed`] is returned.
+    pub fn recv(&mut self) -> Result<Delivery<Payload>, RelayError> {
+        if self.sessions.is_empty() {
+            return Err(RelayError::NoPeers);
+        }
+        let from = loop {
+            if let Some(node_id) = self.sessions.poll(None)?.first() {
+                break *node_id;
+            }
+        };
+        let data = match self.session(from)?.recv_raw_message() {
+            Ok(data) => data,
+            Err(err) => {
+                self.sessions.unregister(&from);
+                return Err(RelayError::PeerFailed(from, err));
+            }
+        };
+        self.process(from, &data)
+    }
+
+    /// Processes onion packet data received from a peer, forwarding the next
+    /// onion packet to the next hop or delivering the final payload.
+    ///
+    /// Like [`OnionPacket::unfold_once`], registers the packet in the replay
+    /// store after its HMAC is checked, but before its hop payload is
+    /// processed: forged packets can't take over replay tags of the valid
+    /// ones, and authenticated packets with invalid payloads can't be
+    /// replayed.
+    pub fn process(
+        &mut self,
+        from: NodeId,
+        data: &[u8],
+    ) -> Result<Delivery<Payload>, RelayError> {
+        let onion = OnionPacket::<PACKET_LEN>::lightning_deserialize(data)?;
+        let shared_secret = onion.shared_secret(self.node_secret);
+        let peeled = onion.peel_with_shared_secret::<_, Payload>(
+            &self.secp,
+            shared_secret,
+            &self.assoc_data,
+        );
+
+        let authenticated = !matches!(
+            peeled,
+            Err(PeelError::UnsupportedVersion(_) | PeelError::InvalidHmac)
+        );
+        let store = self.replay_store.as_mut().filter(|_| authenticated);
+        if let Some(store) = store {
+            let tag = ReplayTag::with(shared_secret);
+            let unique = store
+                .register(tag)
+                .map_err(|err| RelayError::ReplayStore(err.to_string()))?;
+            if !unique {
+                return Err(RelayError::Replay(tag));
+            }
+        }
+
+        match peeled? {
+            Peeled::Forward {
+                payload,
+                next_onion,
+            } => {
+                let next_hop =
+                    payload.next_hop().ok_or(RelayError::NoNextHop)?;
+                self.send(next_hop, &next_onion)?;
+                Ok(Delivery::Forwarded { payload, next_hop })
+            }
+            Peeled::Final { payload } => {
+                Ok(Delivery::Delivered { from, payload })
+            }
+        }
+    }
+
+    fn session(
+        &mut self,
+        node_id: NodeId,
+    ) -> Result<&mut RelaySession, RelayError> {
+        self.sessions
+            .get_mut(&node_id)
+            .ok_or(RelayError::UnknownPeer(node_id))
+    }
+}
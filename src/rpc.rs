@@ -0,0 +1,649 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Typed request/response (RPC) layer working on top of ZMQ-based
+//! [`LocalSession`]s.
+//!
+//! Each RPC frame is prefixed with a call identifier and frame kind, allowing
+//! [`Client`] to match replies to the requests even when multiple calls are
+//! in flight over a single ROUTER socket. Failed requests are answered with
+//! a standard [`Failure`] reply instead of a typed one.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Cursor, Read, Write};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::presentation::{
+    self, CreateUnmarshaller, Unmarshaller, UnsupportedReply,
+};
+use crate::session::{poll_timeout, LocalSession, SendRecvMessage};
+use crate::transport::Pollable;
+use crate::{transport, Dispatch, ExpectedReply, TypeId, TypedEnum};
+
+/// Default deadline for RPC calls made by [`Client`]
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Identifier used to correlate RPC replies with requests
+#[derive(
+    Wrapper, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug,
+    Display, From
+)]
+#[derive(StrictEncode, StrictDecode)]
+#[display("#{0}")]
+pub struct CallId(u64);
+
+/// Kind of the data contained in RPC frame
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[strict_encoding(by_value, repr = u8)]
+#[repr(u8)]
+pub enum FrameKind {
+    /// Request sent by RPC client
+    #[display("request")]
+    Request = 0,
+
+    /// Typed reply sent by RPC server
+    #[display("reply")]
+    Reply = 1,
+
+    /// [`Failure`] reply sent by RPC server
+    #[display("failure")]
+    Failure = 2,
+}
+
+/// Standard failure reply returned by RPC server instead of a typed reply
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[derive(StrictEncode, StrictDecode)]
+#[display("{info} (failure code {code})")]
+pub struct Failure {
+    /// Failure code; codes below [`Failure::APPLICATION`] are reserved by
+    /// this library
+    pub code: u16,
+    /// Human-readable failure description
+    pub info: String,
+}
+
+impl Failure {
+    /// Request data can't be parsed by the server
+    pub const MALFORMED_REQUEST: u16 = 0x0001;
+    /// Request type is not known to the server
+    pub const UNKNOWN_REQUEST: u16 = 0x0002;
+    /// Request type is known to the server, but is not supported by it
+    pub const UNSUPPORTED_REQUEST: u16 = 0x0003;
+    /// Internal server error
+    pub const INTERNAL: u16 = 0x00FF;
+    /// Starting value for application-specific failure codes
+    pub const APPLICATION: u16 = 0x0100;
+
+    /// Constructs failure reply from a code and description
+    pub fn with(code: u16, info: impl ToString) -> Failure {
+        Failure {
+            code,
+            info: info.to_string(),
+        }
+    }
+}
+
+impl UnsupportedReply for Failure {
+    fn unsupported(type_id: TypeId) -> Self {
+        Failure::with(
+            Failure::UNSUPPORTED_REQUEST,
+            format!("request type {} is not supported", type_id),
+        )
+    }
+}
+
+/// RPC-level errors
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum Error {
+    /// {0}
+    #[from]
+    #[from(transport::Error)]
+    Presentation(presentation::Error),
+
+    /// RPC frame data are broken: {0}
+    #[from]
+    Encoding(strict_encoding::Error),
+
+    /// RPC frame of unexpected kind `{0}`
+    UnexpectedFrame(FrameKind),
+
+    /// reply of type {0} is not expected for the request
+    UnexpectedReply(TypeId),
+
+    /// remote peer has not replied to the call {0} before its deadline
+    Timeout(CallId),
+
+    /// call {0} is not known or its reply was already received
+    UnknownCall(CallId),
+
+    /// the request has failed: {0}
+    #[from]
+    Failure(Failure),
+}
+
+/// RPC frame: a typed message serialized with its own encoding, prefixed with
+/// call identifier and frame kind
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Frame {
+    /// Call identifier
+    pub id: CallId,
+    /// Kind of the frame payload
+    pub kind: FrameKind,
+    /// Serialized message or failure data
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    /// Serializes frame into a byte string which can be sent over the session
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.data.len() + 9);
+        self.strict_encode(&mut buf)
+            .expect("memory writers does not error");
+        buf
+    }
+
+    /// Deserializes frame from the data received over the session
+    pub fn deserialize(data: impl AsRef<[u8]>) -> Result<Self, Error> {
+        Frame::strict_decode(Cursor::new(data.as_ref())).map_err(Error::from)
+    }
+}
+
+impl StrictEncode for Frame {
+    fn strict_encode<E: Write>(
+        &self,
+        mut e: E,
+    ) -> Result<usize, strict_encoding::Error> {
+        let len =
+            self.id.strict_encode(&mut e)? + self.kind.strict_encode(&mut e)?;
+        e.write_all(&self.data)?;
+        Ok(len + self.data.len())
+    }
+}
+
+impl StrictDecode for Frame {
+    fn strict_decode<D: Read>(
+        mut d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        let id = CallId::strict_decode(&mut d)?;
+        let kind = FrameKind::strict_decode(&mut d)?;
+        let mut data = Vec::new();
+        d.read_to_end(&mut data)?;
+        Ok(Frame { id, kind, data })
+    }
+}
+
+/// Addressing information used by ROUTER-based RPC sessions
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct Route {
+    local: Vec<u8>,
+    remote: Vec<u8>,
+}
+
+fn send_frame(
+    session: &mut LocalSession,
+    route: Option<&Route>,
+    frame: &Frame,
+) -> Result<(), Error> {
+    let data = frame.serialize();
+    match route {
+        None => session.send_raw_message(&data)?,
+        Some(Route { local, remote }) => {
+            session.send_routed_message(local, remote, remote, &data)?
+        }
+    };
+    Ok(())
+}
+
+fn unmarshall<T>(
+    unmarshaller: &Unmarshaller<T>,
+    data: &[u8],
+) -> Result<T, Error>
+where
+    T: TypedEnum,
+{
+    let msg = unmarshaller.unmarshall_frame(data)?;
+    Ok(Arc::try_unwrap(msg).unwrap_or_else(|msg| (*msg).clone()))
+}
+
+/// RPC client sending requests of type `Req` and receiving replies of type
+/// `Rep`.
+///
+/// Client may work with either ZMQ REQ socket, in which case only a single
+/// call may be in flight, or with ROUTER socket, where any number of calls
+/// may be sent with [`Client::send`] before their replies are collected with
+/// [`Client::recv`].
+pub struct Client<Req, Rep>
+where
+    Req: TypedEnum,
+    Rep: TypedEnum,
+{
+    session: LocalSession,
+    route: Option<Route>,
+    unmarshaller: Unmarshaller<Rep>,
+    timeout: Duration,
+    last_id: CallId,
+    deadlines: BTreeMap<CallId, Instant>,
+    replies: BTreeMap<CallId, Result<Rep, Failure>>,
+    _phantom: PhantomData<Req>,
+}
+
+impl<Req, Rep> Client<Req, Rep>
+where
+    Req: TypedEnum,
+    Rep: TypedEnum + CreateUnmarshaller,
+{
+    /// Constructs RPC client working over ZMQ REQ session
+    pub fn with(session: LocalSession) -> Self {
+        Client {
+            session,
+            route: None,
+            unmarshaller: Rep::create_unmarshaller(),
+            timeout: DEFAULT_TIMEOUT,
+            last_id: CallId::default(),
+            deadlines: empty!(),
+            replies: empty!(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Constructs RPC client working over ZMQ ROUTER session. Requests are
+    /// sent to the `remote` identity on behalf of `local` identity, which
+    /// must match the identity of the session socket.
+    pub fn with_router(
+        session: LocalSession,
+        local: impl AsRef<[u8]>,
+        remote: impl AsRef<[u8]>,
+    ) -> Self {
+        let mut client = Client::with(session);
+        client.route = Some(Route {
+            local: local.as_ref().to_vec(),
+            remote: remote.as_ref().to_vec(),
+        });
+        client
+    }
+}
+
+impl<Req, Rep> Client<Req, Rep>
+where
+    Req: TypedEnum,
+    Rep: TypedEnum,
+{
+    /// Returns deadline applied to all new calls
+    #[inline]
+    pub fn timeout(&self) -> Duration { self.timeout }
+
+    /// Sets deadline applied to all new calls
+    #[inline]
+    pub fn set_timeout(&mut self, timeout: Duration) { self.timeout = timeout }
+
+    /// Returns identifiers of the calls which were sent and which replies were
+    /// not yet returned by [`Client::recv`]
+    pub fn pending_calls(&self) -> BTreeSet<CallId> {
+        self.deadlines.keys().copied().collect()
+    }
+
+    /// Releases the underlying session
+    #[inline]
+    pub fn into_session(self) -> LocalSession { self.session }
+
+    /// Sends request without waiting for the reply, returning call identifier
+    /// which should be used to receive the reply with [`Client::recv`].
+    pub fn send(&mut self, request: &Req) -> Result<CallId, Error> {
+        self.last_id = CallId(self.last_id.0.wrapping_add(1));
+        let id = self.last_id;
+        let frame = Frame {
+            id,
+            kind: FrameKind::Request,
+            data: request.serialize(),
+        };
+        send_frame(&mut self.session, self.route.as_ref(), &frame)?;
+        self.deadlines.insert(id, Instant::now() + self.timeout);
+        Ok(id)
+    }
+
+    /// Sends request and waits for its reply
+    pub fn call(&mut self, request: &Req) -> Result<Rep, Error> {
+        let id = self.send(request)?;
+        self.recv(id)
+    }
+}
+
+impl<Req, Rep> Client<Req, Rep>
+where
+    Req: ExpectedReply,
+    Rep: TypedEnum,
+{
+    /// Sends request and waits for its reply, checking that the reply type
+    /// is one of [`ExpectedReply::expected_reply_types`] for the request.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the errors returned by [`Client::call`], fails with
+    /// [`Error::UnexpectedReply`] if the reply type does not match the
+    /// request.
+    pub fn call_checked(&mut self, request: &Req) -> Result<Rep, Error> {
+        let reply = self.call(request)?;
+        let type_id = reply.get_type();
+        if !request.is_expected_reply(type_id) {
+            return Err(Error::UnexpectedReply(type_id));
+        }
+        Ok(reply)
+    }
+}
+
+impl<Req, Rep> Client<Req, Rep>
+where
+    Req: TypedEnum,
+    Rep: TypedEnum,
+{
+    /// Waits for a reply to a previously sent call. Replies to other pending
+    /// calls received meanwhile are buffered and returned by subsequent calls
+    /// to this method.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Timeout`] if the reply was not received before the call
+    ///   deadline; the call is removed from the list of pending calls and its
+    ///   reply, if received later, is discarded. ZMQ REQ socket remains in the
+    ///   state awaiting the reply, so a client working over REQ session can't
+    ///   send new requests after a timeout and must be re-created with a new
+    ///   session;
+    /// - [`Error::Failure`] if the server has replied with a failure;
+    /// - [`Error::UnknownCall`] if the call was not sent or was already
+    ///   completed.
+    pub fn recv(&mut self, id: CallId) -> Result<Rep, Error> {
+        loop {
+            if let Some(reply) = self.replies.remove(&id) {
+                self.deadlines.remove(&id);
+                return reply.map_err(Error::from);
+            }
+            let deadline =
+                *self.deadlines.get(&id).ok_or(Error::UnknownCall(id))?;
+            let now = Instant::now();
+            let remaining = deadline.saturating_duration_since(now);
+            let ready = self
+                .session
+                .as_socket()
+                .poll(zmq::POLLIN, poll_timeout(remaining))
+                .map_err(transport::Error::from)?;
+            if ready == 0 {
+                self.deadlines.remove(&id);
+                return Err(Error::Timeout(id));
+            }
+            self.recv_frame()?;
+        }
+    }
+
+    fn recv_frame(&mut self) -> Result<(), Error> {
+        let data = match self.route {
+            None => self.session.recv_raw_message()?,
+            Some(_) => self.session.recv_routed_message()?.msg,
+        };
+        let frame = Frame::deserialize(data)?;
+        // Replies for the calls which has already timed out are discarded
+        if !self.deadlines.contains_key(&frame.id) {
+            return Ok(());
+        }
+        let reply = match frame.kind {
+            FrameKind::Reply => {
+                Ok(unmarshall(&self.unmarshaller, &frame.data)?)
+            }
+            FrameKind::Failure => {
+                Err(Failure::strict_decode(Cursor::new(frame.data))?)
+            }
+            kind @ FrameKind::Request => {
+                return Err(Error::UnexpectedFrame(kind))
+            }
+        };
+        self.replies.insert(frame.id, reply);
+        Ok(())
+    }
+}
+
+impl<Req, Rep> Pollable for Client<Req, Rep>
+where
+    Req: TypedEnum,
+    Rep: TypedEnum,
+{
+    #[inline]
+    fn as_poll_item(&self) -> zmq::PollItem<'_> { self.session.as_poll_item() }
+}
+
+/// RPC call received by [`Server`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Call<Req> {
+    /// Call identifier which must be used in the reply
+    pub id: CallId,
+    /// Identity of the client which has sent the call; present only for
+    /// ROUTER-based sessions
+    pub remote: Option<Vec<u8>>,
+    /// Request data
+    pub request: Req,
+}
+
+/// RPC server receiving requests of type `Req` and replying with `Rep`.
+///
+/// Server may work with either ZMQ REP socket, in which case each call must be
+/// replied before the next one is received, or with ROUTER socket, where calls
+/// may be replied in any order.
+pub struct Server<Req, Rep>
+where
+    Req: TypedEnum,
+    Rep: TypedEnum,
+{
+    session: LocalSession,
+    identity: Option<Vec<u8>>,
+    unmarshaller: Unmarshaller<Req>,
+    _phantom: PhantomData<Rep>,
+}
+
+impl<Req, Rep> Server<Req, Rep>
+where
+    Req: TypedEnum + CreateUnmarshaller,
+    Rep: TypedEnum,
+{
+    /// Constructs RPC server working over ZMQ REP session
+    pub fn with(session: LocalSession) -> Self {
+        Server {
+            session,
+            identity: None,
+            unmarshaller: Req::create_unmarshaller(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Constructs RPC server working over ZMQ ROUTER session with the given
+    /// local `identity`, which must match the identity of the session socket.
+    pub fn with_router(
+        session: LocalSession,
+        identity: impl AsRef<[u8]>,
+    ) -> Self {
+        let mut server = Server::with(session);
+        server.identity = Some(identity.as_ref().to_vec());
+        server
+    }
+}
+
+impl<Req, Rep> Server<Req, Rep>
+where
+    Req: TypedEnum,
+    Rep: TypedEnum,
+{
+    /// Releases the underlying session
+    #[inline]
+    pub fn into_session(self) -> LocalSession { self.session }
+
+    /// Receives next call.
+    ///
+    /// If the request can't be parsed, replies to the client with a
+    /// [`Failure`] and returns the parse error. Frames which are not requests
+    /// or can't be parsed at all are also answered with a
+    /// [`Failure::MALFORMED_REQUEST`] failure, so the REP socket is always
+    /// ready to receive the next call.
+    pub fn recv(&mut self) -> Result<Call<Req>, Error> {
+        let (data, remote) = match self.identity {
+            None => (self.session.recv_raw_message()?, None),
+            Some(_) => {
+                let frame = self.session.recv_routed_message()?;
+                (frame.msg, Some(frame.src))
+            }
+        };
+        let frame = match Frame::deserialize(&data) {
+            Ok(frame) => frame,
+            Err(err) => {
+                // Call id can't be recovered from a broken frame, so the
+                // failure is sent with the default one
+                self.send(
+                    CallId::default(),
+                    remote.as_deref(),
+                    FrameKind::Failure,
+                    Failure::with(Failure::MALFORMED_REQUEST, &err)
+                        .strict_serialize()?,
+                )?;
+                return Err(err);
+            }
+        };
+        if frame.kind != FrameKind::Request {
+            let err = Error::UnexpectedFrame(frame.kind);
+            self.send(
+                frame.id,
+                remote.as_deref(),
+                FrameKind::Failure,
+                Failure::with(Failure::MALFORMED_REQUEST, &err)
+                    .strict_serialize()?,
+            )?;
+            return Err(err);
+        }
+        match unmarshall(&self.unmarshaller, &frame.data) {
+            Ok(request) => Ok(Call {
+                id: frame.id,
+                remote,
+                request,
+            }),
+            Err(err) => {
+                let code = match err {
+                    Error::Presentation(
+                        presentation::Error::UnknownDataType,
+                    )
+                    | Error::Presentation(
+                        presentation::Error::MessageEvenType(_),
+                    ) => Failure::UNKNOWN_REQUEST,
+                    _ => Failure::MALFORMED_REQUEST,
+                };
+                self.send(
+                    frame.id,
+                    remote.as_deref(),
+                    FrameKind::Failure,
+                    Failure::with(code, &err).strict_serialize()?,
+                )?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Sends typed reply to the call
+    pub fn reply(
+        &mut self,
+        call: &Call<Req>,
+        reply: &Rep,
+    ) -> Result<(), Error> {
+        self.send(
+            call.id,
+            call.remote.as_deref(),
+            FrameKind::Reply,
+            reply.serialize(),
+        )
+    }
+
+    /// Sends failure reply to the call
+    pub fn fail(
+        &mut self,
+        call: &Call<Req>,
+        failure: &Failure,
+    ) -> Result<(), Error> {
+        self.send(
+            call.id,
+            call.remote.as_deref(),
+            FrameKind::Failure,
+            failure.strict_serialize()?,
+        )
+    }
+
+    /// Receives a single call, processes it with the `handler` and sends
+    /// either typed or failure reply returned by the handler.
+    pub fn serve(
+        &mut self,
+        handler: impl FnOnce(Req) -> Result<Rep, Failure>,
+    ) -> Result<(), Error> {
+        let call = self.recv()?;
+        let id = call.id;
+        let remote = call.remote;
+        match handler(call.request) {
+            Ok(reply) => self.send(
+                id,
+                remote.as_deref(),
+                FrameKind::Reply,
+                reply.serialize(),
+            ),
+            Err(failure) => self.send(
+                id,
+                remote.as_deref(),
+                FrameKind::Failure,
+                failure.strict_serialize()?,
+            ),
+        }
+    }
+
+    /// Receives a single call and passes it to the `handler` method
+    /// corresponding to the request type, sending either typed or failure
+    /// reply returned by the handler.
+    pub fn dispatch<H>(&mut self, handler: &mut H) -> Result<(), Error>
+    where
+        H: ?Sized,
+        Req: Dispatch<H, Reply = Result<Rep, Failure>>,
+    {
+        self.serve(|request| request.dispatch(handler))
+    }
+
+    fn send(
+        &mut self,
+        id: CallId,
+        remote: Option<&[u8]>,
+        kind: FrameKind,
+        data: Vec<u8>,
+    ) -> Result<(), Error> {
+        let route = match (&self.identity, remote) {
+            (Some(local), Some(remote)) => Some(Route {
+                local: local.clone(),
+                remote: remote.to_vec(),
+            }),
+            _ => None,
+        };
+        send_frame(&mut self.session, route.as_ref(), &Frame { id, kind, data })
+    }
+}
+
+impl<Req, Rep> Pollable for Server<Req, Rep>
+where
+    Req: TypedEnum,
+    Rep: TypedEnum,
+{
+    #[inline]
+    fn as_poll_item(&self) -> zmq::PollItem<'_> { self.session.as_poll_item() }
+}
@@ -15,6 +15,8 @@
 //! transport layer
 
 pub mod noise;
+#[cfg(feature = "zmq")]
+mod poller;
 #[allow(clippy::module_inception)]
 mod session;
 mod transcoders;
@@ -22,6 +24,10 @@ mod transcoders;
 pub use noise::{
     HandshakeError, NoiseDecryptor, NoiseEncryptor, NoiseTranscoder,
 };
+#[cfg(feature = "zmq")]
+pub(crate) use poller::poll_timeout;
+#[cfg(feature = "zmq")]
+pub use poller::{PollableSession, SessionPoller};
 pub use session::{
     BrontideSession, BrontozaurSession, Receiver, RecvMessage, SendMessage,
     SendRecvMessage, Sender, Session, Split,
@@ -0,0 +1,141 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use super::SendRecvMessage;
+use crate::transport::{Error, Pollable};
+
+/// Session which can be both polled for incoming data and used for sending
+/// and receiving messages. Allows to register sessions of different types
+/// within a single [`SessionPoller`].
+pub trait PollableSession: SendRecvMessage + Pollable {}
+
+impl<T> PollableSession for T where T: SendRecvMessage + Pollable {}
+
+/// Multiplexer waiting for the incoming data on multiple sessions at once.
+///
+/// Poller owns registered sessions (or session-based types like RPC servers
+/// or bus clients), identifying them with keys of type `K`. Sessions of
+/// different types can be registered by using `Box<dyn PollableSession>` as
+/// `S` (which is the default) or by wrapping them into an enum implementing
+/// [`Pollable`].
+pub struct SessionPoller<K, S = Box<dyn PollableSession>>
+where
+    K: Ord + Clone,
+    S: Pollable,
+{
+    sessions: BTreeMap<K, S>,
+}
+
+impl<K, S> Default for SessionPoller<K, S>
+where
+    K: Ord + Clone,
+    S: Pollable,
+{
+    fn default() -> Self { SessionPoller { sessions: empty!() } }
+}
+
+impl<K, S> SessionPoller<K, S>
+where
+    K: Ord + Clone,
+    S: Pollable,
+{
+    /// Constructs poller without registered sessions
+    #[inline]
+    pub fn new() -> Self { Self::default() }
+
+    /// Registers session under a given key, returning previously registered
+    /// session with the same key, if any
+    #[inline]
+    pub fn register(&mut self, key: K, session: S) -> Option<S> {
+        self.sessions.insert(key, session)
+    }
+
+    /// Removes session from the poller, returning it back to the caller
+    #[inline]
+    pub fn unregister(&mut self, key: &K) -> Option<S> {
+        self.sessions.remove(key)
+    }
+
+    /// Returns number of registered sessions
+    #[inline]
+    pub fn len(&self) -> usize { self.sessions.len() }
+
+    /// Detects whether poller has no registered sessions
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.sessions.is_empty() }
+
+    /// Returns iterator over keys of the registered sessions
+    #[inline]
+    pub fn keys(&self) -> impl Iterator<Item = &K> { self.sessions.keys() }
+
+    /// Returns reference to the session registered under the `key`
+    #[inline]
+    pub fn get(&self, key: &K) -> Option<&S> { self.sessions.get(key) }
+
+    /// Returns mutable reference to the session registered under the `key`
+    #[inline]
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut S> {
+        self.sessions.get_mut(key)
+    }
+
+    /// Waits for any of the registered sessions to have incoming data for up
+    /// to `timeout` (or indefinitely, if `timeout` is `None`), returning keys
+    /// of the sessions ready for reading. Empty list is returned if the
+    /// timeout has expired.
+    pub fn poll(&self, timeout: Option<Duration>) -> Result<Vec<K>, Error> {
+        let mut items = self
+            .sessions
+            .values()
+            .map(Pollable::as_poll_item)
+            .collect::<Vec<_>>();
+        let timeout = timeout.map(poll_timeout).unwrap_or(-1);
+        if zmq::poll(&mut items, timeout)? == 0 {
+            return Ok(vec![]);
+        }
+        Ok(self
+            .sessions
+            .keys()
+            .zip(items)
+            .filter(|(_, item)| item.is_readable())
+            .map(|(key, _)| key.clone())
+            .collect())
+    }
+}
+
+/// Converts timeout into ZMQ poll milliseconds. Sub-millisecond remainders are
+/// rounded up, such that non-zero timeouts never turn into a non-blocking
+/// poll; too large timeouts saturate.
+pub(crate) fn poll_timeout(timeout: Duration) -> i64 {
+    let millis = timeout.as_millis()
+        + u128::from(timeout.subsec_nanos() % 1_000_000 != 0);
+    i64::try_from(millis).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn timeout_conversion() {
+        assert_eq!(poll_timeout(Duration::ZERO), 0);
+        assert_eq!(poll_timeout(Duration::from_nanos(1)), 1);
+        assert_eq!(poll_timeout(Duration::from_micros(999)), 1);
+        assert_eq!(poll_timeout(Duration::from_millis(5)), 5);
+        assert_eq!(poll_timeout(Duration::from_micros(5001)), 6);
+        assert_eq!(poll_timeout(Duration::MAX), i64::MAX);
+    }
+}
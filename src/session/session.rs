@@ -12,6 +12,8 @@
 // If not, see <https://opensource.org/licenses/MIT>.
 
 use std::any::Any;
+#[cfg(feature = "zmq")]
+use std::collections::BTreeSet;
 #[cfg(feature = "keygen")]
 use std::net::TcpListener;
 
@@ -32,6 +34,10 @@ use crate::transport::{
     SendFrame,
 };
 #[cfg(feature = "zmq")]
+use crate::transport::{
+    Pollable, ZmqOptions, FRAME_PREFIX_SIZE, FRAME_SUFFIX_SIZE,
+};
+#[cfg(feature = "zmq")]
 use crate::zeromq;
 use crate::{NoiseDecryptor, NoiseTranscoder};
 
@@ -396,6 +402,32 @@ impl LocalSession {
         )
     }
 
+    /// Connects ZMQ session with sockets configured with `options`. See
+    /// [`zeromq::Connection::connect_with_options`] for details.
+    ///
+    /// Maximum size of the inbound messages, if set, excludes the session
+    /// framing, which is added to the limit of the underlying socket.
+    pub fn connect_with_options(
+        zmq_type: zeromq::ZmqSocketType,
+        remote: &ServiceAddr,
+        local: Option<&ServiceAddr>,
+        identity: Option<&[u8]>,
+        mut options: ZmqOptions,
+        context: &zmq::Context,
+    ) -> Result<Self, Error> {
+        if let Some(len) = options.max_msg_size() {
+            options = options.with_max_msg_size(
+                len.saturating_add(FRAME_PREFIX_SIZE + FRAME_SUFFIX_SIZE),
+            );
+        }
+        Ok(Self {
+            transcoder: PlainTranscoder,
+            connection: zeromq::Connection::connect_with_options(
+                zmq_type, remote, local, identity, options, context,
+            )?,
+        })
+    }
+
     pub fn with_zmq_socket(
         zmq_type: zeromq::ZmqSocketType,
         socket: zmq::Socket,
@@ -593,6 +625,41 @@ where
             .set_identity(identity, context)
             .map_err(Error::from)
     }
+
+    /// Subscribes SUB session to the messages which topic starts with
+    /// `prefix`. See [`zeromq::Connection::subscribe`] for details.
+    pub fn subscribe(&mut self, prefix: impl AsRef<[u8]>) -> Result<(), Error> {
+        self.connection.subscribe(prefix).map_err(Error::from)
+    }
+
+    /// Returns topic prefixes to which SUB session is subscribed
+    #[inline]
+    pub fn topics(&self) -> &BTreeSet<Vec<u8>> { self.connection.topics() }
+
+    /// Removes SUB session subscription for the `prefix`. See
+    /// [`zeromq::Connection::unsubscribe`] for details.
+    pub fn unsubscribe(
+        &mut self,
+        prefix: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        self.connection.unsubscribe(prefix).map_err(Error::from)
+    }
+}
+
+#[cfg(feature = "zmq")]
+impl<T, C> Pollable for Session<T, C>
+where
+    T: Transcode,
+    T::Left: Decrypt,
+    T::Right: Encrypt,
+    C: DuplexConnection + Bipolar + Pollable,
+    C::Left: RecvFrame,
+    C::Right: SendFrame,
+{
+    #[inline]
+    fn as_poll_item(&self) -> zmq::PollItem<'_> {
+        self.connection.as_poll_item()
+    }
 }
 
 // Private trait used to avoid code duplication below
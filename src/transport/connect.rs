@@ -21,6 +21,8 @@ use std::time::Duration;
 use amplify::Bipolar;
 use inet2_addr::InetSocketAddr;
 
+#[cfg(feature = "zmq")]
+use crate::transport::Pollable;
 use crate::transport::{Error, RecvFrame, SendFrame};
 use crate::DuplexConnection;
 
@@ -62,6 +64,12 @@ impl<S: Stream + DuplexConnection> DuplexConnection for Connection<S> {
     }
 }
 
+#[cfg(feature = "zmq")]
+impl<S: Stream + Pollable> Pollable for Connection<S> {
+    #[inline]
+    fn as_poll_item(&self) -> zmq::PollItem<'_> { self.stream.as_poll_item() }
+}
+
 impl<S: Stream + Bipolar<Left = S, Right = S>> Bipolar for Connection<S> {
     type Left = S;
     type Right = S;
@@ -139,6 +147,23 @@ impl TcpInetStream for TcpStream {
     }
 }
 
+#[cfg(feature = "zmq")]
+impl Pollable for TcpStream {
+    fn as_poll_item(&self) -> zmq::PollItem<'_> {
+        #[cfg(not(target_os = "windows"))]
+        use std::os::unix::io::AsRawFd;
+        #[cfg(target_os = "windows")]
+        use std::os::windows::io::AsRawSocket;
+
+        #[cfg(not(target_os = "windows"))]
+        let fd = self.as_raw_fd();
+        #[cfg(target_os = "windows")]
+        let fd = self.as_raw_socket();
+
+        zmq::PollItem::from_fd(fd, zmq::POLLIN)
+    }
+}
+
 impl RecvFrame for TcpStream {
     fn recv_frame(&mut self) -> Result<Vec<u8>, Error> {
         let mut len_buf = [0u8; 2];
@@ -0,0 +1,355 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2022 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! ZMQ CURVE security mechanism support.
+//!
+//! CURVE is ZMQ-native alternative to the Noise-encrypted sessions which can
+//! be used for cross-host ZMQ links. CURVE uses Curve25519 keys, which can be
+//! either generated independently ([`CurveKeys::generate`]) or, with
+//! `zmq-curve` feature, derived from the secp256k1 node key
+//! (`CurveKeys::with_node_key`). Servers may restrict the set of clients
+//! allowed to connect with a [`ZapHandler`].
+//!
+//! NB: CURVE requires libzmq to be compiled with CURVE support; use
+//! [`curve_supported`] to check it at runtime.
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Debug, Formatter};
+use std::hash::Hasher;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::JoinHandle;
+
+#[cfg(feature = "zmq-curve")]
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+#[cfg(feature = "zmq-curve")]
+use curve25519_dalek::constants::X25519_BASEPOINT;
+#[cfg(feature = "zmq-curve")]
+use curve25519_dalek::scalar::Scalar;
+use zeroize::{Zeroize, Zeroizing};
+
+use super::zeromq;
+
+/// Length of CURVE public and secret keys, in bytes
+pub const CURVE_KEY_LEN: usize = 32;
+
+/// Tag used in derivation of CURVE keys from secp256k1 node keys
+#[cfg(feature = "zmq-curve")]
+pub const CURVE_KEY_DERIVATION_TAG: &[u8] = b"internet2:zmq-curve";
+
+/// Endpoint at which ZMQ looks for the ZAP handler (defined by ZMQ RFC 27)
+pub const ZAP_ENDPOINT: &str = "inproc://zeromq.zap.01";
+
+/// ZAP protocol version supported by [`ZapHandler`]
+const ZAP_VERSION: &[u8] = b"1.0";
+
+/// How often [`ZapHandler`] thread checks whether it should be stopped, in
+/// milliseconds
+const ZAP_POLL_INTERVAL: i64 = 100;
+
+/// Detects whether the linked libzmq supports CURVE security mechanism
+#[inline]
+pub fn curve_supported() -> bool { zmq::has("curve").unwrap_or(false) }
+
+/// Curve25519 key pair used by ZMQ CURVE security mechanism.
+///
+/// Key pairs are compared and hashed by their public keys only, such that
+/// the secret key never takes part in non-constant-time operations. The
+/// secret key is erased from memory when the key pair is dropped.
+#[derive(Clone)]
+pub struct CurveKeys {
+    public_key: [u8; CURVE_KEY_LEN],
+    secret_key: Zeroizing<[u8; CURVE_KEY_LEN]>,
+}
+
+impl CurveKeys {
+    /// Generates new random key pair using libzmq.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `ENOTSUP` if libzmq is compiled without CURVE support.
+    pub fn generate() -> Result<CurveKeys, zeromq::Error> {
+        let mut pair = zmq::CurveKeyPair::new()?;
+        let keys = CurveKeys {
+            public_key: pair.public_key,
+            secret_key: Zeroizing::new(pair.secret_key),
+        };
+        pair.secret_key.zeroize();
+        Ok(keys)
+    }
+
+    /// Constructs key pair from the Curve25519 secret key, computing the
+    /// public key from it.
+    ///
+    /// Requires compilation with `zmq-curve` feature.
+    #[cfg(feature = "zmq-curve")]
+    pub fn with_secret_key(secret_key: [u8; CURVE_KEY_LEN]) -> CurveKeys {
+        let secret_key = Zeroizing::new(secret_key);
+        let mut scalar = Zeroizing::new(*secret_key);
+        scalar[0] &= 248;
+        scalar[31] &= 127;
+        scalar[31] |= 64;
+        let mut scalar = Scalar::from_bits(*scalar);
+        let public_key = (X25519_BASEPOINT * scalar).0;
+        scalar.zeroize();
+        CurveKeys {
+            public_key,
+            secret_key,
+        }
+    }
+
+    /// Derives key pair from the secp256k1 node key, such that the same node
+    /// identity may be used both for Noise and CURVE connections.
+    ///
+    /// Since secp256k1 and Curve25519 keys can't be converted into each
+    /// other, the CURVE secret key is computed as a tagged hash
+    /// `SHA256(SHA256(tag) || SHA256(tag) || node_secret_key)`, where `tag`
+    /// is [`CURVE_KEY_DERIVATION_TAG`]. The resulting CURVE public key is not
+    /// derivable from the node public key, so it has to be distributed to
+    /// the clients separately.
+    ///
+    /// Requires compilation with `zmq-curve` feature.
+    #[cfg(feature = "zmq-curve")]
+    pub fn with_node_key(node_key: &secp256k1::SecretKey) -> CurveKeys {
+        let tag = sha256::Hash::hash(CURVE_KEY_DERIVATION_TAG);
+        let mut engine = sha256::Hash::engine();
+        engine.input(&tag[..]);
+        engine.input(&tag[..]);
+        engine.input(&Zeroizing::new(node_key.secret_bytes())[..]);
+        let mut secret_key = sha256::Hash::from_engine(engine).into_inner();
+        let keys = CurveKeys::with_secret_key(secret_key);
+        secret_key.zeroize();
+        keys
+    }
+
+    /// Returns CURVE public key
+    #[inline]
+    pub fn public_key(&self) -> [u8; CURVE_KEY_LEN] { self.public_key }
+
+    /// Returns CURVE secret key
+    #[inline]
+    pub fn secret_key(&self) -> &[u8; CURVE_KEY_LEN] { &self.secret_key }
+
+    /// Returns Z85 representation of the public key, which is the format used
+    /// by ZMQ tools
+    pub fn public_key_z85(&self) -> String {
+        zmq::z85_encode(&self.public_key)
+            .expect("CURVE key length is always divisible by 4")
+    }
+}
+
+impl PartialEq for CurveKeys {
+    fn eq(&self, other: &Self) -> bool { self.public_key == other.public_key }
+}
+
+impl Eq for CurveKeys {}
+
+impl std::hash::Hash for CurveKeys {
+    fn hash<H: Hasher>(&self, state: &mut H) { self.public_key.hash(state) }
+}
+
+impl Debug for CurveKeys {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CurveKeys")
+            .field("public_key", &self.public_key_z85())
+            .field("secret_key", &"..")
+            .finish()
+    }
+}
+
+/// CURVE configuration for a ZMQ connection
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum CurveConfig {
+    /// Socket acts as CURVE server, accepting connections from the clients
+    /// knowing its public key
+    Server {
+        /// Server key pair
+        keys: CurveKeys,
+    },
+
+    /// Socket acts as CURVE client connecting to the server with a given
+    /// public key
+    Client {
+        /// Client key pair
+        keys: CurveKeys,
+        /// Public key of the server
+        server_key: [u8; CURVE_KEY_LEN],
+    },
+}
+
+impl CurveConfig {
+    /// Returns key pair used by the socket
+    #[inline]
+    pub fn keys(&self) -> &CurveKeys {
+        match self {
+            CurveConfig::Server { keys } | CurveConfig::Client { keys, .. } => {
+                keys
+            }
+        }
+    }
+
+    /// Configures socket to use CURVE security mechanism. Must be called
+    /// before the socket is bound or connected.
+    pub fn apply(&self, socket: &zmq::Socket) -> Result<(), zeromq::Error> {
+        match self {
+            CurveConfig::Server { keys } => {
+                socket.set_curve_server(true)?;
+                socket.set_curve_secretkey(&keys.secret_key[..])?;
+            }
+            CurveConfig::Client { keys, server_key } => {
+                socket.set_curve_serverkey(server_key)?;
+                socket.set_curve_publickey(&keys.public_key)?;
+                socket.set_curve_secretkey(&keys.secret_key[..])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// ZAP (ZMQ authentication protocol) handler restricting CURVE clients to an
+/// allow-list of public keys.
+///
+/// Handler serves all CURVE server sockets created within the same ZMQ
+/// context; only one handler per context may exist. Connections using other
+/// security mechanisms which request authentication are rejected. The handler
+/// runs in a separate thread, which is stopped when the handler is dropped.
+pub struct ZapHandler {
+    allowed: Arc<RwLock<BTreeSet<[u8; CURVE_KEY_LEN]>>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ZapHandler {
+    /// Starts ZAP handler for the given ZMQ `context`, allowing connections
+    /// only from the clients with `allowed` public keys.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `EADDRINUSE` if other ZAP handler is already running in the
+    /// same context.
+    pub fn start(
+        context: &zmq::Context,
+        allowed: impl IntoIterator<Item = [u8; CURVE_KEY_LEN]>,
+    ) -> Result<ZapHandler, zeromq::Error> {
+        let socket = context.socket(zmq::REP)?;
+        socket.bind(ZAP_ENDPOINT)?;
+        let allowed = Arc::new(RwLock::new(allowed.into_iter().collect()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let allowed = allowed.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || ZapHandler::run(socket, allowed, stop))
+        };
+        Ok(ZapHandler {
+            allowed,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Adds client public key to the allow-list
+    pub fn allow(&self, public_key: [u8; CURVE_KEY_LEN]) {
+        self.allowed
+            .write()
+            .expect("ZAP handler allow-list lock is poisoned")
+            .insert(public_key);
+    }
+
+    /// Removes client public key from the allow-list. Existing connections
+    /// from the client are not affected.
+    pub fn revoke(&self, public_key: &[u8; CURVE_KEY_LEN]) {
+        self.allowed
+            .write()
+            .expect("ZAP handler allow-list lock is poisoned")
+            .remove(public_key);
+    }
+
+    /// Returns current allow-list of client public keys
+    pub fn allowed(&self) -> BTreeSet<[u8; CURVE_KEY_LEN]> {
+        self.allowed
+            .read()
+            .expect("ZAP handler allow-list lock is poisoned")
+            .clone()
+    }
+
+    fn run(
+        socket: zmq::Socket,
+        allowed: Arc<RwLock<BTreeSet<[u8; CURVE_KEY_LEN]>>>,
+        stop: Arc<AtomicBool>,
+    ) {
+        while !stop.load(Ordering::Relaxed) {
+            match socket.poll(zmq::POLLIN, ZAP_POLL_INTERVAL) {
+                Ok(0) => continue,
+                Ok(_) => {}
+                // Context is terminated
+                Err(_) => return,
+            }
+            let request = match socket.recv_multipart(0) {
+                Ok(request) => request,
+                Err(_) => return,
+            };
+            let reply = ZapHandler::authenticate(&request, &allowed);
+            if socket.send_multipart(reply, 0).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Processes ZAP request according to ZMQ RFC 27. Request frames are:
+    /// version, request id, domain, address, identity, mechanism and
+    /// mechanism-specific credentials.
+    fn authenticate(
+        request: &[Vec<u8>],
+        allowed: &RwLock<BTreeSet<[u8; CURVE_KEY_LEN]>>,
+    ) -> Vec<Vec<u8>> {
+        let request_id = request.get(1).cloned().unwrap_or_default();
+        let (code, text): (&[u8], &[u8]) = match request {
+            [version, ..] if version != ZAP_VERSION => {
+                (b"500", b"unsupported ZAP version")
+            }
+            [_, _, _, _, _, mechanism, key]
+                if mechanism.as_slice() == b"CURVE" =>
+            {
+                let allowed = allowed
+                    .read()
+                    .expect("ZAP handler allow-list lock is poisoned");
+                if key.len() == CURVE_KEY_LEN && allowed.contains(&key[..]) {
+                    (b"200", b"OK")
+                } else {
+                    (b"400", b"client key is not allowed")
+                }
+            }
+            [_, _, _, _, _, _, ..] => {
+                (b"400", b"only CURVE clients are allowed")
+            }
+            _ => (b"500", b"malformed ZAP request"),
+        };
+        vec![
+            ZAP_VERSION.to_vec(),
+            request_id,
+            code.to_vec(),
+            text.to_vec(),
+            vec![],
+            vec![],
+        ]
+    }
+}
+
+impl Drop for ZapHandler {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
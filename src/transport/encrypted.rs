@@ -21,6 +21,8 @@ use std::net::{TcpListener, TcpStream};
 use amplify::Bipolar;
 use inet2_addr::InetSocketAddr;
 
+#[cfg(feature = "zmq")]
+use super::Pollable;
 use super::{DuplexConnection, Error, RecvFrame, SendFrame};
 use crate::session::noise;
 use crate::transport::connect::{self, TcpInetStream};
@@ -69,6 +71,12 @@ impl<const LEN_SIZE: usize> Bipolar for Stream<LEN_SIZE> {
     }
 }
 
+#[cfg(feature = "zmq")]
+impl<const LEN_SIZE: usize> Pollable for Stream<LEN_SIZE> {
+    #[inline]
+    fn as_poll_item(&self) -> zmq::PollItem<'_> { self.0.as_poll_item() }
+}
+
 impl<const LEN_SIZE: usize> DuplexConnection for Stream<LEN_SIZE> {
     #[inline]
     fn as_receiver(&mut self) -> &mut dyn RecvFrame { self }
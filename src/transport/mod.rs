@@ -21,6 +21,8 @@
 //! transport protocol used.
 
 pub mod connect;
+#[cfg(feature = "zmq")]
+pub mod curve;
 pub mod encrypted;
 pub mod unencrypted;
 #[cfg(feature = "zmq")]
@@ -29,7 +31,9 @@ pub mod zeromq;
 use std::io::ErrorKind;
 
 #[cfg(feature = "zmq")]
-pub use zeromq::{ZmqConnectionType, ZmqSocketType};
+pub use curve::{CurveConfig, CurveKeys, ZapHandler};
+#[cfg(feature = "zmq")]
+pub use zeromq::{ZmqConnectionType, ZmqOptions, ZmqSocketType};
 
 use crate::session::HandshakeError;
 
@@ -250,6 +254,23 @@ pub trait SendFrame {
     }
 }
 
+/// Connections and sessions which can be awaited for the incoming data with
+/// [`zmq::poll`], allowing a single thread to serve multiple of them.
+#[cfg(feature = "zmq")]
+pub trait Pollable {
+    /// Returns ZMQ poll item signalling availability of the incoming data
+    fn as_poll_item(&self) -> zmq::PollItem<'_>;
+}
+
+#[cfg(feature = "zmq")]
+impl<P> Pollable for Box<P>
+where
+    P: Pollable + ?Sized,
+{
+    #[inline]
+    fn as_poll_item(&self) -> zmq::PollItem<'_> { self.as_ref().as_poll_item() }
+}
+
 /// Async version of [`RecvFrame`] trait
 #[cfg(feature = "async")]
 #[async_trait]
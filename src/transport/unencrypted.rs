@@ -19,6 +19,8 @@ use std::net::{TcpListener, TcpStream};
 use amplify::Bipolar;
 use inet2_addr::InetSocketAddr;
 
+#[cfg(feature = "zmq")]
+use super::Pollable;
 use super::{DuplexConnection, Error, RecvFrame, SendFrame};
 use crate::transport::connect::{self, TcpInetStream};
 
@@ -60,6 +62,12 @@ impl Bipolar for Stream {
     }
 }
 
+#[cfg(feature = "zmq")]
+impl Pollable for Stream {
+    #[inline]
+    fn as_poll_item(&self) -> zmq::PollItem<'_> { self.0.as_poll_item() }
+}
+
 impl DuplexConnection for Stream {
     #[inline]
     fn as_receiver(&mut self) -> &mut dyn RecvFrame { self }
@@ -11,13 +11,16 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use std::collections::BTreeSet;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::str::FromStr;
 
 use amplify::{Bipolar, Wrapper};
 use inet2_addr::ServiceAddr;
 
-use super::{DuplexConnection, RecvFrame, RoutedFrame, SendFrame};
+use super::{
+    CurveConfig, DuplexConnection, Pollable, RecvFrame, RoutedFrame, SendFrame,
+};
 use crate::transport;
 
 /// API type for node-to-node communications used by ZeroMQ
@@ -227,9 +230,92 @@ impl Display for Error {
     }
 }
 
+/// Options of the ZMQ sockets created by [`Connection`]
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ZmqOptions {
+    curve: Option<CurveConfig>,
+    max_msg_size: Option<usize>,
+    topics: BTreeSet<Vec<u8>>,
+}
+
+impl ZmqOptions {
+    /// Constructs options for plain sockets without message size limit and
+    /// subscriptions
+    #[inline]
+    pub fn new() -> Self { Self::default() }
+
+    /// Protects the socket with CURVE security mechanism.
+    ///
+    /// Only the socket connected to the remote address is protected; the
+    /// socket connected to the local address used by PUSH and PULL types is
+    /// expected to be local and runs without CURVE.
+    #[inline]
+    pub fn with_curve(mut self, curve: CurveConfig) -> Self {
+        self.curve = Some(curve);
+        self
+    }
+
+    /// Limits the size of the inbound messages to `max_msg_size` bytes.
+    ///
+    /// The limit is enforced by ZMQ (`ZMQ_MAXMSGSIZE` socket option) before
+    /// the message is received into memory: peers sending larger messages are
+    /// disconnected. The limit applies to each part of multipart messages and
+    /// includes session framing; it does not apply to the `inproc` transport.
+    #[inline]
+    pub fn with_max_msg_size(mut self, max_msg_size: usize) -> Self {
+        self.max_msg_size = Some(max_msg_size);
+        self
+    }
+
+    /// Subscribes SUB socket to the messages which topic starts with
+    /// `prefix`; see [`Connection::subscribe`] for details. Sockets of other
+    /// types can't be created with subscriptions.
+    #[inline]
+    pub fn with_topic(mut self, prefix: impl AsRef<[u8]>) -> Self {
+        self.topics.insert(prefix.as_ref().to_vec());
+        self
+    }
+
+    /// Returns CURVE configuration, if any
+    #[inline]
+    pub fn curve(&self) -> Option<&CurveConfig> { self.curve.as_ref() }
+
+    /// Returns maximum size of the inbound messages, if limited
+    #[inline]
+    pub fn max_msg_size(&self) -> Option<usize> { self.max_msg_size }
+
+    /// Returns topic prefixes to which SUB socket is subscribed
+    #[inline]
+    pub fn topics(&self) -> &BTreeSet<Vec<u8>> { &self.topics }
+
+    /// Applies options to a newly created `socket`, which must not be bound
+    /// or connected yet. CURVE and subscriptions are applied only if `remote`
+    /// is set.
+    fn apply(
+        &self,
+        socket: &zmq::Socket,
+        remote: bool,
+    ) -> Result<(), zmq::Error> {
+        if remote {
+            if let Some(curve) = &self.curve {
+                curve.apply(socket)?;
+            }
+            for topic in &self.topics {
+                socket.set_subscribe(topic)?;
+            }
+        }
+        let max_msg_size = self
+            .max_msg_size
+            .map(|size| i64::try_from(size).unwrap_or(i64::MAX))
+            .unwrap_or(-1);
+        socket.set_maxmsgsize(max_msg_size)
+    }
+}
+
 pub struct WrappedSocket {
     api_type: ZmqSocketType,
     socket: zmq::Socket,
+    options: ZmqOptions,
 }
 
 pub struct Connection {
@@ -240,6 +326,11 @@ pub struct Connection {
 }
 
 impl Connection {
+    /// Creates new ZMQ socket of `api_type` and binds or connects it to the
+    /// `remote` address, depending on the socket type.
+    ///
+    /// SUB sockets are not subscribed to any topic; use
+    /// [`Connection::subscribe`] to receive messages.
     pub fn connect(
         api_type: ZmqSocketType,
         remote: &ServiceAddr,
@@ -247,10 +338,40 @@ impl Connection {
         identity: Option<impl AsRef<[u8]>>,
         context: &zmq::Context,
     ) -> Result<Self, transport::Error> {
+        Self::connect_with_options(
+            api_type,
+            remote,
+            local,
+            identity,
+            ZmqOptions::new(),
+            context,
+        )
+    }
+
+    /// Creates new ZMQ socket of `api_type` configured with `options` and
+    /// binds or connects it to the `remote` address. Otherwise works as
+    /// [`Connection::connect`].
+    ///
+    /// # Errors
+    ///
+    /// Fails with `EINVAL` if `options` have topics, but the socket type is
+    /// not SUB.
+    pub fn connect_with_options(
+        api_type: ZmqSocketType,
+        remote: &ServiceAddr,
+        local: Option<&ServiceAddr>,
+        identity: Option<impl AsRef<[u8]>>,
+        options: ZmqOptions,
+        context: &zmq::Context,
+    ) -> Result<Self, transport::Error> {
+        if api_type != ZmqSocketType::Sub && !options.topics.is_empty() {
+            return Err(zmq::Error::EINVAL.into());
+        }
         let socket = context.socket(api_type.socket_type())?;
         if let Some(identity) = identity {
             socket.set_identity(identity.as_ref())?;
         }
+        options.apply(&socket, true)?;
         let endpoint = remote.zmq_connect_string();
         match api_type {
             ZmqSocketType::Pull
@@ -270,6 +391,7 @@ impl Connection {
             }
             (ZmqSocketType::Push, Some(local)) => {
                 let socket = context.socket(zmq::SocketType::PULL)?;
+                options.apply(&socket, false)?;
                 socket.bind(&local.zmq_connect_string())?;
                 Some(socket)
             }
@@ -278,15 +400,17 @@ impl Connection {
             }
             (_, _) => None,
         }
-        .map(|s| WrappedSocket::with_socket(api_type, s));
+        .map(|s| WrappedSocket::with_options(api_type, s, options.clone()));
         Ok(Self {
             api_type,
             remote_addr: Some(remote.clone()),
-            input: WrappedSocket::with_socket(api_type, socket),
+            input: WrappedSocket::with_options(api_type, socket, options),
             output,
         })
     }
 
+    /// Wraps existing ZMQ socket. For SUB sockets the subscriptions made
+    /// before wrapping are not tracked by the connection.
     pub fn with_socket(api_type: ZmqSocketType, socket: zmq::Socket) -> Self {
         Self {
             api_type,
@@ -296,6 +420,60 @@ impl Connection {
         }
     }
 
+    /// Returns type of the underlying ZMQ socket
+    #[inline]
+    pub fn api_type(&self) -> ZmqSocketType { self.api_type }
+
+    /// Subscribes SUB socket to the messages which topic starts with
+    /// `prefix`; an empty prefix subscribes to all messages. Subscribing to
+    /// the same prefix more than once has no effect, such that a single
+    /// [`Connection::unsubscribe`] call always removes the subscription.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `EINVAL` if the connection is not using SUB socket.
+    pub fn subscribe(&mut self, prefix: impl AsRef<[u8]>) -> Result<(), Error> {
+        if self.api_type != ZmqSocketType::Sub {
+            return Err(Error::from(zmq::Error::EINVAL));
+        }
+        if self.input.options.topics.contains(prefix.as_ref()) {
+            return Ok(());
+        }
+        self.input.as_socket().set_subscribe(prefix.as_ref())?;
+        self.input.options.topics.insert(prefix.as_ref().to_vec());
+        Ok(())
+    }
+
+    /// Removes SUB socket subscription previously made for the `prefix`;
+    /// does nothing if there is no such subscription.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `EINVAL` if the connection is not using SUB socket.
+    pub fn unsubscribe(
+        &mut self,
+        prefix: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        if self.api_type != ZmqSocketType::Sub {
+            return Err(Error::from(zmq::Error::EINVAL));
+        }
+        if !self.input.options.topics.contains(prefix.as_ref()) {
+            return Ok(());
+        }
+        self.input.as_socket().set_unsubscribe(prefix.as_ref())?;
+        self.input.options.topics.remove(prefix.as_ref());
+        Ok(())
+    }
+
+    /// Returns options of the connection socket, including its current
+    /// subscriptions
+    #[inline]
+    pub fn options(&self) -> &ZmqOptions { &self.input.options }
+
+    /// Returns topic prefixes to which SUB socket is subscribed
+    #[inline]
+    pub fn topics(&self) -> &BTreeSet<Vec<u8>> { self.input.options.topics() }
+
     #[inline]
     pub(crate) fn as_socket(&self) -> &zmq::Socket { self.input.as_socket() }
 
@@ -315,22 +493,26 @@ impl Connection {
         } else {
             return Err(Error::from(zmq::Error::EINVAL));
         };
-        let socket = self.input.as_socket_mut();
         let endpoint = addr.zmq_connect_string();
-        socket.disconnect(&endpoint)?;
-        *socket = context.socket(self.api_type.socket_type())?;
-        socket
+        let input = &mut self.input;
+        input.socket.disconnect(&endpoint)?;
+        input.socket = context.socket(self.api_type.socket_type())?;
+        input
+            .socket
             .set_identity(identity.as_ref())
             .map_err(Error::from)?;
+        input.options.apply(&input.socket, true)?;
         match self.api_type {
             ZmqSocketType::Pull
             | ZmqSocketType::Rep
             | ZmqSocketType::Pub
-            | ZmqSocketType::RouterBind => socket.bind(&endpoint)?,
+            | ZmqSocketType::RouterBind => input.socket.bind(&endpoint)?,
             ZmqSocketType::Push
             | ZmqSocketType::Req
             | ZmqSocketType::Sub
-            | ZmqSocketType::RouterConnect => socket.connect(&endpoint)?,
+            | ZmqSocketType::RouterConnect => {
+                input.socket.connect(&endpoint)?
+            }
         }
         Ok(())
     }
@@ -339,7 +521,20 @@ impl Connection {
 impl WrappedSocket {
     #[inline]
     fn with_socket(api_type: ZmqSocketType, socket: zmq::Socket) -> Self {
-        Self { api_type, socket }
+        Self::with_options(api_type, socket, ZmqOptions::new())
+    }
+
+    #[inline]
+    fn with_options(
+        api_type: ZmqSocketType,
+        socket: zmq::Socket,
+        options: ZmqOptions,
+    ) -> Self {
+        Self {
+            api_type,
+            socket,
+            options,
+        }
     }
 
     #[inline]
@@ -351,6 +546,18 @@ impl WrappedSocket {
     }
 }
 
+impl Pollable for WrappedSocket {
+    #[inline]
+    fn as_poll_item(&self) -> zmq::PollItem<'_> {
+        self.socket.as_poll_item(zmq::POLLIN)
+    }
+}
+
+impl Pollable for Connection {
+    #[inline]
+    fn as_poll_item(&self) -> zmq::PollItem<'_> { self.input.as_poll_item() }
+}
+
 impl DuplexConnection for Connection {
     #[inline]
     fn as_receiver(&mut self) -> &mut dyn RecvFrame { &mut self.input }
@@ -398,6 +605,8 @@ impl Bipolar for Connection {
         {
             panic!("ZMQ streams of {} type can't be joined", input.api_type);
         }
+        // Socket options are kept by the sockets themselves, so they survive
+        // splitting and joining of the connection
         Self {
             api_type: input.api_type,
             remote_addr: None,
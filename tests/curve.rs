@@ -0,0 +1,100 @@
+use inet2_addr::ServiceAddr;
+use internet2::session::LocalSession;
+use internet2::transport::curve::curve_supported;
+use internet2::transport::{CurveConfig, CurveKeys, ZapHandler};
+use internet2::{SendRecvMessage, ZmqOptions, ZmqSocketType};
+
+#[test]
+#[cfg(feature = "zmq-curve")]
+fn key_derivation() {
+    // RFC 7748 section 6.1 test vector
+    let secret_key = [
+        0x77, 0x07, 0x6d, 0x0a, 0x73, 0x18, 0xa5, 0x7d, 0x3c, 0x16, 0xc1, 0x72,
+        0x51, 0xb2, 0x66, 0x45, 0xdf, 0x4c, 0x2f, 0x87, 0xeb, 0xc0, 0x99, 0x2a,
+        0xb1, 0x77, 0xfb, 0xa5, 0x1d, 0xb9, 0x2c, 0x2a,
+    ];
+    let public_key = [
+        0x85, 0x20, 0xf0, 0x09, 0x89, 0x30, 0xa7, 0x54, 0x74, 0x8b, 0x7d, 0xdc,
+        0xb4, 0x3e, 0xf7, 0x5a, 0x0d, 0xbf, 0x3a, 0x0d, 0x26, 0x38, 0x1a, 0xf4,
+        0xeb, 0xa4, 0xa9, 0x8e, 0xaa, 0x9b, 0x4e, 0x6a,
+    ];
+    let keys = CurveKeys::with_secret_key(secret_key);
+    assert_eq!(keys.public_key(), public_key);
+    assert_eq!(keys.secret_key(), &secret_key);
+
+    let node_key = secp256k1::SecretKey::from_slice(&[0x11; 32]).unwrap();
+    let keys = CurveKeys::with_node_key(&node_key);
+    assert_eq!(keys, CurveKeys::with_node_key(&node_key));
+    assert_ne!(keys.secret_key(), &node_key.secret_bytes());
+    let other_key = secp256k1::SecretKey::from_slice(&[0x12; 32]).unwrap();
+    assert_ne!(keys, CurveKeys::with_node_key(&other_key));
+    assert!(
+        !format!("{:?}", keys).contains(&format!("{:?}", keys.secret_key()))
+    );
+}
+
+#[test]
+fn allow_list() {
+    if !curve_supported() {
+        eprintln!("libzmq is compiled without CURVE support, skipping test");
+        return;
+    }
+
+    let addr: ServiceAddr = "tcp://127.0.0.1:59878".parse().unwrap();
+    let ctx = zmq::Context::new();
+
+    let server_keys = CurveKeys::generate().unwrap();
+    let client_keys = CurveKeys::generate().unwrap();
+    let stranger_keys = CurveKeys::generate().unwrap();
+    let zap = ZapHandler::start(&ctx, [client_keys.public_key()]).unwrap();
+    assert!(ZapHandler::start(&ctx, []).is_err());
+
+    let server_config = CurveConfig::Server {
+        keys: server_keys.clone(),
+    };
+    let mut server = LocalSession::connect_with_options(
+        ZmqSocketType::Rep,
+        &addr,
+        None,
+        None,
+        ZmqOptions::new().with_curve(server_config),
+        &ctx,
+    )
+    .unwrap();
+
+    let client_config = CurveConfig::Client {
+        keys: client_keys,
+        server_key: server_keys.public_key(),
+    };
+    let mut client = LocalSession::connect_with_options(
+        ZmqSocketType::Req,
+        &addr,
+        None,
+        None,
+        ZmqOptions::new().with_curve(client_config),
+        &ctx,
+    )
+    .unwrap();
+    client.send_raw_message(b"ping").unwrap();
+    assert_eq!(server.recv_raw_message().unwrap(), b"ping");
+    server.send_raw_message(b"pong").unwrap();
+    assert_eq!(client.recv_raw_message().unwrap(), b"pong");
+
+    let stranger_config = CurveConfig::Client {
+        keys: stranger_keys,
+        server_key: server_keys.public_key(),
+    };
+    let mut stranger = LocalSession::connect_with_options(
+        ZmqSocketType::Req,
+        &addr,
+        None,
+        None,
+        ZmqOptions::new().with_curve(stranger_config),
+        &ctx,
+    )
+    .unwrap();
+    stranger.send_raw_message(b"ping").unwrap();
+    assert_eq!(server.as_socket().poll(zmq::POLLIN, 500).unwrap(), 0);
+
+    drop(zap);
+}
@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use inet2_addr::ServiceAddr;
+use internet2::esb::{Broker, Client, Control, Delivery};
+use internet2::session::LocalSession;
+use internet2::{SendRecvMessage, ZmqSocketType};
+use strict_encoding::StrictDecode;
+
+fn client(addr: &ServiceAddr, identity: &[u8], ctx: &zmq::Context) -> Client {
+    let session = LocalSession::connect(
+        ZmqSocketType::RouterConnect,
+        addr,
+        None,
+        Some(identity),
+        ctx,
+    )
+    .unwrap();
+    Client::with(session, identity, b"broker")
+}
+
+#[test]
+fn bus() {
+    let addr: ServiceAddr = "inproc://esb-test".parse().unwrap();
+    let ctx = zmq::Context::new();
+
+    let session = LocalSession::connect(
+        ZmqSocketType::RouterBind,
+        &addr,
+        None,
+        Some(b"broker"),
+        &ctx,
+    )
+    .unwrap();
+    let mut broker = Broker::with(session, b"broker");
+    broker.set_presence_notifications(true);
+    std::thread::spawn(move || broker.run().unwrap());
+
+    let mut alice = client(&addr, b"alice-socket", &ctx);
+    let mut bob = client(&addr, b"bob-socket", &ctx);
+    // ROUTER sockets drop messages to not-yet-connected peers
+    std::thread::sleep(Duration::from_millis(100));
+
+    alice.register(b"alice").unwrap();
+    assert_eq!(
+        alice.recv().unwrap(),
+        Delivery::Control(Control::Registered(b"alice".to_vec()))
+    );
+    assert_eq!(alice.name(), Some(&b"alice"[..]));
+
+    bob.register(b"bob").unwrap();
+    assert_eq!(
+        bob.recv().unwrap(),
+        Delivery::Control(Control::Registered(b"bob".to_vec()))
+    );
+    assert_eq!(
+        alice.recv().unwrap(),
+        Delivery::Control(Control::Joined(b"bob".to_vec()))
+    );
+
+    bob.send(b"alice", b"hello").unwrap();
+    assert_eq!(alice.recv().unwrap(), Delivery::Message {
+        src: b"bob".to_vec(),
+        msg: b"hello".to_vec()
+    });
+    alice.send(b"bob-socket", b"world").unwrap();
+    assert_eq!(bob.recv().unwrap(), Delivery::Message {
+        src: b"alice".to_vec(),
+        msg: b"world".to_vec()
+    });
+
+    alice.send(b"carol", b"lost").unwrap();
+    assert_eq!(
+        alice.recv().unwrap(),
+        Delivery::Control(Control::UnknownDestination(b"carol".to_vec()))
+    );
+
+    bob.register(b"alice").unwrap();
+    assert_eq!(
+        bob.recv().unwrap(),
+        Delivery::Control(Control::NameTaken(b"alice".to_vec()))
+    );
+
+    bob.unregister().unwrap();
+    assert_eq!(
+        alice.recv().unwrap(),
+        Delivery::Control(Control::Left(b"bob".to_vec()))
+    );
+    alice.send(b"bob", b"lost").unwrap();
+    assert_eq!(
+        alice.recv().unwrap(),
+        Delivery::Control(Control::UnknownDestination(b"bob".to_vec()))
+    );
+    // Unregistered service remains reachable by its socket identity
+    alice.send(b"bob-socket", b"still here").unwrap();
+    assert_eq!(bob.recv().unwrap(), Delivery::Message {
+        src: b"alice".to_vec(),
+        msg: b"still here".to_vec()
+    });
+}
+
+#[test]
+fn impersonation() {
+    let addr: ServiceAddr = "inproc://esb-test-impersonation".parse().unwrap();
+    let ctx = zmq::Context::new();
+
+    let session = LocalSession::connect(
+        ZmqSocketType::RouterBind,
+        &addr,
+        None,
+        Some(b"broker"),
+        &ctx,
+    )
+    .unwrap();
+    let broker = Broker::with(session, b"broker");
+    std::thread::spawn(move || {
+        let mut broker = broker;
+        broker.run().unwrap()
+    });
+
+    let mut alice = client(&addr, b"alice-socket", &ctx);
+    let mut mallory = LocalSession::connect(
+        ZmqSocketType::RouterConnect,
+        &addr,
+        None,
+        Some(b"mallory"),
+        &ctx,
+    )
+    .unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+
+    alice.register(b"alice").unwrap();
+    assert_eq!(
+        alice.recv().unwrap(),
+        Delivery::Control(Control::Registered(b"alice".to_vec()))
+    );
+
+    // Malformed control message does not stop the broker
+    mallory
+        .send_routed_message(b"mallory", b"broker", b"broker", b"\xFF")
+        .unwrap();
+    let frame = mallory.recv_routed_message().unwrap();
+    assert_eq!(frame.dst, b"broker");
+    assert_eq!(
+        Control::strict_deserialize(frame.msg).unwrap(),
+        Control::Malformed
+    );
+
+    // Forged source names are rejected
+    for src in [&b"alice"[..], b"alice-socket", b"broker"] {
+        mallory
+            .send_routed_message(src, b"broker", b"alice", b"forged")
+            .unwrap();
+        let frame = mallory.recv_routed_message().unwrap();
+        assert_eq!(frame.dst, b"broker");
+        assert_eq!(
+            Control::strict_deserialize(frame.msg).unwrap(),
+            Control::InvalidSource(src.to_vec())
+        );
+    }
+
+    // Names clashing with the broker or other services can't be registered
+    let mut mallory = Client::with(mallory, b"mallory", b"broker");
+    for name in [&b"broker"[..], b"alice-socket"] {
+        mallory.register(name).unwrap();
+        assert_eq!(
+            mallory.recv().unwrap(),
+            Delivery::Control(Control::NameTaken(name.to_vec()))
+        );
+    }
+
+    // Services with socket identity matching a registered name are refused
+    let mut eve = LocalSession::connect(
+        ZmqSocketType::RouterConnect,
+        &addr,
+        None,
+        Some(b"alice"),
+        &ctx,
+    )
+    .unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    for dst in [&b"mallory-socket"[..], b"broker"] {
+        eve.send_routed_message(b"alice", b"broker", dst, b"forged")
+            .unwrap();
+        let frame = eve.recv_routed_message().unwrap();
+        assert_eq!(frame.dst, b"broker");
+        assert_eq!(
+            Control::strict_deserialize(frame.msg).unwrap(),
+            Control::IdentityTaken(b"alice".to_vec())
+        );
+    }
+
+    // Broker still forwards valid frames
+    mallory.register(b"mallory").unwrap();
+    mallory.recv().unwrap();
+    mallory.send(b"alice", b"hi").unwrap();
+    assert_eq!(alice.recv().unwrap(), Delivery::Message {
+        src: b"mallory".to_vec(),
+        msg: b"hi".to_vec()
+    });
+    assert_eq!(alice.name(), Some(&b"alice"[..]));
+}
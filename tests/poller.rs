@@ -0,0 +1,87 @@
+use std::convert::TryFrom;
+use std::net::{SocketAddr, TcpListener};
+use std::str::FromStr;
+use std::time::Duration;
+
+use inet2_addr::{LocalNode, NodeAddr, ServiceAddr};
+use internet2::session::{BrontideSession, LocalSession, PollableSession};
+use internet2::{SendRecvMessage, SessionPoller, ZmqSocketType};
+use secp256k1::Secp256k1;
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Endpoint {
+    Rpc,
+    Bus,
+    Peer,
+}
+
+#[test]
+fn main() {
+    let ctx = zmq::Context::new();
+    let rpc_addr: ServiceAddr = "inproc://poller-rpc".parse().unwrap();
+    let bus_addr: ServiceAddr = "inproc://poller-bus".parse().unwrap();
+
+    let secp = Secp256k1::new();
+    let local_node = LocalNode::new(&secp);
+    let remote_node = LocalNode::new(&secp);
+    let node = NodeAddr::from_str(&format!(
+        "{}@127.0.0.1:59879",
+        local_node.node_id()
+    ))
+    .unwrap();
+    let listener =
+        TcpListener::bind(SocketAddr::try_from(node.addr).unwrap()).unwrap();
+    let remote = std::thread::spawn(move || {
+        let mut session =
+            BrontideSession::connect(remote_node.private_key(), node).unwrap();
+        session.send_raw_message(b"peer").unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+    });
+    let peer =
+        BrontideSession::accept(local_node.private_key(), &listener).unwrap();
+
+    let mut poller = SessionPoller::<Endpoint>::new();
+    let session =
+        LocalSession::connect(ZmqSocketType::Rep, &rpc_addr, None, None, &ctx)
+            .unwrap();
+    poller.register(Endpoint::Rpc, Box::new(session));
+    let session =
+        LocalSession::connect(ZmqSocketType::Rep, &bus_addr, None, None, &ctx)
+            .unwrap();
+    poller.register(Endpoint::Bus, Box::new(session));
+    poller.register(Endpoint::Peer, Box::new(peer) as Box<dyn PollableSession>);
+    assert_eq!(poller.len(), 3);
+
+    let mut rpc_client =
+        LocalSession::connect(ZmqSocketType::Req, &rpc_addr, None, None, &ctx)
+            .unwrap();
+    let mut bus_client =
+        LocalSession::connect(ZmqSocketType::Req, &bus_addr, None, None, &ctx)
+            .unwrap();
+
+    assert_eq!(poller.poll(None).unwrap(), vec![Endpoint::Peer]);
+    let session = poller.get_mut(&Endpoint::Peer).unwrap();
+    assert_eq!(session.recv_raw_message().unwrap(), b"peer");
+    assert!(poller
+        .poll(Some(Duration::from_millis(100)))
+        .unwrap()
+        .is_empty());
+
+    rpc_client.send_raw_message(b"rpc").unwrap();
+    bus_client.send_raw_message(b"bus").unwrap();
+    assert_eq!(poller.poll(None).unwrap(), vec![
+        Endpoint::Rpc,
+        Endpoint::Bus
+    ]);
+
+    let session = poller.get_mut(&Endpoint::Bus).unwrap();
+    assert_eq!(session.recv_raw_message().unwrap(), b"bus");
+    assert_eq!(poller.poll(None).unwrap(), vec![Endpoint::Rpc]);
+    let session = poller.get_mut(&Endpoint::Rpc).unwrap();
+    assert_eq!(session.recv_raw_message().unwrap(), b"rpc");
+
+    assert!(poller.unregister(&Endpoint::Peer).is_some());
+    assert_eq!(poller.len(), 2);
+
+    remote.join().unwrap();
+}
@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use inet2_addr::ServiceAddr;
+use internet2::pubsub::{Publisher, Subscriber, Topic};
+use internet2::session::LocalSession;
+use internet2::{Api, SendRecvMessage, TypedEnum, ZmqSocketType};
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "strict")]
+pub enum Event {
+    #[api(type = 0x0001)]
+    Block(u32),
+
+    #[api(type = 0x0003)]
+    Tx(String),
+}
+
+#[test]
+fn topics() {
+    let addr: ServiceAddr = "inproc://pubsub-test".parse().unwrap();
+    let ctx = zmq::Context::new();
+
+    let session =
+        LocalSession::connect(ZmqSocketType::Pub, &addr, None, None, &ctx)
+            .unwrap();
+    let mut publisher = Publisher::<Event>::with(session);
+
+    let session =
+        LocalSession::connect(ZmqSocketType::Sub, &addr, None, None, &ctx)
+            .unwrap();
+    let mut subscriber = Subscriber::<Event>::with(session).unwrap();
+    assert!(subscriber.topics().is_empty());
+    subscriber.subscribe("mempool/").unwrap();
+    subscriber
+        .subscribe_type(Event::Block(0).get_type())
+        .unwrap();
+    assert_eq!(subscriber.topics().len(), 2);
+    // Let the subscription propagate to the publisher
+    std::thread::sleep(Duration::from_millis(100));
+
+    publisher
+        .publish("chain/", &Event::Tx("ignored".to_owned()))
+        .unwrap();
+    publisher
+        .publish("mempool/in", &Event::Tx("tx".to_owned()))
+        .unwrap();
+    publisher
+        .publish_typed(&Event::Tx("ignored".to_owned()))
+        .unwrap();
+    publisher.publish_typed(&Event::Block(1)).unwrap();
+
+    let publication = subscriber.recv().unwrap();
+    assert_eq!(publication.topic, Topic::from("mempool/in"));
+    assert_eq!(*publication.msg, Event::Tx("tx".to_owned()));
+
+    let publication = subscriber.recv().unwrap();
+    assert_eq!(publication.topic, Topic::from("type/0001"));
+    assert_eq!(*publication.msg, Event::Block(1));
+
+    subscriber.unsubscribe("mempool/").unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    publisher
+        .publish("mempool/in", &Event::Tx("ignored".to_owned()))
+        .unwrap();
+    publisher.publish_typed(&Event::Block(2)).unwrap();
+    assert_eq!(*subscriber.recv().unwrap().msg, Event::Block(2));
+}
+
+#[test]
+fn plain_sub_session() {
+    let addr: ServiceAddr = "inproc://pubsub-test-plain".parse().unwrap();
+    let ctx = zmq::Context::new();
+
+    let mut publisher =
+        LocalSession::connect(ZmqSocketType::Pub, &addr, None, None, &ctx)
+            .unwrap();
+    let mut subscriber =
+        LocalSession::connect(ZmqSocketType::Sub, &addr, None, None, &ctx)
+            .unwrap();
+    assert!(subscriber.topics().is_empty());
+    subscriber.subscribe(b"").unwrap();
+    subscriber.subscribe(b"").unwrap();
+    subscriber.unsubscribe(b"").unwrap();
+    assert!(subscriber.topics().is_empty());
+    subscriber.subscribe(b"").unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+
+    publisher.send_raw_message(b"hello").unwrap();
+    assert_eq!(subscriber.recv_raw_message().unwrap(), b"hello");
+}
@@ -0,0 +1,218 @@
+use std::net::TcpListener;
+use std::str::FromStr;
+use std::thread;
+
+use inet2_addr::{LocalNode, NodeAddr};
+use internet2::addr::NodeId;
+use internet2::relay::{Delivery, Relay, RelayError};
+use internet2::session::BrontideSession;
+use internet2::sphinx::{
+    Hop, OnionPacket, ReplayCache, RouteKeys, TlvHopPayload,
+};
+use internet2::tlv;
+use secp256k1::{Secp256k1, SecretKey};
+
+const PACKET_LEN: usize = 1300;
+
+fn payload(data: u8, next_hop: Option<NodeId>) -> TlvHopPayload {
+    let mut stream = tlv::Stream::new();
+    stream.insert(2usize.into(), [data]);
+    let mut payload = TlvHopPayload::with(stream);
+    if let Some(node_id) = next_hop {
+        payload.set_next_hop(node_id);
+    }
+    payload
+}
+
+fn listen(node: &LocalNode) -> (TcpListener, NodeAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let addr =
+        NodeAddr::from_str(&format!("{}@127.0.0.1:{}", node.node_id(), port))
+            .unwrap();
+    (listener, addr)
+}
+
+#[test]
+fn relay() {
+    let secp = Secp256k1::new();
+    let origin = LocalNode::new(&secp);
+    let relay_node = LocalNode::new(&secp);
+    let final_node = LocalNode::new(&secp);
+
+    let (relay_listener, relay_addr) = listen(&relay_node);
+    let (final_listener, final_addr) = listen(&final_node);
+
+    let hops = vec![
+        Hop::with(relay_node.node_id(), payload(1, Some(final_node.node_id()))),
+        Hop::with(final_node.node_id(), payload(2, None)),
+    ];
+    let expected = hops.clone();
+
+    let final_thread = thread::spawn(move || {
+        let mut relay =
+            Relay::<TlvHopPayload, PACKET_LEN>::with(final_node.private_key());
+        // Final node has sessions both with the origin and the relay node
+        // and must receive the packet from whichever peer sends it
+        for _ in 0..2 {
+            let session = BrontideSession::accept(
+                final_node.private_key(),
+                &final_listener,
+            )
+            .unwrap();
+            relay.add_brontide(session);
+        }
+        assert_eq!(relay.peers().count(), 2);
+        relay.recv().unwrap()
+    });
+
+    let relay_thread = thread::spawn(move || {
+        let mut relay =
+            Relay::<TlvHopPayload, PACKET_LEN>::with(relay_node.private_key());
+        let session =
+            BrontideSession::accept(relay_node.private_key(), &relay_listener)
+                .unwrap();
+        relay.add_brontide(session);
+        let session =
+            BrontideSession::connect(relay_node.private_key(), final_addr)
+                .unwrap();
+        relay.add_brontide(session);
+        assert_eq!(relay.peers().count(), 2);
+        relay.recv().unwrap()
+    });
+
+    let mut relay =
+        Relay::<TlvHopPayload, PACKET_LEN>::with(origin.private_key());
+    let session =
+        BrontideSession::connect(origin.private_key(), relay_addr).unwrap();
+    relay.add_brontide(session);
+    let direct =
+        BrontideSession::connect(origin.private_key(), final_addr).unwrap();
+
+    let onion = OnionPacket::with(&secp, &hops, &[]).unwrap();
+    assert_eq!(
+        relay.send(final_node.node_id(), &onion),
+        Err(RelayError::UnknownPeer(final_node.node_id()))
+    );
+    relay.send(relay_node.node_id(), &onion).unwrap();
+
+    assert_eq!(relay_thread.join().unwrap(), Delivery::Forwarded {
+        payload: expected[0].payload.clone(),
+        next_hop: final_node.node_id(),
+    });
+    assert_eq!(final_thread.join().unwrap(), Delivery::Delivered {
+        from: relay_node.node_id(),
+        payload: expected[1].payload.clone(),
+    });
+    drop(direct);
+}
+
+#[test]
+fn relay_disconnect() {
+    let secp = Secp256k1::new();
+    let relay_node = LocalNode::new(&secp);
+    let peer = LocalNode::new(&secp);
+    let (listener, addr) = listen(&relay_node);
+
+    let relay_thread = thread::spawn(move || {
+        let mut relay =
+            Relay::<TlvHopPayload, PACKET_LEN>::with(relay_node.private_key());
+        let session =
+            BrontideSession::accept(relay_node.private_key(), &listener)
+                .unwrap();
+        relay.add_brontide(session);
+        let err = relay.recv().unwrap_err();
+        let peers = relay.peers().copied().collect::<Vec<_>>();
+        (err, peers, relay.recv())
+    });
+
+    let session = BrontideSession::connect(peer.private_key(), addr).unwrap();
+    drop(session);
+
+    let (err, peers, next) = relay_thread.join().unwrap();
+    match err {
+        RelayError::PeerFailed(node_id, _) => {
+            assert_eq!(node_id, peer.node_id())
+        }
+        err => panic!("unexpected relay error: {}", err),
+    }
+    assert!(peers.is_empty());
+    assert_eq!(next, Err(RelayError::NoPeers));
+}
+
+#[test]
+fn relay_errors() {
+    let secp = Secp256k1::new();
+    let node_secret = SecretKey::from_slice(&[1u8; 32]).unwrap();
+    let node_id = secp256k1::PublicKey::from_secret_key(&secp, &node_secret);
+    let peer = SecretKey::from_slice(&[2u8; 32]).unwrap();
+    let peer =
+        NodeId::from(secp256k1::PublicKey::from_secret_key(&secp, &peer));
+
+    let mut relay = Relay::<TlvHopPayload, PACKET_LEN>::with(node_secret);
+    relay.set_replay_store(ReplayCache::with_capacity(16));
+    assert_eq!(relay.node_id(), node_id.into());
+
+    let hops = vec![
+        Hop::with(node_id.into(), payload(1, None)),
+        Hop::with(peer, payload(2, None)),
+    ];
+    let onion = OnionPacket::<PACKET_LEN>::with(&secp, &hops, &[]).unwrap();
+    let data = lightning_encoding::LightningEncode::lightning_serialize(&onion)
+        .unwrap();
+    assert_eq!(relay.process(peer, &data), Err(RelayError::NoNextHop));
+    assert!(matches!(
+        relay.process(peer, &data),
+        Err(RelayError::Replay(_))
+    ));
+
+    relay.set_assoc_data(b"other");
+    let hops = vec![Hop::with(node_id.into(), payload(1, None))];
+    let onion = OnionPacket::<PACKET_LEN>::with(&secp, &hops, &[]).unwrap();
+    let data = lightning_encoding::LightningEncode::lightning_serialize(&onion)
+        .unwrap();
+    assert_eq!(relay.process(peer, &data), Err(RelayError::InvalidHmac));
+    assert_eq!(relay.replay_store().map(ReplayCache::len), Some(1));
+    assert_eq!(relay.recv(), Err(RelayError::NoPeers));
+}
+
+#[test]
+fn relay_batch() {
+    let secp = Secp256k1::new();
+    let node_secret = SecretKey::from_slice(&[1u8; 32]).unwrap();
+    let node_id = NodeId::from(secp256k1::PublicKey::from_secret_key(
+        &secp,
+        &node_secret,
+    ));
+    let peer = SecretKey::from_slice(&[2u8; 32]).unwrap();
+    let peer =
+        NodeId::from(secp256k1::PublicKey::from_secret_key(&secp, &peer));
+
+    let mut relay = Relay::<TlvHopPayload, PACKET_LEN>::with(node_secret);
+    relay.set_replay_store(ReplayCache::with_capacity(16));
+
+    let mut keys = RouteKeys::with(&secp, [node_id]).unwrap();
+    for part in 0u8..2 {
+        let (onion, _) = OnionPacket::<PACKET_LEN>::with_route_keys(
+            keys.next(&secp),
+            &[Hop::with(node_id, payload(part, None))],
+            &[],
+        )
+        .unwrap();
+        let data =
+            lightning_encoding::LightningEncode::lightning_serialize(&onion)
+                .unwrap();
+        assert_eq!(
+            relay.process(peer, &data),
+            Ok(Delivery::Delivered {
+                from: peer,
+                payload: payload(part, None),
+            })
+        );
+        assert!(matches!(
+            relay.process(peer, &data),
+            Err(RelayError::Replay(_))
+        ));
+    }
+    assert_eq!(relay.replay_store().map(ReplayCache::len), Some(2));
+}
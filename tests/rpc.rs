@@ -0,0 +1,285 @@
+use std::time::Duration;
+
+use inet2_addr::ServiceAddr;
+use internet2::rpc::{self, Client, Failure, Frame, FrameKind, Server};
+use internet2::session::{LocalSession, SendRecvMessage};
+use strict_encoding::StrictDecode;
+use internet2::{Api, ZmqSocketType};
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "strict")]
+pub enum Request {
+    #[api(type = 0x0001)]
+    Ping(u16),
+
+    #[api(type = 0x0003)]
+    Fail,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "strict")]
+pub enum Reply {
+    #[api(type = 0x0002)]
+    Pong(u16),
+}
+
+#[test]
+fn req_rep() {
+    let addr: ServiceAddr = "inproc://rpc-test-reqrep".parse().unwrap();
+    let ctx = zmq::Context::new();
+
+    let session =
+        LocalSession::connect(ZmqSocketType::Rep, &addr, None, None, &ctx)
+            .unwrap();
+    let mut server = Server::<Request, Reply>::with(session);
+
+    let session =
+        LocalSession::connect(ZmqSocketType::Req, &addr, None, None, &ctx)
+            .unwrap();
+    let client = std::thread::spawn(move || {
+        let mut client = Client::<Request, Reply>::with(session);
+        assert_eq!(client.call(&Request::Ping(7)).unwrap(), Reply::Pong(7));
+        assert_eq!(
+            client.call(&Request::Fail).unwrap_err(),
+            rpc::Error::Failure(Failure::with(Failure::APPLICATION, "failed"))
+        );
+        assert!(client.pending_calls().is_empty());
+    });
+
+    for _ in 0..2 {
+        server
+            .serve(|req| match req {
+                Request::Ping(n) => Ok(Reply::Pong(n)),
+                Request::Fail => {
+                    Err(Failure::with(Failure::APPLICATION, "failed"))
+                }
+            })
+            .unwrap();
+    }
+
+    client.join().unwrap();
+}
+
+#[test]
+fn malformed_frame() {
+    let addr: ServiceAddr = "inproc://rpc-test-malformed".parse().unwrap();
+    let ctx = zmq::Context::new();
+
+    let session =
+        LocalSession::connect(ZmqSocketType::Rep, &addr, None, None, &ctx)
+            .unwrap();
+    let mut server = Server::<Request, Reply>::with(session);
+
+    let mut session =
+        LocalSession::connect(ZmqSocketType::Req, &addr, None, None, &ctx)
+            .unwrap();
+    let client = std::thread::spawn(move || {
+        session.send_raw_message(&[0x01, 0x02]).unwrap();
+        let frame = Frame::deserialize(session.recv_raw_message().unwrap())
+            .unwrap();
+        assert_eq!(frame.kind, FrameKind::Failure);
+        let failure =
+            Failure::strict_decode(std::io::Cursor::new(frame.data)).unwrap();
+        assert_eq!(failure.code, Failure::MALFORMED_REQUEST);
+
+        // REQ socket is usable again since the server has replied
+        let mut client = Client::<Request, Reply>::with(session);
+        assert_eq!(client.call(&Request::Ping(1)).unwrap(), Reply::Pong(1));
+    });
+
+    assert!(matches!(server.recv(), Err(rpc::Error::Encoding(_))));
+    server
+        .serve(|req| match req {
+            Request::Ping(n) => Ok(Reply::Pong(n)),
+            Request::Fail => Err(Failure::with(Failure::APPLICATION, "failed")),
+        })
+        .unwrap();
+
+    client.join().unwrap();
+}
+
+#[test]
+fn router_concurrent_calls() {
+    let addr: ServiceAddr = "inproc://rpc-test-router".parse().unwrap();
+    let ctx = zmq::Context::new();
+
+    let session = LocalSession::connect(
+        ZmqSocketType::RouterBind,
+        &addr,
+        None,
+        Some(b"server"),
+        &ctx,
+    )
+    .unwrap();
+    let mut server = Server::<Request, Reply>::with_router(session, b"server");
+
+    let session = LocalSession::connect(
+        ZmqSocketType::RouterConnect,
+        &addr,
+        None,
+        Some(b"client"),
+        &ctx,
+    )
+    .unwrap();
+    let client = std::thread::spawn(move || {
+        // ROUTER sockets drop messages to not-yet-connected peers
+        std::thread::sleep(Duration::from_millis(100));
+        let mut client = Client::<Request, Reply>::with_router(
+            session, b"client", b"server",
+        );
+        let first = client.send(&Request::Ping(1)).unwrap();
+        let second = client.send(&Request::Ping(2)).unwrap();
+        let third = client.send(&Request::Ping(3)).unwrap();
+        assert_eq!(client.pending_calls().len(), 3);
+        assert_eq!(client.recv(third).unwrap(), Reply::Pong(3));
+        assert_eq!(client.recv(first).unwrap(), Reply::Pong(1));
+        assert_eq!(client.recv(second).unwrap(), Reply::Pong(2));
+        assert_eq!(
+            client.recv(second).unwrap_err(),
+            rpc::Error::UnknownCall(second)
+        );
+
+        client.set_timeout(Duration::from_millis(100));
+        let lost = client.send(&Request::Ping(4)).unwrap();
+        assert_eq!(client.recv(lost).unwrap_err(), rpc::Error::Timeout(lost));
+    });
+
+    // Replying in reverse order
+    let calls = (0..3).map(|_| server.recv().unwrap()).collect::<Vec<_>>();
+    for call in calls.iter().rev() {
+        let reply = match call.request {
+            Request::Ping(n) => Reply::Pong(n),
+            Request::Fail => unreachable!(),
+        };
+        server.reply(call, &reply).unwrap();
+    }
+    // Receiving the last request but never replying to it
+    server.recv().unwrap();
+
+    client.join().unwrap();
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "strict")]
+pub enum Query {
+    #[api(type = 0x0011, reply = "Answer::Value", error = "Answer::Missing")]
+    Get(String),
+
+    #[api(type = 0x0013, reply = "Answer::Done")]
+    Set(String),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "strict")]
+pub enum Answer {
+    #[api(type = 0x0012)]
+    Value(String),
+
+    #[api(type = 0x0014)]
+    Missing,
+
+    #[api(type = 0x0016)]
+    Done,
+}
+
+#[test]
+fn checked_replies() {
+    let addr: ServiceAddr = "inproc://rpc-test-checked".parse().unwrap();
+    let ctx = zmq::Context::new();
+
+    let session =
+        LocalSession::connect(ZmqSocketType::Rep, &addr, None, None, &ctx)
+            .unwrap();
+    let mut server = Server::<Query, Answer>::with(session);
+
+    let session =
+        LocalSession::connect(ZmqSocketType::Req, &addr, None, None, &ctx)
+            .unwrap();
+    let client = std::thread::spawn(move || {
+        let mut client = Client::<Query, Answer>::with(session);
+        assert_eq!(
+            client.call_checked(&Query::Get("key".to_owned())).unwrap(),
+            Answer::Value("value".to_owned())
+        );
+        assert_eq!(
+            client.call_checked(&Query::Get("none".to_owned())).unwrap(),
+            Answer::Missing
+        );
+        assert_eq!(
+            client
+                .call_checked(&Query::Set("key".to_owned()))
+                .unwrap_err(),
+            rpc::Error::UnexpectedReply(Answer::MSG_TYPE_VALUE.into())
+        );
+    });
+
+    for _ in 0..3 {
+        server
+            .serve(|req| match req {
+                Query::Get(key) if key == "key" => {
+                    Ok(Answer::Value("value".to_owned()))
+                }
+                Query::Get(_) => Ok(Answer::Missing),
+                // Intentionally wrong reply type
+                Query::Set(_) => Ok(Answer::Value("value".to_owned())),
+            })
+            .unwrap();
+    }
+
+    client.join().unwrap();
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Api)]
+#[api(encoding = "strict", handler = "CommandHandler")]
+pub enum Command {
+    #[api(type = 0x0021)]
+    Add(u16),
+
+    #[api(type = 0x0023)]
+    Reset,
+}
+
+struct Counter(u16);
+
+impl CommandHandler for Counter {
+    type Reply = Result<Reply, Failure>;
+
+    fn handle_add(&mut self, value: &u16) -> Self::Reply {
+        self.0 += value;
+        Ok(Reply::Pong(self.0))
+    }
+}
+
+#[test]
+fn dispatched_calls() {
+    let addr: ServiceAddr = "inproc://rpc-test-dispatch".parse().unwrap();
+    let ctx = zmq::Context::new();
+
+    let session =
+        LocalSession::connect(ZmqSocketType::Rep, &addr, None, None, &ctx)
+            .unwrap();
+    let mut server = Server::<Command, Reply>::with(session);
+
+    let session =
+        LocalSession::connect(ZmqSocketType::Req, &addr, None, None, &ctx)
+            .unwrap();
+    let client = std::thread::spawn(move || {
+        let mut client = Client::<Command, Reply>::with(session);
+        assert_eq!(client.call(&Command::Add(2)).unwrap(), Reply::Pong(2));
+        assert_eq!(client.call(&Command::Add(3)).unwrap(), Reply::Pong(5));
+        match client.call(&Command::Reset).unwrap_err() {
+            rpc::Error::Failure(failure) => {
+                assert_eq!(failure.code, Failure::UNSUPPORTED_REQUEST)
+            }
+            err => panic!("unexpected error {}", err),
+        }
+    });
+
+    let mut counter = Counter(0);
+    for _ in 0..3 {
+        server.dispatch(&mut counter).unwrap();
+    }
+    assert_eq!(counter.0, 5);
+
+    client.join().unwrap();
+}
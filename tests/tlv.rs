@@ -275,6 +275,20 @@ fn tlv_unknown() -> Result {
         .map_err(Error::from)
 }
 
+#[test]
+fn tlv_raw_value_len() {
+    use internet2::tlv::RawValue;
+    use lightning_encoding::LightningEncode;
+
+    // Values crossing BigSize length prefix boundaries
+    for len in [0usize, 1, 252, 253, 0x10000] {
+        let value = RawValue::from(vec![0xA5u8; len].into_boxed_slice());
+        let mut data = vec![];
+        let written = value.lightning_encode(&mut data).unwrap();
+        assert_eq!(written, data.len());
+    }
+}
+
 // TODO: Complete TLV encoding derivation test cases:
 //       - Failing on unknown even fields
 //       - Failed lengths etc
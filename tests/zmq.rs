@@ -1,6 +1,11 @@
+use std::time::Duration;
+
+use amplify::Bipolar;
 use inet2_addr::ServiceAddr;
 use internet2::session::LocalSession;
-use internet2::{SendRecvMessage, ZmqSocketType};
+use internet2::transport::DuplexConnection;
+use internet2::zeromq::Connection;
+use internet2::{SendRecvMessage, ZmqOptions, ZmqSocketType};
 
 #[test]
 fn main() {
@@ -59,3 +64,91 @@ fn main() {
 
     tx.join().unwrap();
 }
+
+#[test]
+fn max_msg_size() {
+    let addr: ServiceAddr = "tcp://127.0.0.1:59879".parse().unwrap();
+    let ctx = zmq::Context::new();
+
+    let mut server = LocalSession::connect_with_options(
+        ZmqSocketType::Rep,
+        &addr,
+        None,
+        None,
+        ZmqOptions::new().with_max_msg_size(1024),
+        &ctx,
+    )
+    .unwrap();
+
+    let mut client =
+        LocalSession::connect(ZmqSocketType::Req, &addr, None, None, &ctx)
+            .unwrap();
+    client.send_raw_message(&[0u8; 1024]).unwrap();
+    assert_eq!(server.recv_raw_message().unwrap(), [0u8; 1024]);
+    server.send_raw_message(b"pong").unwrap();
+    assert_eq!(client.recv_raw_message().unwrap(), b"pong");
+
+    // Peers sending oversized messages are disconnected by ZMQ before the
+    // message reaches the application
+    client.send_raw_message(&[0u8; 1025]).unwrap();
+    assert_eq!(server.as_socket().poll(zmq::POLLIN, 500).unwrap(), 0);
+}
+
+#[test]
+fn options() {
+    let addr: ServiceAddr = "inproc://zmq-test-options".parse().unwrap();
+    let ctx = zmq::Context::new();
+
+    let options = ZmqOptions::new().with_max_msg_size(1024).with_topic(b"a");
+    assert!(Connection::connect_with_options(
+        ZmqSocketType::Pub,
+        &addr,
+        None,
+        None::<&[u8]>,
+        options.clone(),
+        &ctx
+    )
+    .is_err());
+
+    let mut publisher = Connection::connect(
+        ZmqSocketType::Pub,
+        &addr,
+        None,
+        None::<&[u8]>,
+        &ctx,
+    )
+    .unwrap();
+    let mut subscriber = Connection::connect_with_options(
+        ZmqSocketType::Sub,
+        &addr,
+        None,
+        None::<&[u8]>,
+        options.clone(),
+        &ctx,
+    )
+    .unwrap();
+    assert_eq!(subscriber.options(), &options);
+    // Let the subscription propagate to the publisher
+    std::thread::sleep(Duration::from_millis(100));
+    publisher.as_sender().send_frame(b"b-skipped").unwrap();
+    publisher.as_sender().send_frame(b"a-received").unwrap();
+    assert_eq!(
+        subscriber.as_receiver().recv_frame().unwrap(),
+        b"a-received"
+    );
+
+    // Socket options survive splitting and joining of the connection
+    let options = ZmqOptions::new().with_max_msg_size(1024);
+    let connection = Connection::connect_with_options(
+        ZmqSocketType::Push,
+        &"inproc://zmq-test-options-push".parse().unwrap(),
+        Some(&"inproc://zmq-test-options-pull".parse().unwrap()),
+        None::<&[u8]>,
+        options.clone(),
+        &ctx,
+    )
+    .unwrap();
+    let (input, output) = Bipolar::split(connection);
+    let connection = Connection::join(input, output);
+    assert_eq!(connection.options(), &options);
+}